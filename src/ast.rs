@@ -1,42 +1,52 @@
 use crate::types::Operator;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Program {
+    pub name: String,
     pub block: Block,
+    #[serde(default)]
+    pub line: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Block {
     pub consts: Vec<ConstDecl>,
     pub vars: Vec<String>,
     pub procedures: Vec<ProcedureDecl>,
     pub statement: Statement,
+    #[serde(default, skip_serializing)]
     pub scope_id: Option<usize>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ConstDecl {
     pub name: String,
     pub value: i64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ProcedureDecl {
     pub name: String,
     pub params: Vec<String>,
     pub block: Block,
+    #[serde(default)]
+    pub line: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Statement {
     Assignment {
         name: String,
         expr: Expr,
+        #[serde(default)]
         line: usize,
     },
     Call {
         name: String,
         args: Vec<Expr>,
+        #[serde(default)]
         line: usize,
     },
     BeginEnd {
@@ -46,25 +56,29 @@ pub enum Statement {
         condition: Condition,
         then_stmt: Box<Statement>,
         else_stmt: Option<Box<Statement>>,
+        #[serde(default)]
         line: usize,
     },
     While {
         condition: Condition,
         body: Box<Statement>,
+        #[serde(default)]
         line: usize,
     },
     Read {
         names: Vec<String>,
+        #[serde(default)]
         line: usize,
     },
     Write {
         exprs: Vec<Expr>,
+        #[serde(default)]
         line: usize,
     },
     Empty,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Condition {
     Odd {
         expr: Expr,
@@ -76,7 +90,7 @@ pub enum Condition {
     },
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Expr {
     Binary {
         left: Box<Expr>,
@@ -90,3 +104,70 @@ pub enum Expr {
     Number(i64),
     Identifier(String),
 }
+
+impl Expr {
+    /// 1 for `+`/`-`, 2 for `*`/`/`, 3 for atoms; decides where `Display` needs parentheses.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary {
+                op: Operator::MUL | Operator::DIV,
+                ..
+            } => 2,
+            Expr::Binary { .. } | Expr::Unary { .. } => 1,
+            Expr::Number(n) if *n < 0 => 1,
+            _ => 3,
+        }
+    }
+}
+
+/// Renders PL/0 syntax with only the parentheses needed to parse back the same tree.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Identifier(name) => write!(f, "{}", name),
+            Expr::Unary { expr, .. } if expr.precedence() < 2 => write!(f, "-({})", expr),
+            Expr::Unary { expr, .. } => write!(f, "-{}", expr),
+            Expr::Binary { left, op, right } => {
+                let prec = self.precedence();
+                let symbol = match op {
+                    Operator::ADD => "+",
+                    Operator::SUB => "-",
+                    Operator::MUL => "*",
+                    _ => "/",
+                };
+                if left.precedence() < prec || (prec == 2 && matches!(**left, Expr::Unary { .. }))
+                {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", symbol)?;
+                if right.precedence() <= prec {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Odd { expr } => write!(f, "odd {}", expr),
+            Condition::Compare { left, op, right } => {
+                let symbol = match op {
+                    Operator::EQL => "=",
+                    Operator::NEQ => "#",
+                    Operator::LSS => "<",
+                    Operator::LEQ => "<=",
+                    Operator::GTR => ">",
+                    _ => ">=",
+                };
+                write!(f, "{} {} {}", left, symbol, right)
+            }
+        }
+    }
+}
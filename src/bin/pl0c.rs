@@ -1,128 +1,648 @@
-use pl0::codegen::CodeGenerator;
-use pl0::lexer::Lexer;
-use pl0::optimizer::optimize_ast;
-use pl0::parser::Parser;
-use pl0::semantic::SemanticAnalyzer;
+use pl0::ast::Program;
+use pl0::cfg;
+use pl0::compiler::{self, CompileError, CompileOptions};
+use pl0::debug_info::DebugInfo;
+use pl0::exit::{
+    EXIT_COMPILE_ERROR, EXIT_IO_ERROR, EXIT_LIMIT, EXIT_OK, EXIT_RUNTIME_ERROR, EXIT_USAGE,
+};
+use pl0::listing;
+use pl0::module::Module;
+use pl0::optimizer::{Pass, dataflow, modref};
+use pl0::sexpr;
 use pl0::symbol_table::SymbolTable;
+use pl0::types::SymbolType;
+use pl0::vm::{Arithmetic, Limits, Overflow, ReaderInput, VM, VMState, WriterOutput};
 use std::env;
-use std::fs::{self, File};
-use std::io::Write;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use std::time::Duration;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let mut verbose = false;
-    let mut use_optimization = false;
-    let mut dump_symbols = false;
-    let mut positional_args = Vec::new();
-
-    for arg in args.iter().skip(1) {
-        if arg == "--verbose" || arg == "-v" {
-            verbose = true;
-        } else if arg == "-o2" {
-            use_optimization = true;
-        } else if arg == "--dump-symbols" {
-            dump_symbols = true;
-        } else {
-            positional_args.push(arg);
-        }
+const USAGE: &str = "\
+Usage: pl0c <command> [options] <source>
+
+Commands:
+  check <source>                  Parse and analyze, report diagnostics only
+  build <source> [-o <path>]      Compile to a binary module (default output: out.pl0b)
+  run <source>                    Compile and execute in the VM, reading input from stdin
+  emit --stage=<stage> <source>   Print an intermediate representation
+                                  stages: tokens, ast, symbols, asm, dot, cfg, dataflow,
+                                  effects
+
+Options:
+  -o, --output <path>   Output file for build/emit (emit defaults to stdout)
+  --format <fmt>        AST format for --stage=ast: json (default), sexpr
+  --source-format <fmt> Read the source as pl0 (default), or as a json/sexpr AST
+  --listing <path>      Also write an annotated source/P-code listing (build, run)
+  -O0, -O1, -O2, -O3    Optimization level (default -O0; -o2 is the same as -O2)
+  --passes <list>       Run exactly these AST passes, comma separated, in this order:
+                        inline, fold, dce, propagate, sr, licm, unroll, cse, dse
+  --disable-pass <list> Leave these passes out of the pipeline
+  --print-after <pass>  Print the AST to stderr after every run of a pass
+  --inline-limit <n>    Inline procedures of at most n AST nodes (default 16, 0 disables)
+  --unroll-budget <n>   Let unrolling add up to n AST nodes per loop (default 64, 0 disables)
+  --overflow <mode>     On integer overflow at runtime: trap (default), wrap or saturate
+                        (run; build optimizes for it and records it in the module)
+  --word-size <bits>    Run on a 16, 32 or 64 (default) bit machine word
+                        (run; build optimizes for it and records it in the module)
+  --stack-limit <n>     Let the VM stack grow to at most n cells (run, default 1048576)
+  --max-steps <n>       Stop the program after n instructions (run)
+  --time-limit <s>      Stop the program after s seconds (run)
+  --max-output <n>      Stop the program once it has written more than n bytes (run)
+  --max-depth <n>       Stop the program when more than n calls are active at once (run)
+  -v, --verbose         Trace tokens and compiler phases
+  -h, --help            Show this help
+
+Exit codes:
+  0 success, 1 compile error, 2 usage error, 3 runtime error, 4 I/O error,
+  5 resource limit exceeded";
+
+#[derive(PartialEq)]
+enum Command {
+    Check,
+    Build,
+    Run,
+    Emit,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum AstFormat {
+    Json,
+    Sexpr,
+}
+
+#[derive(PartialEq)]
+enum Stage {
+    Tokens,
+    Ast,
+    Symbols,
+    Asm,
+    Dot,
+    Cfg,
+    Dataflow,
+    Effects,
+}
+
+struct Args {
+    command: Command,
+    source: String,
+    output: Option<String>,
+    listing: Option<String>,
+    stage: Option<Stage>,
+    format: Option<AstFormat>,
+    /// `None` for PL/0 source text.
+    source_format: Option<AstFormat>,
+    options: CompileOptions,
+    arithmetic: Arithmetic,
+    stack_limit: Option<usize>,
+    limits: Limits,
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("pl0c: {}", msg);
+    eprintln!("Try 'pl0c --help' for more information.");
+    process::exit(EXIT_USAGE);
+}
+
+fn parse_stage(s: &str) -> Stage {
+    match s {
+        "tokens" => Stage::Tokens,
+        "ast" => Stage::Ast,
+        "symbols" => Stage::Symbols,
+        "asm" => Stage::Asm,
+        "dot" => Stage::Dot,
+        "cfg" => Stage::Cfg,
+        "dataflow" => Stage::Dataflow,
+        "effects" => Stage::Effects,
+        _ => usage_error(&format!("unknown stage '{}'", s)),
+    }
+}
+
+fn parse_format(option: &str, s: &str) -> AstFormat {
+    match s {
+        "json" => AstFormat::Json,
+        "sexpr" => AstFormat::Sexpr,
+        _ => usage_error(&format!("unknown {} '{}'", option, s)),
+    }
+}
+
+fn parse_passes(s: &str) -> Vec<Pass> {
+    s.split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            Pass::from_name(name).unwrap_or_else(|| {
+                let known: Vec<&str> = Pass::ALL.iter().map(|p| p.name()).collect();
+                usage_error(&format!(
+                    "unknown pass '{}' (known passes: {})",
+                    name,
+                    known.join(", ")
+                ))
+            })
+        })
+        .collect()
+}
+
+fn parse_size(option: &str, s: &str) -> usize {
+    s.parse()
+        .unwrap_or_else(|_| usage_error(&format!("invalid {} '{}'", option, s)))
+}
+
+fn parse_overflow(s: &str) -> Overflow {
+    Overflow::from_name(s).unwrap_or_else(|| {
+        usage_error(&format!(
+            "unknown overflow mode '{}' (expected trap, wrap or saturate)",
+            s
+        ))
+    })
+}
+
+fn parse_word_size(s: &str) -> u32 {
+    match s.parse() {
+        Ok(bits) if Arithmetic::WORD_SIZES.contains(&bits) => bits,
+        _ => usage_error(&format!("invalid word size '{}' (expected 16, 32 or 64)", s)),
     }
+}
 
-    if positional_args.is_empty() {
-        eprintln!(
-            "Usage: {} <source_file> [output_file] [--verbose] [-o2] [--dump-symbols]",
-            args[0]
-        );
-        std::process::exit(1);
+fn parse_seconds(s: &str) -> Duration {
+    s.parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .unwrap_or_else(|| usage_error(&format!("invalid time limit '{}'", s)))
+}
+
+fn parse_source_format(s: &str) -> Option<AstFormat> {
+    match s {
+        "pl0" => None,
+        _ => Some(parse_format("source format", s)),
     }
+}
 
-    let source_path = &positional_args[0];
-    let output_path = if positional_args.len() >= 2 {
-        &positional_args[1]
-    } else {
-        "out.asm"
+fn parse_args(args: &[String]) -> Args {
+    if args.iter().any(|a| a == "-h" || a == "--help") || args.is_empty() {
+        println!("{}", USAGE);
+        process::exit(if args.is_empty() { EXIT_USAGE } else { EXIT_OK });
+    }
+
+    let command = match args[0].as_str() {
+        "check" => Command::Check,
+        "build" => Command::Build,
+        "run" => Command::Run,
+        "emit" => Command::Emit,
+        other => usage_error(&format!("unknown command '{}'", other)),
     };
 
-    let source_code = fs::read_to_string(source_path).expect("Failed to read source file");
+    let mut options = CompileOptions::default();
+    let mut passes = None;
+    let mut disabled = Vec::new();
+    let mut output = None;
+    let mut listing = None;
+    let mut stage = None;
+    let mut format = None;
+    let mut source_format = None;
+    let mut arithmetic = None;
+    let mut stack_limit = None;
+    let mut limits = Limits::default();
+    let mut positional = Vec::new();
 
-    let lexer = Lexer::new(&source_code);
-    let mut parser = Parser::new(lexer, verbose);
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-v" | "--verbose" => options.verbose = true,
+            "-O0" => options.optimize = false,
+            "-O1" | "-O2" | "-o2" | "-O3" => {
+                options.optimize = true;
+                options.optimizer.passes = Pass::level(arg.as_bytes()[2] - b'0');
+            }
+            "--passes" => match iter.next() {
+                Some(s) => passes = Some(parse_passes(s)),
+                None => usage_error("--passes requires a list of passes"),
+            },
+            "--disable-pass" => match iter.next() {
+                Some(s) => disabled.extend(parse_passes(s)),
+                None => usage_error("--disable-pass requires a list of passes"),
+            },
+            "--print-after" => match iter.next() {
+                Some(s) => options.optimizer.print_after.extend(parse_passes(s)),
+                None => usage_error("--print-after requires a pass"),
+            },
+            "-o" | "--output" => match iter.next() {
+                Some(path) => output = Some(path.clone()),
+                None => usage_error(&format!("{} requires a path", arg)),
+            },
+            "--inline-limit" => match iter.next() {
+                Some(s) => options.optimizer.inline_limit = parse_size("inline limit", s),
+                None => usage_error("--inline-limit requires a value"),
+            },
+            "--unroll-budget" => match iter.next() {
+                Some(s) => options.optimizer.unroll_budget = parse_size("unroll budget", s),
+                None => usage_error("--unroll-budget requires a value"),
+            },
+            "--overflow" => match iter.next() {
+                Some(s) => {
+                    arithmetic.get_or_insert_with(Arithmetic::default).overflow = parse_overflow(s)
+                }
+                None => usage_error("--overflow requires a mode"),
+            },
+            "--word-size" => match iter.next() {
+                Some(s) => {
+                    arithmetic.get_or_insert_with(Arithmetic::default).word_bits =
+                        parse_word_size(s)
+                }
+                None => usage_error("--word-size requires a number of bits"),
+            },
+            "--stack-limit" => match iter.next() {
+                Some(s) => stack_limit = Some(parse_size("stack limit", s)),
+                None => usage_error("--stack-limit requires a number of cells"),
+            },
+            "--max-steps" => match iter.next() {
+                Some(s) => limits.steps = Some(parse_size("step limit", s)),
+                None => usage_error("--max-steps requires a number of instructions"),
+            },
+            "--time-limit" => match iter.next() {
+                Some(s) => limits.time = Some(parse_seconds(s)),
+                None => usage_error("--time-limit requires a number of seconds"),
+            },
+            "--max-output" => match iter.next() {
+                Some(s) => limits.output_bytes = Some(parse_size("output limit", s)),
+                None => usage_error("--max-output requires a number of bytes"),
+            },
+            "--max-depth" => match iter.next() {
+                Some(s) => limits.depth = Some(parse_size("depth limit", s)),
+                None => usage_error("--max-depth requires a number of calls"),
+            },
+            "--listing" => match iter.next() {
+                Some(path) => listing = Some(path.clone()),
+                None => usage_error("--listing requires a path"),
+            },
+            "--stage" => match iter.next() {
+                Some(s) => stage = Some(parse_stage(s)),
+                None => usage_error("--stage requires a value"),
+            },
+            "--format" => match iter.next() {
+                Some(s) => format = Some(parse_format("format", s)),
+                None => usage_error("--format requires a value"),
+            },
+            "--source-format" => match iter.next() {
+                Some(s) => source_format = parse_source_format(s),
+                None => usage_error("--source-format requires a value"),
+            },
+            _ => {
+                if let Some(s) = arg.strip_prefix("--stage=") {
+                    stage = Some(parse_stage(s));
+                } else if let Some(s) = arg.strip_prefix("--format=") {
+                    format = Some(parse_format("format", s));
+                } else if let Some(s) = arg.strip_prefix("--source-format=") {
+                    source_format = parse_source_format(s);
+                } else if let Some(s) = arg.strip_prefix("--passes=") {
+                    passes = Some(parse_passes(s));
+                } else if let Some(s) = arg.strip_prefix("--disable-pass=") {
+                    disabled.extend(parse_passes(s));
+                } else if let Some(s) = arg.strip_prefix("--print-after=") {
+                    options.optimizer.print_after.extend(parse_passes(s));
+                } else if let Some(s) = arg.strip_prefix("--inline-limit=") {
+                    options.optimizer.inline_limit = parse_size("inline limit", s);
+                } else if let Some(s) = arg.strip_prefix("--unroll-budget=") {
+                    options.optimizer.unroll_budget = parse_size("unroll budget", s);
+                } else if let Some(s) = arg.strip_prefix("--overflow=") {
+                    arithmetic.get_or_insert_with(Arithmetic::default).overflow = parse_overflow(s);
+                } else if let Some(s) = arg.strip_prefix("--word-size=") {
+                    arithmetic.get_or_insert_with(Arithmetic::default).word_bits =
+                        parse_word_size(s);
+                } else if let Some(s) = arg.strip_prefix("--stack-limit=") {
+                    stack_limit = Some(parse_size("stack limit", s));
+                } else if let Some(s) = arg.strip_prefix("--max-steps=") {
+                    limits.steps = Some(parse_size("step limit", s));
+                } else if let Some(s) = arg.strip_prefix("--time-limit=") {
+                    limits.time = Some(parse_seconds(s));
+                } else if let Some(s) = arg.strip_prefix("--max-output=") {
+                    limits.output_bytes = Some(parse_size("output limit", s));
+                } else if let Some(s) = arg.strip_prefix("--max-depth=") {
+                    limits.depth = Some(parse_size("depth limit", s));
+                } else if let Some(path) = arg.strip_prefix("--output=") {
+                    output = Some(path.to_string());
+                } else if let Some(path) = arg.strip_prefix("--listing=") {
+                    listing = Some(path.to_string());
+                } else if arg.starts_with('-') {
+                    usage_error(&format!("unknown option '{}'", arg));
+                } else {
+                    positional.push(arg.clone());
+                }
+            }
+        }
+    }
 
-    println!("Compiling {}...", source_path);
+    if positional.len() != 1 {
+        usage_error("expected exactly one source file");
+    }
+    // An explicit pass list implies optimizing, whatever level came before it
+    if let Some(passes) = passes {
+        options.optimize = true;
+        options.optimizer.passes = passes;
+    }
+    options.optimizer.passes.retain(|p| !disabled.contains(p));
+    if let Some(pass) = options
+        .optimizer
+        .print_after
+        .iter()
+        .find(|p| !options.optimize || !options.optimizer.passes.contains(p))
+    {
+        usage_error(&format!("--print-after={}: pass is not in the pipeline", pass));
+    }
+    if command == Command::Emit && stage.is_none() {
+        usage_error("emit requires --stage=<tokens|ast|symbols|asm|dot|cfg|dataflow|effects>");
+    }
+    if command != Command::Emit && stage.is_some() {
+        usage_error("--stage is only valid with emit");
+    }
+    if matches!(command, Command::Check | Command::Run) && output.is_some() {
+        usage_error("-o is only valid with build and emit");
+    }
+    if matches!(command, Command::Check | Command::Emit) && listing.is_some() {
+        usage_error("--listing is only valid with build and run");
+    }
+    let vm_options = stack_limit.is_some() || limits != Limits::default();
+    if command != Command::Run && vm_options {
+        usage_error("VM options such as --stack-limit and --max-steps are only valid with run");
+    }
+    if !matches!(command, Command::Build | Command::Run) && arithmetic.is_some() {
+        usage_error("--overflow and --word-size are only valid with build and run");
+    }
+    // The optimizer must not fold what the VM would overflow on
+    let arithmetic = arithmetic.unwrap_or_default();
+    options.optimizer.arithmetic = arithmetic;
+    if format.is_some() && stage != Some(Stage::Ast) {
+        usage_error("--format is only valid with --stage=ast");
+    }
+    if source_format.is_some() && stage == Some(Stage::Tokens) {
+        usage_error("--stage=tokens needs PL/0 source text");
+    }
 
-    // Parse to AST
-    let parse_result = parser.parse();
+    Args {
+        command,
+        source: positional.remove(0),
+        output,
+        listing,
+        stage,
+        format,
+        source_format,
+        options,
+        arithmetic,
+        stack_limit,
+        limits,
+    }
+}
 
-    if !parser.errors.is_empty() {
-        eprintln!("Parsing encountered errors.");
-        let lines: Vec<&str> = source_code.lines().collect();
-        for err in &parser.errors {
-            eprintln!(
-                "{}:{}:{}: error: {}",
-                source_path, err.line, err.col, err.message
-            );
-            if err.line > 0 && err.line <= lines.len() {
-                let line_content = lines[err.line - 1];
-                eprintln!("    {}", line_content);
-                let indent: String = line_content
-                    .chars()
-                    .take(err.col - 1)
-                    .map(|c| if c.is_whitespace() { c } else { ' ' })
-                    .collect();
-                eprintln!("    {}^", indent);
+fn report(source: &str, path: &str, err: &CompileError) {
+    match err {
+        CompileError::Parse(errors) => {
+            for e in errors {
+                eprintln!("{}", compiler::format_parse_error(source, path, e));
+            }
+            eprintln!("Compilation failed due to parsing errors.");
+        }
+        CompileError::Semantic(errors) => {
+            for e in errors {
+                eprintln!("{}: error: {}", path, e);
             }
+            eprintln!("Semantic analysis failed.");
         }
-        eprintln!("Compilation failed due to parsing errors.");
-        std::process::exit(1);
     }
+}
 
-    if parse_result.is_err() {
-        eprintln!("Fatal parsing error.");
-        std::process::exit(1);
+/// Reads a serialized AST; the program still goes through semantic analysis afterwards.
+fn load_ast(text: &str, path: &str, format: AstFormat) -> Program {
+    let result = match format {
+        AstFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+        AstFormat::Sexpr => sexpr::from_str(text),
+    };
+    match result {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}: error: invalid AST: {}", path, e);
+            process::exit(EXIT_COMPILE_ERROR);
+        }
     }
+}
+
+fn write_file(path: &str, content: &[u8]) {
+    if let Err(e) = fs::write(path, content) {
+        eprintln!("pl0c: failed to write {}: {}", path, e);
+        process::exit(EXIT_IO_ERROR);
+    }
+}
 
-    let mut program = parse_result.unwrap();
+fn write_output(path: Option<&str>, content: &str) {
+    match path {
+        Some(p) => write_file(p, content.as_bytes()),
+        None => {
+            print!("{}", content);
+        }
+    }
+}
 
-    if use_optimization {
-        println!("Optimizing AST...");
-        optimize_ast(&mut program);
+fn format_symbols(symbol_table: &SymbolTable) -> String {
+    let mut out = String::new();
+    for (id, scope) in symbol_table.scopes.iter().enumerate() {
+        match scope.parent {
+            Some(parent) => out.push_str(&format!("scope {} (parent {})\n", id, parent)),
+            None => out.push_str(&format!("scope {} (global)\n", id)),
+        }
+        let mut symbols: Vec<_> = scope.symbols.values().collect();
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+        for sym in symbols {
+            let desc = match &sym.kind {
+                SymbolType::Constant { val } => format!("const {} = {}", sym.name, val),
+                SymbolType::Variable { level, addr } => {
+                    format!("var   {} level={} addr={}", sym.name, level, addr)
+                }
+                SymbolType::Procedure { level, addr } => {
+                    format!("proc  {} level={} addr={}", sym.name, level, addr)
+                }
+            };
+            out.push_str(&format!("  {}\n", desc));
+        }
     }
+    out
+}
 
-    println!("Performing Semantic Analysis...");
-    let mut symbol_table = SymbolTable::new();
-    let mut analyzer = SemanticAnalyzer::new(&mut symbol_table);
+fn run(code: Vec<pl0::types::Instruction>, debug_info: DebugInfo, args: &Args) -> i32 {
+    let mut vm = VM::new(code)
+        .with_debug_info(Some(debug_info))
+        .with_arithmetic(args.arithmetic)
+        .with_limits(args.limits);
+    if let Some(limit) = args.stack_limit {
+        vm = vm.with_stack_limit(limit);
+    }
+    let mut vm = vm.with_io(
+        ReaderInput::new(io::stdin().lock()),
+        WriterOutput(io::stdout()),
+    );
 
-    if let Err(errors) = analyzer.analyze(&mut program) {
-        eprintln!("Semantic analysis failed:");
-        for err in errors {
-            eprintln!("  {}", err);
+    while vm.state == VMState::Running {
+        vm.step();
+    }
+    match vm.state {
+        VMState::Halted => EXIT_OK,
+        VMState::Error(ref e) => {
+            io::stdout().flush().ok();
+            eprintln!("Runtime Error: {}", e);
+            EXIT_RUNTIME_ERROR
         }
-        std::process::exit(1);
+        VMState::LimitExceeded(limit) => {
+            io::stdout().flush().ok();
+            eprintln!("Stopped: {} at pc {}", limit, vm.p);
+            EXIT_LIMIT
+        }
+        _ => unreachable!("stdin never leaves the VM waiting for input"),
     }
+}
+
+fn main() {
+    let raw_args: Vec<String> = env::args().skip(1).collect();
+    let args = parse_args(&raw_args);
 
-    if dump_symbols {
-        let dot_output = symbol_table.to_dot();
-        let dot_file = "symbols.dot";
-        let mut f = File::create(dot_file).expect("Failed to create dot file");
-        f.write_all(dot_output.as_bytes()).expect("Failed to write dot file");
-        println!("Dumped symbol table to {}", dot_file);
+    let source = match fs::read_to_string(&args.source) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("pl0c: failed to read {}: {}", args.source, e);
+            process::exit(EXIT_IO_ERROR);
+        }
+    };
+
+    if args.options.verbose {
+        eprintln!("Compiling {}...", args.source);
     }
 
-    println!("Generating Code...");
-    let mut generator = CodeGenerator::new();
-    let code = generator.generate(&program, &mut symbol_table);
+    if args.stage == Some(Stage::Tokens) {
+        let mut out = String::new();
+        for (line, col, token) in compiler::tokenize(&source) {
+            out.push_str(&format!("{}:{}\t{:?}\n", line, col, token));
+        }
+        write_output(args.output.as_deref(), &out);
+        process::exit(EXIT_OK);
+    }
 
-    println!(
-        "Compilation successful! Generated {} instructions.",
-        code.len()
-    );
+    let program = match args.source_format {
+        Some(format) => load_ast(&source, &args.source, format),
+        None => match compiler::parse(&source, args.options.verbose) {
+            Ok(program) => program,
+            Err(e) => {
+                report(&source, &args.source, &e);
+                process::exit(EXIT_COMPILE_ERROR);
+            }
+        },
+    };
 
-    let final_code = code;
+    let warnings = compiler::warnings(&program);
+    let report_warnings = || {
+        for w in &warnings {
+            eprintln!("{}: warning: {}", args.source, w);
+        }
+    };
 
-    let mut file = File::create(output_path).expect("Failed to create output file");
-    for instr in &final_code {
-        writeln!(file, "{:?} {} {}", instr.f, instr.l, instr.a)
-            .expect("Failed to write instruction");
+    if args.command == Command::Check {
+        match compiler::check_program(program) {
+            Ok(_) => {
+                report_warnings();
+                eprintln!("{}: no errors", args.source);
+                process::exit(EXIT_OK);
+            }
+            Err(e) => {
+                report(&source, &args.source, &e);
+                process::exit(EXIT_COMPILE_ERROR);
+            }
+        }
     }
 
-    println!("Wrote assembly to {}", output_path);
+    let compilation = match compiler::compile_program(program, &args.options) {
+        Ok(c) => {
+            report_warnings();
+            for (heading, ast) in &c.stats.dumps {
+                eprintln!("; {}\n{}", heading, ast);
+            }
+            if args.options.verbose && args.options.optimize {
+                eprintln!(
+                    "Optimizer inlined {} calls, unrolled {} loops, removed {} dead stores and {} variable slots",
+                    c.stats.inlined_calls,
+                    c.stats.unrolled_loops,
+                    c.stats.dead_stores,
+                    c.stats.removed_slots
+                );
+                eprintln!("{:<10} {:>5} {:>8} {:>11} {:>12}", "pass", "runs", "changes", "stmts-gone", "exprs-folded");
+                for p in &c.stats.passes {
+                    eprintln!(
+                        "{:<10} {:>5} {:>8} {:>11} {:>12}",
+                        p.pass.name(),
+                        p.runs,
+                        p.changes,
+                        p.statements_removed,
+                        p.expressions_folded
+                    );
+                }
+            }
+            c
+        }
+        Err(e) => {
+            report(&source, &args.source, &e);
+            process::exit(EXIT_COMPILE_ERROR);
+        }
+    };
+
+    if let Some(path) = &args.listing {
+        // Line numbers of a deserialized AST refer to some other file, so show code only
+        let listed_source = if args.source_format.is_some() {
+            ""
+        } else {
+            &source
+        };
+        let text = listing::render(listed_source, &compilation.code, &compilation.debug_info);
+        write_file(path, text.as_bytes());
+    }
+
+    let code = match args.command {
+        Command::Build => {
+            let output = args.output.as_deref().unwrap_or("out.pl0b");
+            let mut module = Module::new(compilation.code.clone());
+            module.debug = Some(DebugInfo {
+                source_file: Some(args.source.clone()),
+                ..compilation.debug_info.clone()
+            });
+            module.arithmetic = Some(args.arithmetic);
+            write_file(output, &module.to_bytes());
+            eprintln!(
+                "Wrote {} instructions to {}",
+                compilation.code.len(),
+                output
+            );
+            EXIT_OK
+        }
+        Command::Run => run(compilation.code, compilation.debug_info, &args),
+        Command::Emit => {
+            let content = match args.stage {
+                Some(Stage::Ast) => match args.format.unwrap_or(AstFormat::Json) {
+                    AstFormat::Json => {
+                        let mut json = serde_json::to_string_pretty(&compilation.program)
+                            .expect("AST serialization cannot fail");
+                        json.push('\n');
+                        json
+                    }
+                    AstFormat::Sexpr => sexpr::to_string(&compilation.program),
+                },
+                Some(Stage::Symbols) => format_symbols(&compilation.symbol_table),
+                Some(Stage::Asm) => compiler::format_asm(&compilation.code),
+                Some(Stage::Dot) => compilation.symbol_table.to_dot(),
+                Some(Stage::Cfg) => cfg::to_dot(&cfg::build_program(&compilation.program)),
+                Some(Stage::Dataflow) => dataflow::dump_program(&compilation.program),
+                Some(Stage::Effects) => {
+                    let cfgs = cfg::build_program(&compilation.program);
+                    modref::analyze(&cfgs).dump(&cfgs)
+                }
+                _ => unreachable!("stage validated in parse_args"),
+            };
+            write_output(args.output.as_deref(), &content);
+            EXIT_OK
+        }
+        Command::Check => unreachable!("check handled above"),
+    };
+    process::exit(code);
 }
@@ -0,0 +1,135 @@
+use pl0::compiler::{self, CompileError};
+use pl0::formatter::{self, FormatOptions};
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process;
+
+const EXIT_OK: i32 = 0;
+const EXIT_PARSE_ERROR: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_UNFORMATTED: i32 = 3;
+const EXIT_IO_ERROR: i32 = 4;
+
+const USAGE: &str = "\
+Usage: pl0fmt [options] [files...]
+
+Formats PL/0 sources in place. With no files, reads stdin and writes stdout.
+
+Options:
+  --check             Don't write anything; list files that would change
+  --one-per-line      Put each const/var declaration on its own line
+  --uppercase         Write keywords in upper case
+  --indent <n>        Spaces per indentation level (default 2)
+  -h, --help          Show this help
+
+Exit codes:
+  0 success, 1 parse error, 2 usage error, 3 files need formatting (--check), 4 I/O error";
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("pl0fmt: {}", msg);
+    eprintln!("Try 'pl0fmt --help' for more information.");
+    process::exit(EXIT_USAGE);
+}
+
+fn parse_indent(s: &str) -> usize {
+    match s.parse() {
+        Ok(n) if n <= 16 => n,
+        _ => usage_error(&format!("invalid indent '{}'", s)),
+    }
+}
+
+fn report(source: &str, path: &str, err: &CompileError) {
+    if let CompileError::Parse(errors) = err {
+        for e in errors {
+            eprintln!("{}", compiler::format_parse_error(source, path, e));
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut options = FormatOptions::default();
+    let mut check = false;
+    let mut files = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(EXIT_OK);
+            }
+            "--check" => check = true,
+            "--one-per-line" => options.packed_decls = false,
+            "--uppercase" => options.uppercase_keywords = true,
+            "--indent" => match iter.next() {
+                Some(n) => options.indent_width = parse_indent(n),
+                None => usage_error("--indent requires a value"),
+            },
+            _ => {
+                if let Some(n) = arg.strip_prefix("--indent=") {
+                    options.indent_width = parse_indent(n);
+                } else if arg.starts_with('-') {
+                    usage_error(&format!("unknown option '{}'", arg));
+                } else {
+                    files.push(arg.clone());
+                }
+            }
+        }
+    }
+
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut source) {
+            eprintln!("pl0fmt: failed to read stdin: {}", e);
+            process::exit(EXIT_IO_ERROR);
+        }
+        match formatter::format_source(&source, &options) {
+            Ok(formatted) if check => {
+                if formatted != source {
+                    eprintln!("<stdin> is not formatted");
+                    process::exit(EXIT_UNFORMATTED);
+                }
+            }
+            Ok(formatted) => print!("{}", formatted),
+            Err(e) => {
+                report(&source, "<stdin>", &e);
+                process::exit(EXIT_PARSE_ERROR);
+            }
+        }
+        process::exit(EXIT_OK);
+    }
+
+    let mut status = EXIT_OK;
+    for path in &files {
+        let source = match fs::read_to_string(path) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("pl0fmt: failed to read {}: {}", path, e);
+                process::exit(EXIT_IO_ERROR);
+            }
+        };
+        let formatted = match formatter::format_source(&source, &options) {
+            Ok(f) => f,
+            Err(e) => {
+                report(&source, path, &e);
+                status = EXIT_PARSE_ERROR;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", path);
+            if status == EXIT_OK {
+                status = EXIT_UNFORMATTED;
+            }
+        } else if let Err(e) = fs::write(path, formatted) {
+            eprintln!("pl0fmt: failed to write {}: {}", path, e);
+            process::exit(EXIT_IO_ERROR);
+        }
+    }
+    process::exit(status);
+}
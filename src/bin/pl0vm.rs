@@ -1,53 +1,423 @@
-use pl0::types::{Instruction, OpCode};
-use pl0::vm::VM;
+use pl0::debugger::{Debugger, Stop};
+use pl0::exit::{EXIT_COMPILE_ERROR, EXIT_IO_ERROR, EXIT_LIMIT, EXIT_RUNTIME_ERROR, EXIT_USAGE};
+use pl0::module::{self, ModuleError};
+use pl0::verifier;
+use pl0::vm::{
+    Arithmetic, DEFAULT_STACK_LIMIT, Limits, Overflow, ReaderInput, VM, VMState, WriterOutput,
+};
+use std::collections::VecDeque;
 use std::env;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Stdout, Write};
+use std::time::Duration;
 
-fn parse_opcode(s: &str) -> OpCode {
-    match s {
-        "LIT" => OpCode::LIT,
-        "OPR" => OpCode::OPR,
-        "LOD" => OpCode::LOD,
-        "STO" => OpCode::STO,
-        "CAL" => OpCode::CAL,
-        "INT" => OpCode::INT,
-        "JMP" => OpCode::JMP,
-        "JPC" => OpCode::JPC,
-        "RED" => OpCode::RED,
-        "WRT" => OpCode::WRT,
-        _ => panic!("Unknown opcode: {}", s),
+const USAGE: &str = "\
+Usage: pl0vm [options] <module_file>
+
+Options:
+  --overflow <mode>   On integer overflow: trap (default), wrap or saturate
+  --word-size <bits>  Emulate a 16, 32 or 64 (default) bit machine word
+                      (a module built by pl0c runs with the arithmetic it was
+                      built for; these may only repeat it)
+  --stack-limit <n>   Let the stack grow to at most n cells (default 1048576)
+  --input <source>    Run without prompting, reading input from a file, or from
+                      the option itself if it is a list of integers (\"3 4\");
+                      running out of input is a runtime error
+  --max-steps <n>     Stop after n instructions
+  --time-limit <s>    Stop after s seconds of wall-clock time
+  --max-output <n>    Stop once more than n bytes have been written
+  --max-depth <n>     Stop when more than n procedure calls are active at once
+  --no-verify         Run the module without checking its code first
+  --debug             Run under an interactive debugger (type help for its commands);
+                      --input is read up front and the debugger asks for more
+
+Exit codes:
+  0 program finished, 1 invalid module, 2 usage error, 3 runtime error, 4 I/O error,
+  5 resource limit exceeded";
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("pl0vm: {}", msg);
+    eprintln!("{}", USAGE);
+    std::process::exit(EXIT_USAGE);
+}
+
+fn parse_overflow(s: &str) -> Overflow {
+    Overflow::from_name(s).unwrap_or_else(|| {
+        usage_error(&format!(
+            "unknown overflow mode '{}' (expected trap, wrap or saturate)",
+            s
+        ))
+    })
+}
+
+fn parse_word_size(s: &str) -> u32 {
+    match s.parse() {
+        Ok(bits) if Arithmetic::WORD_SIZES.contains(&bits) => bits,
+        _ => usage_error(&format!("invalid word size '{}' (expected 16, 32 or 64)", s)),
     }
 }
 
+fn parse_size(option: &str, s: &str) -> usize {
+    s.parse()
+        .unwrap_or_else(|_| usage_error(&format!("invalid {} '{}'", option, s)))
+}
+
+fn parse_seconds(s: &str) -> Duration {
+    s.parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .unwrap_or_else(|| usage_error(&format!("invalid time limit '{}'", s)))
+}
+
+const DEBUG_HELP: &str = "\
+Commands:
+  break <where>   Stop at a source line, a procedure (by name) or an address (*N);
+                  with no argument, list the breakpoints
+  delete <where>  Remove a breakpoint
+  step            Run to the next statement, entering procedure calls
+  next            Run to the next statement, stepping over procedure calls
+  finish          Run until the current procedure returns
+  continue        Run until a breakpoint or watch stops the program
+  stepi           Run one instruction
+  print <var>     Show a variable visible from the current procedure
+  watch <var>     Stop whenever a variable changes
+  stack           Show the stack up to its top
+  frames          Show the active procedure calls
+  quit            Leave the debugger
+An empty line repeats the last command.";
+
+/// Batch input: the integers in `source` itself, or else the contents of the file it names.
+fn open_input(source: &str) -> Box<dyn BufRead> {
+    if source.split_whitespace().all(|s| s.parse::<i64>().is_ok()) {
+        return Box::new(Cursor::new(source.to_string()));
+    }
+    match File::open(source) {
+        Ok(file) => Box::new(BufReader::new(file)),
+        Err(e) => {
+            eprintln!("Failed to open input {}: {}", source, e);
+            std::process::exit(EXIT_IO_ERROR);
+        }
+    }
+}
+
+/// Every integer in `source`, for the debugger's input queue.
+fn read_all_input(source: &str) -> VecDeque<i64> {
+    let mut text = String::new();
+    if let Err(e) = open_input(source).read_to_string(&mut text) {
+        eprintln!("Failed to read input {}: {}", source, e);
+        std::process::exit(EXIT_IO_ERROR);
+    }
+    text.split_whitespace()
+        .map(|s| {
+            s.parse().unwrap_or_else(|_| {
+                // As when the VM reads it without the debugger
+                eprintln!("Invalid input '{}' in {}", s, source);
+                std::process::exit(EXIT_RUNTIME_ERROR);
+            })
+        })
+        .collect()
+}
+
+fn show_location(dbg: &Debugger<VecDeque<i64>, WriterOutput<Stdout>>) {
+    match dbg.vm.code.get(dbg.vm.p) {
+        Some(ir) => println!("{}: {:?} {} {}", dbg.location(), ir.f, ir.l, ir.a),
+        None => println!("{}", dbg.location()),
+    }
+}
+
+/// Runs `vm` under a command-line debugger reading commands from stdin.
+fn debug(vm: VM<VecDeque<i64>, WriterOutput<Stdout>>) -> VMState {
+    let mut dbg = Debugger::new(vm);
+    let mut lines = io::stdin().lock().lines();
+    let mut last = String::new();
+    println!("Type help for a list of commands.");
+    show_location(&dbg);
+    loop {
+        print!("(pl0db) ");
+        io::stdout().flush().ok();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => {
+                println!();
+                break;
+            }
+        };
+        let line = if line.trim().is_empty() { last.clone() } else { line };
+        last = line.clone();
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            continue;
+        };
+        let arg = words.next();
+        let run: fn(&mut Debugger<_, _>) -> Stop = match (command, arg) {
+            ("step" | "s", None) => Debugger::step,
+            ("next" | "n", None) => Debugger::step_over,
+            ("finish", None) => Debugger::finish,
+            ("continue" | "c", None) => Debugger::cont,
+            ("stepi" | "si", None) => Debugger::stepi,
+            ("break" | "b", None) => {
+                for &addr in dbg.breakpoints() {
+                    println!("Breakpoint at pc {}", addr);
+                }
+                continue;
+            }
+            ("break" | "b", Some(spec)) => {
+                match dbg.resolve(spec) {
+                    Ok(addr) if dbg.add_breakpoint(addr) => println!("Breakpoint at pc {}", addr),
+                    Ok(addr) => println!("There is already a breakpoint at pc {}", addr),
+                    Err(e) => println!("{}", e),
+                }
+                continue;
+            }
+            ("delete" | "d", Some(spec)) => {
+                match dbg.resolve(spec) {
+                    Ok(addr) if dbg.remove_breakpoint(addr) => println!("Deleted pc {}", addr),
+                    Ok(addr) => println!("No breakpoint at pc {}", addr),
+                    Err(e) => println!("{}", e),
+                }
+                continue;
+            }
+            ("print" | "p", Some(name)) => {
+                match dbg.print(name) {
+                    Ok(value) => println!("{} = {}", name, value),
+                    Err(e) => println!("{}", e),
+                }
+                continue;
+            }
+            ("watch" | "w", Some(name)) => {
+                match dbg.watch(name) {
+                    Ok(value) => println!("Watching {} (currently {})", name, value),
+                    Err(e) => println!("{}", e),
+                }
+                continue;
+            }
+            ("stack", None) => {
+                for (addr, value, label) in dbg.stack_labels().into_iter().rev() {
+                    println!("{:>6}: {:>12}  {}", addr, value, label);
+                }
+                continue;
+            }
+            ("frames" | "bt", None) => {
+                for (i, frame) in dbg.frames().iter().enumerate() {
+                    println!(
+                        "#{} {} at pc {} (base {}, static link {}, dynamic link {}, return {})",
+                        i,
+                        frame.procedure.as_deref().unwrap_or("?"),
+                        frame.pc,
+                        frame.base,
+                        frame.static_link,
+                        frame.dynamic_link,
+                        frame.return_addr
+                    );
+                }
+                continue;
+            }
+            ("help" | "h", None) => {
+                println!("{}", DEBUG_HELP);
+                continue;
+            }
+            ("quit" | "q", None) => break,
+            _ => {
+                println!("Unknown command '{}' (type help for a list)", line.trim());
+                continue;
+            }
+        };
+        if dbg.vm.state != VMState::Running {
+            println!("The program is not running");
+            continue;
+        }
+        let mut stop = run(&mut dbg);
+        // Input runs out into a prompt, after which the command carries on
+        while stop == Stop::Ended && dbg.vm.state == VMState::WaitingForInput {
+            print!("Input: ");
+            io::stdout().flush().ok();
+            match lines.next() {
+                Some(Ok(line)) => match line.trim().parse() {
+                    Ok(value) => {
+                        dbg.vm.input.push_back(value);
+                        dbg.vm.state = VMState::Running;
+                        stop = run(&mut dbg);
+                    }
+                    Err(_) => println!("Invalid input '{}'", line.trim()),
+                },
+                _ => return dbg.vm.state,
+            }
+        }
+        match stop {
+            Stop::Step => {}
+            Stop::Breakpoint(_) => print!("Breakpoint, "),
+            Stop::Watch { name, old, new } => println!("{} changed from {} to {}", name, old, new),
+            Stop::WatchOutOfScope(name) => {
+                println!("Stopped watching {}: its frame returned", name)
+            }
+            Stop::Ended => {
+                match &dbg.vm.state {
+                    VMState::Halted => println!("Program finished"),
+                    VMState::Error(e) => println!("Runtime Error: {}", e),
+                    VMState::LimitExceeded(limit) => {
+                        println!("Stopped: {} at pc {}", limit, dbg.vm.p)
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+        }
+        show_location(&dbg);
+    }
+    dbg.vm.state
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    if args.len() < 2 {
-        eprintln!("Usage: {} <asm_file>", args[0]);
-        std::process::exit(1);
+    let mut overflow = None;
+    let mut word_bits = None;
+    let mut stack_limit = DEFAULT_STACK_LIMIT;
+    let mut input = None;
+    let mut limits = Limits::default();
+    let mut verify = true;
+    let mut debugging = false;
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "--no-verify" => verify = false,
+            "--debug" => debugging = true,
+            "--overflow" => match iter.next() {
+                Some(s) => overflow = Some(parse_overflow(s)),
+                None => usage_error("--overflow requires a mode"),
+            },
+            "--word-size" => match iter.next() {
+                Some(s) => word_bits = Some(parse_word_size(s)),
+                None => usage_error("--word-size requires a number of bits"),
+            },
+            "--stack-limit" => match iter.next() {
+                Some(s) => stack_limit = parse_size("stack limit", s),
+                None => usage_error("--stack-limit requires a number of cells"),
+            },
+            "--input" => match iter.next() {
+                Some(s) => input = Some(s.as_str()),
+                None => usage_error("--input requires a file or a list of integers"),
+            },
+            "--max-steps" => match iter.next() {
+                Some(s) => limits.steps = Some(parse_size("step limit", s)),
+                None => usage_error("--max-steps requires a number of instructions"),
+            },
+            "--time-limit" => match iter.next() {
+                Some(s) => limits.time = Some(parse_seconds(s)),
+                None => usage_error("--time-limit requires a number of seconds"),
+            },
+            "--max-output" => match iter.next() {
+                Some(s) => limits.output_bytes = Some(parse_size("output limit", s)),
+                None => usage_error("--max-output requires a number of bytes"),
+            },
+            "--max-depth" => match iter.next() {
+                Some(s) => limits.depth = Some(parse_size("depth limit", s)),
+                None => usage_error("--max-depth requires a number of calls"),
+            },
+            _ => {
+                if let Some(s) = arg.strip_prefix("--overflow=") {
+                    overflow = Some(parse_overflow(s));
+                } else if let Some(s) = arg.strip_prefix("--word-size=") {
+                    word_bits = Some(parse_word_size(s));
+                } else if let Some(s) = arg.strip_prefix("--stack-limit=") {
+                    stack_limit = parse_size("stack limit", s);
+                } else if let Some(s) = arg.strip_prefix("--input=") {
+                    input = Some(s);
+                } else if let Some(s) = arg.strip_prefix("--max-steps=") {
+                    limits.steps = Some(parse_size("step limit", s));
+                } else if let Some(s) = arg.strip_prefix("--time-limit=") {
+                    limits.time = Some(parse_seconds(s));
+                } else if let Some(s) = arg.strip_prefix("--max-output=") {
+                    limits.output_bytes = Some(parse_size("output limit", s));
+                } else if let Some(s) = arg.strip_prefix("--max-depth=") {
+                    limits.depth = Some(parse_size("depth limit", s));
+                } else if arg.starts_with('-') {
+                    usage_error(&format!("unknown option '{}'", arg));
+                } else {
+                    positional.push(arg);
+                }
+            }
+        }
     }
 
-    let path = &args[1];
+    if positional.len() != 1 {
+        usage_error("expected exactly one module file");
+    }
+    let path = positional[0];
 
-    let file = File::open(path).expect("Failed to open asm file");
-    let reader = BufReader::new(file);
-    let mut instructions = Vec::new();
+    let module = match module::load_file(path) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", path, e);
+            std::process::exit(match e {
+                ModuleError::Io(_) => EXIT_IO_ERROR,
+                _ => EXIT_COMPILE_ERROR,
+            });
+        }
+    };
 
-    for line in reader.lines() {
-        let line = line.expect("Failed to read line");
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() >= 3 {
-            let f = parse_opcode(parts[0]);
-            let l = parts[1].parse::<usize>().expect("Failed to parse level");
-            let a = parts[2].parse::<i64>().expect("Failed to parse address");
-            instructions.push(Instruction::new(f, l, a));
+    if verify && let Err(errors) = verifier::verify(&module.code) {
+        for e in &errors {
+            eprintln!("{}: invalid code: {}", path, e);
         }
+        std::process::exit(EXIT_COMPILE_ERROR);
     }
 
-    println!("Loaded {} instructions.", instructions.len());
-    println!("Executing...");
+    // The optimizer folded constants for the arithmetic the module records
+    let arithmetic = match module.arithmetic {
+        Some(built) => {
+            if overflow.is_some_and(|o| o != built.overflow)
+                || word_bits.is_some_and(|bits| bits != built.word_bits)
+            {
+                usage_error(&format!(
+                    "{} was built for --overflow {} --word-size {}",
+                    path,
+                    built.overflow.name(),
+                    built.word_bits
+                ));
+            }
+            built
+        }
+        None => {
+            let default = Arithmetic::default();
+            Arithmetic {
+                overflow: overflow.unwrap_or(default.overflow),
+                word_bits: word_bits.unwrap_or(default.word_bits),
+            }
+        }
+    };
+
+    println!("Loaded {} instructions.", module.code.len());
 
-    let mut vm = VM::new(instructions);
-    vm.interpret();
+    let vm = VM::new(module.code)
+        .with_debug_info(module.debug)
+        .with_arithmetic(arithmetic)
+        .with_stack_limit(stack_limit)
+        .with_limits(limits);
+    let state = if debugging {
+        if vm.debug_info.is_none() {
+            println!("{} has no debug information: only addresses can be used", path);
+        }
+        let queue = input.map(read_all_input).unwrap_or_default();
+        debug(vm.with_io(queue, WriterOutput(io::stdout())))
+    } else {
+        let input = match input {
+            Some(source) => ReaderInput::new(open_input(source)),
+            None => ReaderInput::interactive(Box::new(io::stdin().lock()) as Box<dyn BufRead>),
+        };
+        println!("Executing...");
+        let mut vm = vm.with_io(input, WriterOutput(io::stdout()));
+        vm.interpret();
+        vm.state
+    };
+    match state {
+        VMState::Halted | VMState::Running => {}
+        VMState::LimitExceeded(_) => std::process::exit(EXIT_LIMIT),
+        _ => std::process::exit(EXIT_RUNTIME_ERROR),
+    }
 }
@@ -1,8 +1,10 @@
 use eframe::egui;
-use pl0::types::{Instruction, OpCode};
-use pl0::vm::{VM, VMState};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use pl0::debug_info::DebugInfo;
+use pl0::module;
+use pl0::verifier;
+use pl0::types::Instruction;
+use pl0::gui::arithmetic_controls;
+use pl0::vm::{Arithmetic, VM, VMState};
 use std::time::{Duration, Instant};
 
 fn main() -> eframe::Result<()> {
@@ -24,6 +26,10 @@ struct Pl0VmGui {
     // State
     vm: VM,
     instructions: Vec<Instruction>, // Keep a copy for reset
+    debug_info: Option<DebugInfo>,
+    arithmetic: Arithmetic,
+    /// The arithmetic the loaded module was built for, if it says
+    built_for: Option<Arithmetic>,
 
     // UI State
     status_message: String,
@@ -38,79 +44,57 @@ impl Pl0VmGui {
         Self {
             vm: VM::new(vec![]),
             instructions: vec![],
-            status_message: "Ready. Load an ASM file to begin.".to_string(),
+            debug_info: None,
+            arithmetic: Arithmetic::default(),
+            built_for: None,
+            status_message: "Ready. Load a module to begin.".to_string(),
             auto_run: false,
             last_tick: Instant::now(),
             input_buffer: String::new(),
         }
     }
 
-    fn parse_opcode(s: &str) -> Option<OpCode> {
-        match s {
-            "LIT" => Some(OpCode::LIT),
-            "OPR" => Some(OpCode::OPR),
-            "LOD" => Some(OpCode::LOD),
-            "STO" => Some(OpCode::STO),
-            "CAL" => Some(OpCode::CAL),
-            "INT" => Some(OpCode::INT),
-            "JMP" => Some(OpCode::JMP),
-            "JPC" => Some(OpCode::JPC),
-            "RED" => Some(OpCode::RED),
-            "WRT" => Some(OpCode::WRT),
-            _ => None,
-        }
+    fn reset(&mut self) {
+        self.vm = VM::new(self.instructions.clone())
+            .with_debug_info(self.debug_info.clone())
+            .with_arithmetic(self.arithmetic);
+        self.auto_run = false;
+        self.status_message = "VM Reset".to_string();
     }
 
-    fn load_asm_file(&mut self) {
+    fn load_module_file(&mut self) {
         if let Some(path) = rfd::FileDialog::new()
-            .add_filter("ASM File", &["asm", "txt", "pl0asm"])
+            .add_filter("PL/0 Module", &["pl0b", "asm", "txt", "pl0asm"])
             .pick_file()
         {
-            match File::open(&path) {
-                Ok(file) => {
-                    let reader = BufReader::new(file);
-                    let mut new_instructions = Vec::new();
-                    let mut success = true;
-
-                    for (i, line) in reader.lines().enumerate() {
-                        if let Ok(l) = line {
-                            let parts: Vec<&str> = l.split_whitespace().collect();
-                            if parts.is_empty() {
-                                continue; // Skip empty lines
-                            }
-                            if parts.len() >= 3 {
-                                if let Some(f) = Self::parse_opcode(parts[0]) {
-                                    if let (Ok(l), Ok(a)) = (parts[1].parse::<usize>(), parts[2].parse::<i64>()) {
-                                        new_instructions.push(Instruction::new(f, l, a));
-                                    } else {
-                                        self.status_message = format!("Error parsing line {}: Invalid numbers", i + 1);
-                                        success = false;
-                                        break;
-                                    }
-                                } else {
-                                    self.status_message = format!("Error parsing line {}: Unknown opcode {}", i + 1, parts[0]);
-                                    success = false;
-                                    break;
-                                }
-                            } else {
-                                // Maybe handle lines that don't match strict format? 
-                                // For now, strict: Op L A
-                                self.status_message = format!("Error parsing line {}: Expected 'OP L A'", i + 1);
-                                success = false;
-                                break;
-                            }
-                        }
-                    }
-
-                    if success {
-                        self.instructions = new_instructions.clone();
-                        self.vm = VM::new(new_instructions);
-                        self.status_message = format!("Loaded {} instructions from {:?}", self.instructions.len(), path);
-                        self.auto_run = false;
+            match module::load_file(&path) {
+                Ok(module) if let Err(errors) = verifier::verify(&module.code) => {
+                    self.status_message = format!(
+                        "Rejected {:?}: {} ({} problem(s) in total)",
+                        path,
+                        errors[0],
+                        errors.len()
+                    );
+                }
+                Ok(module) => {
+                    self.instructions = module.code.clone();
+                    self.debug_info = module.debug.clone();
+                    self.built_for = module.arithmetic;
+                    if let Some(built) = module.arithmetic {
+                        self.arithmetic = built;
                     }
+                    self.vm = VM::new(module.code)
+                        .with_debug_info(module.debug)
+                        .with_arithmetic(self.arithmetic);
+                    self.status_message = format!(
+                        "Loaded {} instructions from {:?}",
+                        self.instructions.len(),
+                        path
+                    );
+                    self.auto_run = false;
                 }
                 Err(e) => {
-                    self.status_message = format!("Failed to open file: {}", e);
+                    self.status_message = format!("Failed to load {:?}: {}", path, e);
                 }
             }
         }
@@ -122,29 +106,38 @@ impl eframe::App for Pl0VmGui {
         // Top Panel: Controls
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                if ui.button("📂 Load ASM").clicked() {
-                    self.load_asm_file();
+                if ui.button("📂 Load Module").clicked() {
+                    self.load_module_file();
                 }
 
                 ui.separator();
 
-                if ui.button("Step").clicked() {
-                    if !self.instructions.is_empty() {
-                         self.vm.step();
-                    }
+                if ui.button("Step").clicked() && !self.instructions.is_empty() {
+                    self.vm.step();
                 }
                 if ui
                     .button(if self.auto_run { "Pause" } else { "Run" })
                     .clicked()
+                    && !self.instructions.is_empty()
                 {
-                    if !self.instructions.is_empty() {
-                        self.auto_run = !self.auto_run;
-                    }
+                    self.auto_run = !self.auto_run;
                 }
                 if ui.button("Reset").clicked() {
-                    self.vm = VM::new(self.instructions.clone());
-                    self.auto_run = false;
-                    self.status_message = "VM Reset".to_string();
+                    self.reset();
+                }
+
+                ui.separator();
+                if arithmetic_controls(ui, &mut self.arithmetic) {
+                    self.reset();
+                    if let Some(built) = self.built_for
+                        && built != self.arithmetic
+                    {
+                        self.status_message = format!(
+                            "VM Reset; the module was built for {} overflow on {} bits",
+                            built.overflow.name(),
+                            built.word_bits
+                        );
+                    }
                 }
 
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
@@ -163,6 +156,11 @@ impl eframe::App for Pl0VmGui {
                 ui.label(format!("SP: {:<3}", self.vm.t));
                 ui.label(format!("IR: {:?}", self.vm.i));
 
+                if let Some(loc) = self.vm.location(self.vm.p) {
+                    ui.separator();
+                    ui.label(format!("Source: {}", loc));
+                }
+
                 ui.separator();
                 ui.label(format!("State: {:?}", self.vm.state));
 
@@ -368,7 +366,7 @@ impl eframe::App for Pl0VmGui {
                                         && ui.input(|i| i.key_pressed(egui::Key::Enter))))
                                     && !self.input_buffer.is_empty()
                                         && let Ok(val) = self.input_buffer.trim().parse::<i64>() {
-                                            self.vm.input_queue.push(val);
+                                            self.vm.input.push_back(val);
                                             // Echo input to output
                                             self.vm.output.push(format!("> {}", val));
 
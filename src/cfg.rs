@@ -0,0 +1,399 @@
+//! Control-flow graphs of basic blocks, one per procedure, built from the AST.
+//!
+//! Blocks hold the simple statements (assignments, `read`, `write`, `call`);
+//! `if` and `while` become branch terminators. Every node remembers the
+//! [`StmtId`] of the AST statement it came from, so analyses on the graph can
+//! be mapped back onto the tree.
+
+use crate::ast::{Block, Condition, Program, Statement};
+use std::fmt;
+
+pub type BlockId = usize;
+
+/// Position of a statement in a pre-order walk of one procedure body, starting
+/// at 0 for the block's own statement. Nested procedures are numbered separately.
+pub type StmtId = usize;
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: StmtId,
+    /// Always an `Assignment`, `Read`, `Write` or `Call`.
+    pub stmt: Statement,
+}
+
+#[derive(Debug, Clone)]
+pub enum Terminator {
+    Goto(BlockId),
+    /// `id` is the `if` or `while` the condition belongs to.
+    Branch {
+        id: StmtId,
+        line: usize,
+        condition: Condition,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    Return,
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub nodes: Vec<Node>,
+    pub terminator: Terminator,
+}
+
+impl BasicBlock {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self.terminator {
+            Terminator::Goto(b) => vec![b],
+            Terminator::Branch {
+                then_block,
+                else_block,
+                ..
+            } => vec![then_block, else_block],
+            Terminator::Return => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub name: String,
+    /// Index of the enclosing procedure in the list returned by [`build_program`].
+    pub parent: Option<usize>,
+    pub params: Vec<String>,
+    pub locals: Vec<String>,
+    /// Variables of enclosing procedures that are visible here (not shadowed).
+    pub outer_vars: Vec<String>,
+    pub blocks: Vec<BasicBlock>,
+    pub entry: BlockId,
+    pub exit: BlockId,
+}
+
+struct Builder {
+    blocks: Vec<BasicBlock>,
+    next_id: StmtId,
+}
+
+impl Builder {
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(BasicBlock {
+            nodes: Vec::new(),
+            terminator: Terminator::Return,
+        });
+        self.blocks.len() - 1
+    }
+
+    /// Adds `stmt` to the graph starting in `current`; returns the block control continues in.
+    fn statement(&mut self, stmt: &Statement, current: BlockId) -> BlockId {
+        let id = self.next_id;
+        self.next_id += 1;
+        match stmt {
+            Statement::Assignment { .. }
+            | Statement::Read { .. }
+            | Statement::Write { .. }
+            | Statement::Call { .. } => {
+                self.blocks[current].nodes.push(Node {
+                    id,
+                    stmt: stmt.clone(),
+                });
+                current
+            }
+            Statement::BeginEnd { statements } => statements
+                .iter()
+                .fold(current, |block, s| self.statement(s, block)),
+            Statement::If {
+                condition,
+                then_stmt,
+                else_stmt,
+                line,
+            } => {
+                let then_block = self.new_block();
+                let join = self.new_block();
+                let then_end = self.statement(then_stmt, then_block);
+                self.blocks[then_end].terminator = Terminator::Goto(join);
+                let else_block = match else_stmt {
+                    Some(else_stmt) => {
+                        let else_block = self.new_block();
+                        let else_end = self.statement(else_stmt, else_block);
+                        self.blocks[else_end].terminator = Terminator::Goto(join);
+                        else_block
+                    }
+                    None => join,
+                };
+                self.blocks[current].terminator = Terminator::Branch {
+                    id,
+                    line: *line,
+                    condition: condition.clone(),
+                    then_block,
+                    else_block,
+                };
+                join
+            }
+            Statement::While {
+                condition,
+                body,
+                line,
+            } => {
+                let header = self.new_block();
+                let body_block = self.new_block();
+                let after = self.new_block();
+                self.blocks[current].terminator = Terminator::Goto(header);
+                self.blocks[header].terminator = Terminator::Branch {
+                    id,
+                    line: *line,
+                    condition: condition.clone(),
+                    then_block: body_block,
+                    else_block: after,
+                };
+                let body_end = self.statement(body, body_block);
+                self.blocks[body_end].terminator = Terminator::Goto(header);
+                after
+            }
+            Statement::Empty => current,
+        }
+    }
+}
+
+impl Cfg {
+    /// Builds the graph of one procedure body; nested procedures are not included.
+    pub fn build(name: &str, params: &[String], block: &Block, parent: Option<usize>) -> Cfg {
+        let mut builder = Builder {
+            blocks: Vec::new(),
+            next_id: 0,
+        };
+        let entry = builder.new_block();
+        let exit = builder.new_block();
+        let end = builder.statement(&block.statement, entry);
+        builder.blocks[end].terminator = Terminator::Goto(exit);
+        Cfg {
+            name: name.to_string(),
+            parent,
+            params: params.to_vec(),
+            locals: block.vars.clone(),
+            outer_vars: Vec::new(),
+            blocks: builder.blocks,
+            entry,
+            exit,
+        }
+    }
+
+    /// Every variable a statement of this procedure can name: params, locals and outer variables.
+    pub fn variables(&self) -> Vec<String> {
+        let mut vars = self.params.clone();
+        vars.extend(self.locals.iter().cloned());
+        vars.extend(self.outer_vars.iter().cloned());
+        vars
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut preds = vec![Vec::new(); self.blocks.len()];
+        for (id, block) in self.blocks.iter().enumerate() {
+            for succ in block.successors() {
+                preds[succ].push(id);
+            }
+        }
+        preds
+    }
+
+    /// Block ids in reverse post-order from the entry, the usual order for forward analyses.
+    pub fn reverse_postorder(&self) -> Vec<BlockId> {
+        let mut visited = vec![false; self.blocks.len()];
+        let mut order = Vec::new();
+        // Iterative DFS; the flag marks the second visit, after all successors are done
+        let mut stack = vec![(self.entry, false)];
+        while let Some((block, done)) = stack.pop() {
+            if done {
+                order.push(block);
+                continue;
+            }
+            if visited[block] {
+                continue;
+            }
+            visited[block] = true;
+            stack.push((block, true));
+            for succ in self.blocks[block].successors().into_iter().rev() {
+                if !visited[succ] {
+                    stack.push((succ, false));
+                }
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    fn dot_cluster(&self, index: usize, out: &mut String) {
+        out.push_str(&format!("  subgraph cluster_{} {{\n", index));
+        out.push_str(&format!("    label=\"{}\";\n", self.name));
+        for (id, block) in self.blocks.iter().enumerate() {
+            let mut label = if id == self.entry {
+                format!("B{} (entry)\\l", id)
+            } else if id == self.exit {
+                format!("B{} (exit)\\l", id)
+            } else {
+                format!("B{}\\l", id)
+            };
+            for node in &block.nodes {
+                label.push_str(&format!("{}\\l", node));
+            }
+            if let Terminator::Branch { condition, .. } = &block.terminator {
+                label.push_str(&format!("if {}\\l", condition));
+            }
+            out.push_str(&format!("    p{}_b{} [label=\"{}\"];\n", index, id, label));
+        }
+        for (id, block) in self.blocks.iter().enumerate() {
+            match block.terminator {
+                Terminator::Goto(to) => {
+                    out.push_str(&format!("    p{0}_b{1} -> p{0}_b{2};\n", index, id, to))
+                }
+                Terminator::Branch {
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    out.push_str(&format!(
+                        "    p{0}_b{1} -> p{0}_b{2} [label=\"T\"];\n",
+                        index, id, then_block
+                    ));
+                    out.push_str(&format!(
+                        "    p{0}_b{1} -> p{0}_b{2} [label=\"F\"];\n",
+                        index, id, else_block
+                    ));
+                }
+                Terminator::Return => {}
+            }
+        }
+        out.push_str("  }\n");
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |exprs: &[crate::ast::Expr]| {
+            exprs
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match &self.stmt {
+            Statement::Assignment { name, expr, .. } => write!(f, "{} := {}", name, expr),
+            Statement::Read { names, .. } => write!(f, "read({})", names.join(", ")),
+            Statement::Write { exprs, .. } => write!(f, "write({})", list(exprs)),
+            Statement::Call { name, args, .. } if args.is_empty() => write!(f, "call {}", name),
+            Statement::Call { name, args, .. } => write!(f, "call {}({})", name, list(args)),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+fn collect(
+    name: &str,
+    params: &[String],
+    block: &Block,
+    parent: Option<usize>,
+    outer_vars: &[String],
+    cfgs: &mut Vec<Cfg>,
+) {
+    let index = cfgs.len();
+    let mut cfg = Cfg::build(name, params, block, parent);
+    // Anything declared here hides an outer variable of the same name
+    cfg.outer_vars = outer_vars
+        .iter()
+        .filter(|v| {
+            !params.contains(v)
+                && !block.vars.contains(v)
+                && !block.consts.iter().any(|c| &c.name == *v)
+                && !block.procedures.iter().any(|p| &p.name == *v)
+        })
+        .cloned()
+        .collect();
+    let visible = cfg.variables();
+    cfgs.push(cfg);
+    for proc_decl in &block.procedures {
+        collect(
+            &proc_decl.name,
+            &proc_decl.params,
+            &proc_decl.block,
+            Some(index),
+            &visible,
+            cfgs,
+        );
+    }
+}
+
+/// One graph per procedure, main program first, in the same order as
+/// [`DebugInfo::procedures`](crate::debug_info::DebugInfo::procedures).
+pub fn build_program(program: &Program) -> Vec<Cfg> {
+    let mut cfgs = Vec::new();
+    collect(&program.name, &[], &program.block, None, &[], &mut cfgs);
+    cfgs
+}
+
+/// Graphviz rendering with one cluster per procedure.
+pub fn to_dot(cfgs: &[Cfg]) -> String {
+    let mut out = String::from("digraph cfg {\n  node [shape=box, fontname=\"monospace\"];\n");
+    for (index, cfg) in cfgs.iter().enumerate() {
+        cfg.dot_cluster(index, &mut out);
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+
+    #[test]
+    fn test_shape() {
+        let source = "program t;
+var x, y;
+procedure p;
+begin
+  write(x)
+end;
+begin
+  read(x);
+  if x > 0 then y := 1 else y := 2;
+  while y < 10 do y := y * 2;
+  call p
+end.";
+        let program = compiler::parse(source, false).unwrap();
+        let cfgs = build_program(&program);
+        assert_eq!(cfgs.len(), 2);
+        assert_eq!(cfgs[1].parent, Some(0));
+
+        let main = &cfgs[0];
+        let entry = &main.blocks[main.entry];
+        assert_eq!(entry.nodes.len(), 1);
+        let Terminator::Branch {
+            id,
+            then_block,
+            else_block,
+            ..
+        } = entry.terminator
+        else {
+            panic!("entry should end in the if");
+        };
+        // begin = 0, read = 1, if = 2
+        assert_eq!(id, 2);
+        assert_eq!(main.blocks[then_block].nodes[0].to_string(), "y := 1");
+        assert_eq!(main.blocks[else_block].nodes[0].to_string(), "y := 2");
+
+        // Every block except the exit reaches the exit, and the loop header has two predecessors
+        let preds = main.predecessors();
+        let header = main
+            .blocks
+            .iter()
+            .position(|b| matches!(&b.terminator, Terminator::Branch { id, .. } if *id == 5))
+            .unwrap();
+        assert_eq!(preds[header].len(), 2);
+        assert_eq!(main.reverse_postorder().len(), main.blocks.len());
+        assert_eq!(main.reverse_postorder()[0], main.entry);
+
+        let dot = to_dot(&cfgs);
+        assert!(dot.contains("label=\"p\""));
+        assert!(dot.contains("if y < 10\\l"));
+        assert!(dot.contains("call p\\l"));
+    }
+}
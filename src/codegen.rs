@@ -1,10 +1,15 @@
 use crate::ast::*;
+use crate::debug_info::{DebugInfo, LineEntry, ProcInfo, VarInfo};
 use crate::symbol_table::SymbolTable;
 use crate::types::{Instruction, OpCode, Operator, SymbolType};
 
 pub struct CodeGenerator {
     code: Vec<Instruction>,
     level: usize,
+    debug: DebugInfo,
+    /// Entry address and first body instruction of the procedure being generated,
+    /// so that a call to itself in tail position can become a jump.
+    tail_target: Option<(i64, usize)>,
 }
 
 impl Default for CodeGenerator {
@@ -18,9 +23,16 @@ impl CodeGenerator {
         Self {
             code: Vec::new(),
             level: 0,
+            debug: DebugInfo::default(),
+            tail_target: None,
         }
     }
 
+    /// Line table, procedure table and variable layouts for the last `generate` call.
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug
+    }
+
     pub fn generate(
         &mut self,
         program: &Program,
@@ -28,7 +40,8 @@ impl CodeGenerator {
     ) -> Vec<Instruction> {
         // Ensure we start at root scope
         symbol_table.current_scope_id = 0;
-        self.generate_block(&program.block, symbol_table);
+        self.generate_block(&program.name, &program.block, program.line, None, symbol_table);
+        self.debug.procedures[0].exit = self.code.len();
         self.emit(OpCode::OPR, 0, Operator::RET as i64);
         self.code.clone()
     }
@@ -37,7 +50,26 @@ impl CodeGenerator {
         self.code.push(Instruction::new(f, l, a));
     }
 
-    fn generate_block(&mut self, block: &Block, symbol_table: &mut SymbolTable) {
+    /// Attributes the instructions emitted from here on to `line`.
+    fn mark_line(&mut self, line: usize) {
+        let addr = self.code.len();
+        // An entry that has not covered any instruction yet is simply replaced
+        if self.debug.lines.last().is_some_and(|last| last.addr == addr) {
+            self.debug.lines.pop();
+        }
+        if self.debug.lines.last().is_none_or(|last| last.line != line) {
+            self.debug.lines.push(LineEntry { addr, line });
+        }
+    }
+
+    fn generate_block(
+        &mut self,
+        name: &str,
+        block: &Block,
+        line: usize,
+        parent: Option<usize>,
+        symbol_table: &mut SymbolTable,
+    ) {
         // Enter the scope associated with this block
         if let Some(scope_id) = block.scope_id {
             symbol_table.enter_scope(scope_id);
@@ -45,6 +77,31 @@ impl CodeGenerator {
             panic!("Block has no scope ID assigned");
         }
 
+        let mut vars: Vec<VarInfo> = symbol_table.scopes[symbol_table.current_scope_id]
+            .symbols
+            .values()
+            .filter_map(|sym| match sym.kind {
+                SymbolType::Variable { addr, .. } => Some(VarInfo {
+                    name: sym.name.clone(),
+                    addr,
+                }),
+                _ => None,
+            })
+            .collect();
+        vars.sort_by_key(|v| v.addr);
+
+        let proc_index = self.debug.procedures.len();
+        self.debug.procedures.push(ProcInfo {
+            name: name.to_string(),
+            level: self.level,
+            parent,
+            entry: self.code.len(),
+            body: 0,
+            exit: 0,
+            vars,
+        });
+
+        self.mark_line(line);
         let jmp_addr = self.code.len();
         self.emit(OpCode::JMP, 0, 0); // Placeholder
 
@@ -65,29 +122,46 @@ impl CodeGenerator {
                     *addr = proc_addr as i64;
                 }
 
+            let child_index = self.debug.procedures.len();
             self.level += 1;
-            self.generate_block(&proc_decl.block, symbol_table);
+            self.generate_block(
+                &proc_decl.name,
+                &proc_decl.block,
+                proc_decl.line,
+                Some(proc_index),
+                symbol_table,
+            );
             self.level -= 1;
 
+            self.debug.procedures[child_index].exit = self.code.len();
             self.emit(OpCode::OPR, 0, Operator::RET as i64);
         }
 
         // Fix JMP
         self.code[jmp_addr].a = self.code.len() as i64;
+        self.debug.procedures[proc_index].body = self.code.len();
+        self.mark_line(line);
 
         // Allocate space
         self.emit(OpCode::INT, 0, var_offset as i64);
 
-        self.generate_statement(&block.statement, symbol_table);
+        // Nested procedures are done by now, so this only covers our own statements
+        self.tail_target = parent.map(|_| {
+            (self.debug.procedures[proc_index].entry as i64, self.code.len())
+        });
+        let tail = self.tail_target.is_some();
+        self.generate_statement(&block.statement, tail, symbol_table);
 
         if block.scope_id != Some(0) {
             symbol_table.exit_scope();
         }
     }
 
-    fn generate_statement(&mut self, stmt: &Statement, symbol_table: &mut SymbolTable) {
+    /// `tail` is set when nothing but the procedure's return follows `stmt`.
+    fn generate_statement(&mut self, stmt: &Statement, tail: bool, symbol_table: &mut SymbolTable) {
         match stmt {
-            Statement::Assignment { name, expr, .. } => {
+            Statement::Assignment { name, expr, line } => {
+                self.mark_line(*line);
                 self.generate_expr(expr, symbol_table);
                 let sym = symbol_table.resolve(name).expect("Undefined variable");
                 match sym.kind {
@@ -97,13 +171,26 @@ impl CodeGenerator {
                     _ => panic!("Cannot assign to non-variable"),
                 }
             }
-            Statement::Call { name, args, .. } => {
+            Statement::Call { name, args, line } => {
+                self.mark_line(*line);
                 for arg in args {
                     self.generate_expr(arg, symbol_table);
                 }
 
                 let sym = symbol_table.resolve(name).expect("Undefined procedure");
                 match sym.kind {
+                    // A self call with no work after it reuses the frame: store the
+                    // arguments into the parameters and restart the body
+                    SymbolType::Procedure { addr, .. }
+                        if tail && self.tail_target.is_some_and(|(entry, _)| entry == addr) =>
+                    {
+                        // The last argument is on top and belongs in the last parameter, at -1
+                        for offset in 1..=args.len() {
+                            self.emit(OpCode::STO, 0, -(offset as i64));
+                        }
+                        let (_, body) = self.tail_target.unwrap();
+                        self.emit(OpCode::JMP, 0, body as i64);
+                    }
                     SymbolType::Procedure { level, addr } => {
                         self.emit(OpCode::CAL, self.level - level, addr);
                         if !args.is_empty() {
@@ -114,44 +201,56 @@ impl CodeGenerator {
                 }
             }
             Statement::BeginEnd { statements } => {
-                for s in statements {
-                    self.generate_statement(s, symbol_table);
+                // Trailing empty statements (`...; end`) do not count as work
+                let last = statements
+                    .iter()
+                    .rposition(|s| !matches!(s, Statement::Empty));
+                for (i, s) in statements.iter().enumerate() {
+                    self.generate_statement(s, tail && Some(i) == last, symbol_table);
                 }
             }
             Statement::If {
                 condition,
                 then_stmt,
                 else_stmt,
-                ..
+                line,
             } => {
+                self.mark_line(*line);
                 self.generate_condition(condition, symbol_table);
                 let jpc_idx = self.code.len();
                 self.emit(OpCode::JPC, 0, 0);
 
-                self.generate_statement(then_stmt, symbol_table);
+                self.generate_statement(then_stmt, tail, symbol_table);
 
                 if let Some(else_s) = else_stmt {
                     let jmp_idx = self.code.len();
                     self.emit(OpCode::JMP, 0, 0);
                     self.code[jpc_idx].a = self.code.len() as i64;
-                    self.generate_statement(else_s, symbol_table);
+                    self.generate_statement(else_s, tail, symbol_table);
                     self.code[jmp_idx].a = self.code.len() as i64;
                 } else {
                     self.code[jpc_idx].a = self.code.len() as i64;
                 }
             }
-            Statement::While { condition, body, .. } => {
+            Statement::While {
+                condition,
+                body,
+                line,
+            } => {
+                self.mark_line(*line);
                 let start_idx = self.code.len();
                 self.generate_condition(condition, symbol_table);
                 let jpc_idx = self.code.len();
                 self.emit(OpCode::JPC, 0, 0);
 
-                self.generate_statement(body, symbol_table);
+                self.generate_statement(body, false, symbol_table);
+                self.mark_line(*line);
                 self.emit(OpCode::JMP, 0, start_idx as i64);
 
                 self.code[jpc_idx].a = self.code.len() as i64;
             }
-            Statement::Read { names, .. } => {
+            Statement::Read { names, line } => {
+                self.mark_line(*line);
                 for name in names {
                     self.emit(OpCode::OPR, 0, Operator::RED as i64);
                     let sym = symbol_table.resolve(name).expect("Undefined variable");
@@ -163,7 +262,8 @@ impl CodeGenerator {
                     }
                 }
             }
-            Statement::Write { exprs, .. } => {
+            Statement::Write { exprs, line } => {
+                self.mark_line(*line);
                 for expr in exprs {
                     self.generate_expr(expr, symbol_table);
                     self.emit(OpCode::OPR, 0, Operator::WRT as i64);
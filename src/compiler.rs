@@ -0,0 +1,154 @@
+use crate::ast::Program;
+use crate::codegen::CodeGenerator;
+use crate::debug_info::DebugInfo;
+use crate::lexer::Lexer;
+use crate::optimizer::{self, OptimizeOptions, OptimizeStats, dataflow, optimize_ast_with};
+use crate::parser::{ParseError, Parser};
+use crate::peephole;
+use crate::semantic::SemanticAnalyzer;
+use crate::symbol_table::SymbolTable;
+use crate::types::{Instruction, TokenType};
+
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+    pub optimize: bool,
+    pub verbose: bool,
+    /// The AST passes run when `optimize` is set.
+    pub optimizer: OptimizeOptions,
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    Parse(Vec<ParseError>),
+    Semantic(Vec<String>),
+}
+
+/// Everything produced by a successful run of the pipeline.
+pub struct Compilation {
+    pub program: Program,
+    pub symbol_table: SymbolTable,
+    pub code: Vec<Instruction>,
+    pub debug_info: DebugInfo,
+    /// All zero unless `optimize` was set.
+    pub stats: OptimizeStats,
+}
+
+/// Runs the lexer on its own and collects every token with its position.
+pub fn tokenize(source: &str) -> Vec<(usize, usize, TokenType)> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.current_token.clone();
+        tokens.push((lexer.token_line, lexer.token_col, token.clone()));
+        if token == TokenType::Eof {
+            break;
+        }
+        lexer.next_token();
+    }
+    tokens
+}
+
+pub fn parse(source: &str, verbose: bool) -> Result<Program, CompileError> {
+    let lexer = Lexer::new(source);
+    let mut parser = Parser::new(lexer, verbose);
+    let result = parser.parse();
+    if !parser.errors.is_empty() {
+        return Err(CompileError::Parse(parser.errors));
+    }
+    match result {
+        Ok(program) => Ok(program),
+        Err(_) => Err(CompileError::Parse(vec![ParseError {
+            line: 0,
+            col: 0,
+            message: "Fatal parsing error".to_string(),
+        }])),
+    }
+}
+
+/// Parses and checks the program, returning the annotated AST and its symbol table.
+pub fn check(source: &str, verbose: bool) -> Result<(Program, SymbolTable), CompileError> {
+    check_program(parse(source, verbose)?)
+}
+
+/// Checks an already-built AST, e.g. one deserialized from JSON or an S-expression.
+pub fn check_program(mut program: Program) -> Result<(Program, SymbolTable), CompileError> {
+    let mut symbol_table = SymbolTable::new();
+    let mut analyzer = SemanticAnalyzer::new(&mut symbol_table);
+    analyzer
+        .analyze(&mut program)
+        .map_err(CompileError::Semantic)?;
+    Ok((program, symbol_table))
+}
+
+/// Non-fatal diagnostics for a checked program, such as reads of unassigned
+/// variables and constant arithmetic that overflows.
+pub fn warnings(program: &Program) -> Vec<String> {
+    let mut warnings = dataflow::uninitialized_warnings(program);
+    warnings.extend(optimizer::overflow_warnings(program));
+    warnings
+}
+
+/// Full pipeline: parse, analyze, optionally optimize, and generate P-code.
+pub fn compile(source: &str, options: &CompileOptions) -> Result<Compilation, CompileError> {
+    compile_program(parse(source, options.verbose)?, options)
+}
+
+/// Same as [`compile`], starting from an AST instead of source text.
+pub fn compile_program(
+    program: Program,
+    options: &CompileOptions,
+) -> Result<Compilation, CompileError> {
+    let (mut program, mut symbol_table) = check_program(program)?;
+
+    let mut stats = OptimizeStats::default();
+    if options.optimize {
+        // The optimizer may rewrite declarations, so analyze the result again
+        stats = optimize_ast_with(&mut program, &options.optimizer);
+        symbol_table = SymbolTable::new();
+        let mut analyzer = SemanticAnalyzer::new(&mut symbol_table);
+        analyzer
+            .analyze(&mut program)
+            .map_err(CompileError::Semantic)?;
+    }
+
+    let mut generator = CodeGenerator::new();
+    let mut code = generator.generate(&program, &mut symbol_table);
+    let mut debug_info = generator.debug_info().clone();
+    if options.optimize {
+        code = peephole::optimize(code, &mut debug_info);
+    }
+
+    Ok(Compilation {
+        program,
+        symbol_table,
+        code,
+        debug_info,
+        stats,
+    })
+}
+
+/// Renders a parse error the way compilers usually do, with the offending line and a caret.
+pub fn format_parse_error(source: &str, path: &str, err: &ParseError) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut msg = format!("{}:{}:{}: error: {}", path, err.line, err.col, err.message);
+    if err.line > 0 && err.line <= lines.len() {
+        let line_content = lines[err.line - 1];
+        msg.push_str(&format!("\n    {}", line_content));
+        let indent: String = line_content
+            .chars()
+            .take(err.col.saturating_sub(1))
+            .map(|c| if c.is_whitespace() { c } else { ' ' })
+            .collect();
+        msg.push_str(&format!("\n    {}^", indent));
+    }
+    msg
+}
+
+/// Writes instructions in the whitespace-separated text form `pl0vm` reads.
+pub fn format_asm(code: &[Instruction]) -> String {
+    let mut out = String::new();
+    for instr in code {
+        out.push_str(&format!("{:?} {} {}\n", instr.f, instr.l, instr.a));
+    }
+    out
+}
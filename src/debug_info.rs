@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+/// Source-level information carried alongside the code of a module.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DebugInfo {
+    pub source_file: Option<String>,
+    /// Sorted by address; each entry covers instructions up to the next one.
+    pub lines: Vec<LineEntry>,
+    /// The main program is always entry 0.
+    pub procedures: Vec<ProcInfo>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LineEntry {
+    pub addr: usize,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcInfo {
+    pub name: String,
+    /// Static nesting level of the procedure body (0 for the main program).
+    pub level: usize,
+    /// Index of the enclosing procedure in `DebugInfo::procedures`.
+    pub parent: Option<usize>,
+    /// Address of the leading `JMP`; this is what `CAL` targets.
+    pub entry: usize,
+    /// Address of the `INT` that allocates the frame, i.e. the first body instruction.
+    pub body: usize,
+    /// Address of the final `OPR 0 0`.
+    pub exit: usize,
+    /// Parameters (negative addresses) and locals, ordered by address.
+    pub vars: Vec<VarInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VarInfo {
+    pub name: String,
+    pub addr: i64,
+}
+
+impl DebugInfo {
+    pub fn line_for(&self, addr: usize) -> Option<usize> {
+        let idx = self.lines.partition_point(|e| e.addr <= addr);
+        if idx == 0 {
+            None
+        } else {
+            Some(self.lines[idx - 1].line)
+        }
+    }
+
+    /// Index of the innermost procedure whose code range contains `addr`.
+    pub fn procedure_at(&self, addr: usize) -> Option<usize> {
+        self.procedures
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.entry <= addr && addr <= p.exit)
+            .min_by_key(|(_, p)| p.exit - p.entry)
+            .map(|(i, _)| i)
+    }
+
+    pub fn procedure_named(&self, name: &str) -> Option<usize> {
+        self.procedures.iter().position(|p| p.name == name)
+    }
+
+    /// Human-readable location such as `line 7 in fact`.
+    pub fn describe(&self, addr: usize) -> Option<String> {
+        let line = self.line_for(addr);
+        let proc_name = self.procedure_at(addr).map(|i| &self.procedures[i].name);
+        match (line, proc_name) {
+            (Some(l), Some(p)) => Some(format!("line {} in {}", l, p)),
+            (Some(l), None) => Some(format!("line {}", l)),
+            (None, Some(p)) => Some(format!("in {}", p)),
+            (None, None) => None,
+        }
+    }
+}
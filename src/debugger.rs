@@ -0,0 +1,471 @@
+//! Source-level debugging on top of the VM.
+//!
+//! A [`Debugger`] owns a [`VM`] and runs it in small pieces: one statement
+//! (`step`), one statement of the current procedure (`step_over`), up to the
+//! return of the current procedure (`finish`), or until a breakpoint or
+//! watched variable stops it (`cont`). Statements are the entries of the debug
+//! info's line table; variables are looked up through the procedures' variable
+//! lists and the static links on the stack, just as the code itself reaches
+//! them.
+
+use crate::debug_info::DebugInfo;
+use crate::vm::{Input, Output, VM, VMState};
+use std::collections::VecDeque;
+
+/// Why a run of the debugger stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    /// The requested step is complete.
+    Step,
+    /// The VM reached a breakpoint at this address.
+    Breakpoint(usize),
+    /// A watched variable changed.
+    Watch { name: String, old: i64, new: i64 },
+    /// A watched variable's frame returned, so the watch was removed.
+    WatchOutOfScope(String),
+    /// The VM is no longer running; its `state` says why.
+    Ended,
+}
+
+/// One activation record on the stack.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub base: usize,
+    /// Where the frame is executing: the current instruction for the innermost
+    /// frame, the `CAL` it is waiting on for the others.
+    pub pc: usize,
+    pub procedure: Option<String>,
+    pub static_link: i64,
+    pub dynamic_link: i64,
+    pub return_addr: i64,
+}
+
+struct Watch {
+    name: String,
+    addr: usize,
+    value: i64,
+    /// Call depth of the frame holding the variable.
+    depth: usize,
+}
+
+pub struct Debugger<I = VecDeque<i64>, O = Vec<String>> {
+    pub vm: VM<I, O>,
+    breakpoints: Vec<usize>,
+    watches: Vec<Watch>,
+}
+
+impl<I: Input, O: Output> Debugger<I, O> {
+    pub fn new(vm: VM<I, O>) -> Self {
+        Self {
+            vm,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+        }
+    }
+
+    fn debug_info(&self) -> Result<&DebugInfo, String> {
+        self.vm
+            .debug_info
+            .as_ref()
+            .ok_or_else(|| "the module has no debug information".to_string())
+    }
+
+    fn cell(&self, addr: usize) -> i64 {
+        self.vm.stack.get(addr).copied().unwrap_or(0)
+    }
+
+    /// Address of the instruction about to run, with its source location if known.
+    pub fn location(&self) -> String {
+        match self.vm.location(self.vm.p) {
+            Some(loc) => format!("pc {} ({})", self.vm.p, loc),
+            None => format!("pc {}", self.vm.p),
+        }
+    }
+
+    /// The address a breakpoint spec stands for: `*N` is address N, a bare
+    /// number is the first instruction of that source line, and a name is the
+    /// first instruction of that procedure's body, after its frame is allocated.
+    pub fn resolve(&self, spec: &str) -> Result<usize, String> {
+        let code_len = self.vm.code.len();
+        if let Some(addr) = spec.strip_prefix('*') {
+            return match addr.parse::<usize>() {
+                Ok(addr) if addr < code_len => Ok(addr),
+                _ => Err(format!("no instruction at address '{}'", addr)),
+            };
+        }
+        let debug = self.debug_info()?;
+        if let Ok(line) = spec.parse::<usize>() {
+            return debug
+                .lines
+                .iter()
+                .filter(|e| e.line == line)
+                .map(|e| e.addr)
+                .min()
+                .ok_or_else(|| format!("no code for line {}", line));
+        }
+        let proc_info = debug
+            .procedure_named(spec)
+            .map(|i| &debug.procedures[i])
+            .ok_or_else(|| format!("no procedure named '{}'", spec))?;
+        Ok((proc_info.body + 1).min(proc_info.exit))
+    }
+
+    /// Adds a breakpoint; returns false if there already was one at `addr`.
+    pub fn add_breakpoint(&mut self, addr: usize) -> bool {
+        match self.breakpoints.binary_search(&addr) {
+            Ok(_) => false,
+            Err(i) => {
+                self.breakpoints.insert(i, addr);
+                true
+            }
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: usize) -> bool {
+        let before = self.breakpoints.len();
+        self.breakpoints.retain(|&b| b != addr);
+        self.breakpoints.len() != before
+    }
+
+    /// Breakpoint addresses in increasing order.
+    pub fn breakpoints(&self) -> &[usize] {
+        &self.breakpoints
+    }
+
+    /// Stack address of variable `name` as seen from the current instruction,
+    /// and the call depth of the frame it lives in.
+    fn lookup(&self, name: &str) -> Result<(usize, usize), String> {
+        let debug = self.debug_info()?;
+        let not_found = || format!("no variable '{}' in scope", name);
+        let mut proc_index = debug.procedure_at(self.vm.p).ok_or_else(not_found)?;
+        let mut base = self.vm.b;
+        loop {
+            let proc_info = &debug.procedures[proc_index];
+            if let Some(var) = proc_info.vars.iter().find(|v| v.name == name) {
+                let addr = (base as i64)
+                    .checked_add(var.addr)
+                    .and_then(|addr| usize::try_from(addr).ok())
+                    .filter(|&addr| addr < self.vm.stack.len())
+                    .ok_or_else(|| format!("'{}' is not on the stack", name))?;
+                let frames = self.frames();
+                let depth = frames
+                    .iter()
+                    .position(|f| f.base == base)
+                    .map_or(0, |i| self.vm.depth.saturating_sub(i));
+                return Ok((addr, depth));
+            }
+            proc_index = proc_info.parent.ok_or_else(not_found)?;
+            base = usize::try_from(self.cell(base))
+                .ok()
+                .filter(|&link| link < base)
+                .ok_or_else(|| format!("broken static link in frame {}", base))?;
+        }
+    }
+
+    /// Current value of variable `name`.
+    pub fn print(&self, name: &str) -> Result<i64, String> {
+        let (addr, _) = self.lookup(name)?;
+        Ok(self.cell(addr))
+    }
+
+    /// Stops execution whenever variable `name`, as seen from here, changes.
+    pub fn watch(&mut self, name: &str) -> Result<i64, String> {
+        let (addr, depth) = self.lookup(name)?;
+        let value = self.cell(addr);
+        self.watches.push(Watch {
+            name: name.to_string(),
+            addr,
+            value,
+            depth,
+        });
+        Ok(value)
+    }
+
+    /// Names of the variables being watched.
+    pub fn watches(&self) -> Vec<&str> {
+        self.watches.iter().map(|w| w.name.as_str()).collect()
+    }
+
+    /// Activation records from the innermost out, following the dynamic links.
+    pub fn frames(&self) -> Vec<Frame> {
+        let procedure = |pc: usize| {
+            let debug = self.vm.debug_info.as_ref()?;
+            debug
+                .procedure_at(pc)
+                .map(|i| debug.procedures[i].name.clone())
+        };
+        let mut frames: Vec<Frame> = Vec::new();
+        let (mut base, mut pc) = (self.vm.b, self.vm.p);
+        loop {
+            let frame = Frame {
+                base,
+                pc,
+                procedure: procedure(pc),
+                static_link: self.cell(base),
+                dynamic_link: self.cell(base + 1),
+                return_addr: self.cell(base + 2),
+            };
+            let (dynamic_link, return_addr) = (frame.dynamic_link, frame.return_addr);
+            frames.push(frame);
+            // Dynamic links always point further down, which also ends the walk
+            // on a corrupted stack
+            match usize::try_from(dynamic_link) {
+                Ok(link) if base > 0 && link < base => {
+                    base = link;
+                    pc = usize::try_from(return_addr).unwrap_or(0).saturating_sub(1);
+                }
+                _ => break,
+            }
+        }
+        frames
+    }
+
+    /// Labels for the stack cells below the top: frame bookkeeping and the
+    /// names of the variables of every active frame.
+    pub fn stack_labels(&self) -> Vec<(usize, i64, String)> {
+        let mut labels = vec![String::new(); self.vm.t.min(self.vm.stack.len())];
+        let debug = self.vm.debug_info.as_ref();
+        for frame in self.frames() {
+            let name = frame.procedure.as_deref().unwrap_or("?");
+            for (offset, what) in ["SL", "DL", "RA"].iter().enumerate() {
+                if let Some(label) = labels.get_mut(frame.base + offset) {
+                    *label = format!("{} of {}", what, name);
+                }
+            }
+            let vars = debug
+                .and_then(|d| d.procedure_at(frame.pc).map(|i| &d.procedures[i].vars))
+                .into_iter()
+                .flatten();
+            for var in vars {
+                let addr = (frame.base as i64).checked_add(var.addr);
+                if let Some(addr) = addr.and_then(|addr| usize::try_from(addr).ok())
+                    && let Some(label) = labels.get_mut(addr)
+                {
+                    *label = format!("{} in {}", var.name, name);
+                }
+            }
+        }
+        labels
+            .into_iter()
+            .enumerate()
+            .map(|(addr, label)| (addr, self.vm.stack[addr], label))
+            .collect()
+    }
+
+    /// The first watched variable that changed or went out of scope.
+    fn check_watches(&mut self) -> Option<Stop> {
+        for i in 0..self.watches.len() {
+            let watch = &self.watches[i];
+            if self.vm.depth < watch.depth {
+                let watch = self.watches.remove(i);
+                return Some(Stop::WatchOutOfScope(watch.name));
+            }
+            let new = self.cell(watch.addr);
+            if new != watch.value {
+                let watch = &mut self.watches[i];
+                let old = std::mem::replace(&mut watch.value, new);
+                return Some(Stop::Watch {
+                    name: watch.name.clone(),
+                    old,
+                    new,
+                });
+            }
+        }
+        None
+    }
+
+    /// Runs at least one instruction, then until `done` holds or something
+    /// else stops the VM.
+    fn run_until(&mut self, done: impl Fn(&VM<I, O>) -> bool) -> Stop {
+        loop {
+            if self.vm.state != VMState::Running {
+                return Stop::Ended;
+            }
+            self.vm.step();
+            if let Some(stop) = self.check_watches() {
+                return stop;
+            }
+            if self.vm.state != VMState::Running {
+                return Stop::Ended;
+            }
+            if self.breakpoints.binary_search(&self.vm.p).is_ok() {
+                return Stop::Breakpoint(self.vm.p);
+            }
+            if done(&self.vm) {
+                return Stop::Step;
+            }
+        }
+    }
+
+    /// True if `vm` is about to start a new statement other than `line` at `depth`.
+    fn at_statement(vm: &VM<I, O>, line: Option<usize>, depth: usize) -> bool {
+        let Some(debug) = &vm.debug_info else {
+            return true;
+        };
+        let starts_line = debug.lines.binary_search_by_key(&vm.p, |e| e.addr).is_ok();
+        starts_line || debug.line_for(vm.p) != line || vm.depth != depth
+    }
+
+    /// Runs a single instruction.
+    pub fn stepi(&mut self) -> Stop {
+        self.run_until(|_| true)
+    }
+
+    /// Runs to the next statement, entering procedures that are called.
+    pub fn step(&mut self) -> Stop {
+        let line = self
+            .vm
+            .debug_info
+            .as_ref()
+            .and_then(|d| d.line_for(self.vm.p));
+        let depth = self.vm.depth;
+        self.run_until(|vm| Self::at_statement(vm, line, depth))
+    }
+
+    /// Runs to the next statement of the current procedure, or of its caller
+    /// once it returns, running any calls in between to completion.
+    pub fn step_over(&mut self) -> Stop {
+        let line = self
+            .vm
+            .debug_info
+            .as_ref()
+            .and_then(|d| d.line_for(self.vm.p));
+        let depth = self.vm.depth;
+        self.run_until(|vm| vm.depth <= depth && Self::at_statement(vm, line, depth))
+    }
+
+    /// Runs until the current procedure returns; in the main program, to the end.
+    pub fn finish(&mut self) -> Stop {
+        let depth = self.vm.depth;
+        self.run_until(|vm| vm.depth < depth)
+    }
+
+    /// Runs until a breakpoint or watch stops the program, or it ends.
+    pub fn cont(&mut self) -> Stop {
+        self.run_until(|_| false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{self, CompileOptions};
+
+    const SOURCE: &str = "program t;
+var n, total;
+procedure add(x);
+var doubled;
+begin
+  doubled := x * 2;
+  total := total + doubled
+end;
+begin
+  n := 3;
+  total := 0;
+  while n > 0 do
+  begin
+    call add(n);
+    n := n - 1
+  end;
+  write(total)
+end.";
+
+    fn debugger() -> Debugger {
+        let compilation = compiler::compile(SOURCE, &CompileOptions::default()).unwrap();
+        Debugger::new(VM::new(compilation.code).with_debug_info(Some(compilation.debug_info)))
+    }
+
+    fn line(dbg: &Debugger) -> Option<usize> {
+        dbg.vm.location(dbg.vm.p).map(|loc| {
+            loc.trim_start_matches("line ")
+                .split(' ')
+                .next()
+                .unwrap()
+                .parse()
+                .unwrap()
+        })
+    }
+
+    #[test]
+    fn test_breakpoints_and_variables() {
+        let mut dbg = debugger();
+        let entry = dbg.resolve("add").unwrap();
+        assert!(dbg.add_breakpoint(entry));
+        assert!(!dbg.add_breakpoint(entry));
+        assert_eq!(dbg.cont(), Stop::Breakpoint(entry));
+        assert_eq!(line(&dbg), Some(6));
+        assert_eq!(dbg.print("x"), Ok(3));
+        assert_eq!(dbg.print("n"), Ok(3));
+        assert!(dbg.print("nope").is_err());
+
+        let frames = dbg.frames();
+        let names: Vec<_> = frames.iter().map(|f| f.procedure.as_deref()).collect();
+        assert_eq!(names, vec![Some("add"), Some("t")]);
+        assert_eq!(frames[0].static_link, 0);
+        assert_eq!(frames[0].dynamic_link, 0);
+        let labels = dbg.stack_labels();
+        assert!(
+            labels.iter().any(|(_, _, l)| l == "doubled in add"),
+            "{:?}",
+            labels
+        );
+
+        // Statement by statement through the procedure and back into the loop
+        assert_eq!(dbg.step(), Stop::Step);
+        assert_eq!(line(&dbg), Some(7));
+        assert_eq!(dbg.print("doubled"), Ok(6));
+        assert_eq!(dbg.finish(), Stop::Step);
+        assert_eq!(dbg.vm.depth, 0);
+        assert_eq!(dbg.print("total"), Ok(6));
+
+        // The next call stops at the breakpoint again, with the next argument
+        assert!(dbg.remove_breakpoint(entry));
+        let call_line = dbg.resolve("14").unwrap();
+        dbg.add_breakpoint(call_line);
+        assert_eq!(dbg.cont(), Stop::Breakpoint(call_line));
+        assert_eq!(dbg.print("n"), Ok(2));
+        // Stepping over runs the call without stopping in it
+        assert_eq!(dbg.step_over(), Stop::Step);
+        assert_eq!(line(&dbg), Some(15));
+        assert_eq!(dbg.print("total"), Ok(10));
+
+        assert!(dbg.resolve("99").is_err());
+        assert!(dbg.resolve("*1000").is_err());
+        assert_eq!(dbg.resolve("*2"), Ok(2));
+    }
+
+    #[test]
+    fn test_watches() {
+        let mut dbg = debugger();
+        assert_eq!(dbg.watch("total"), Ok(0));
+        let mut changes = Vec::new();
+        loop {
+            match dbg.cont() {
+                Stop::Watch { new, .. } => changes.push(new),
+                Stop::Ended => break,
+                other => panic!("unexpected stop {:?}", other),
+            }
+        }
+        assert_eq!(dbg.vm.state, VMState::Halted);
+        assert_eq!(changes, vec![6, 10, 12]);
+        assert_eq!(dbg.vm.output, vec!["12"]);
+
+        // A local's watch ends with its frame
+        let mut dbg = debugger();
+        let entry = dbg.resolve("add").unwrap();
+        dbg.add_breakpoint(entry);
+        dbg.cont();
+        dbg.remove_breakpoint(entry);
+        dbg.watch("doubled").unwrap();
+        assert_eq!(
+            dbg.cont(),
+            Stop::Watch {
+                name: "doubled".to_string(),
+                old: 0,
+                new: 6
+            }
+        );
+        assert_eq!(dbg.cont(), Stop::WatchOutOfScope("doubled".to_string()));
+        assert!(dbg.watches().is_empty());
+    }
+}
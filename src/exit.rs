@@ -0,0 +1,11 @@
+//! Process exit codes shared by `pl0c` and `pl0vm`, so scripts can treat
+//! both the same way.
+
+pub const EXIT_OK: i32 = 0;
+/// The source does not compile, or a module is not valid code.
+pub const EXIT_COMPILE_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_RUNTIME_ERROR: i32 = 3;
+pub const EXIT_IO_ERROR: i32 = 4;
+/// A step, time, output or depth limit stopped the program.
+pub const EXIT_LIMIT: i32 = 5;
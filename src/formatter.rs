@@ -0,0 +1,599 @@
+//! Canonical pretty-printer for PL/0 source, used by `pl0fmt`.
+//!
+//! The program is parsed into an AST and printed back. Comments are not part
+//! of the AST, so they are re-attached by position: the words (keywords,
+//! identifiers and numbers) of the output appear in exactly the same order as
+//! in the source, which lets every comment be placed next to the word it
+//! followed or preceded. A comment that started its own line stays on its own
+//! line; any other comment ends up at the end of the output line holding the
+//! word before it. Formatting is idempotent.
+
+use crate::ast::{Block, Condition, Expr, Program, Statement};
+use crate::compiler::{self, CompileError};
+use crate::lexer::{Comment, Lexer};
+use crate::types::{Operator, TokenType};
+
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    pub uppercase_keywords: bool,
+    /// `var x, y, z;` on one line instead of one name per line.
+    pub packed_decls: bool,
+    pub indent_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            uppercase_keywords: false,
+            packed_decls: true,
+            indent_width: 2,
+        }
+    }
+}
+
+struct Formatter<'a> {
+    options: &'a FormatOptions,
+    /// Source positions of every word token, in order.
+    words: Vec<(usize, usize)>,
+    comments: Vec<Comment>,
+    next_word: usize,
+    next_comment: usize,
+    out: String,
+    line: String,
+    line_indent: usize,
+    trailing: Vec<String>,
+    indent: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &str, options: &'a FormatOptions) -> Self {
+        let mut lexer = Lexer::new(source);
+        let mut words = Vec::new();
+        while lexer.current_token != TokenType::Eof {
+            if is_word(&lexer.current_token) {
+                words.push((lexer.token_line, lexer.token_col));
+            }
+            lexer.next_token();
+        }
+        Self {
+            options,
+            words,
+            comments: lexer.comments,
+            next_word: 0,
+            next_comment: 0,
+            out: String::new(),
+            line: String::new(),
+            line_indent: 0,
+            trailing: Vec::new(),
+            indent: 0,
+        }
+    }
+
+    fn pad(&self, level: usize) -> String {
+        " ".repeat(level * self.options.indent_width)
+    }
+
+    fn pending_comment_before(&self, pos: Option<(usize, usize)>) -> Option<&Comment> {
+        let c = self.comments.get(self.next_comment)?;
+        match pos {
+            Some(pos) if (c.line, c.col) > pos => None,
+            _ => Some(c),
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        if self.line.is_empty() {
+            self.line_indent = self.indent;
+        }
+        self.line.push_str(text);
+    }
+
+    /// Emits a word token, first placing any comments that came before it in the source.
+    fn word(&mut self, text: &str) {
+        let pos = self.words.get(self.next_word).copied();
+        while let Some(c) = self.pending_comment_before(pos) {
+            let text = c.text.clone();
+            if self.line.is_empty() {
+                let pad = self.pad(self.indent);
+                self.out.push_str(&format!("{}{}\n", pad, text));
+            } else {
+                self.trailing.push(text);
+            }
+            self.next_comment += 1;
+        }
+        self.next_word += 1;
+        self.push(text);
+    }
+
+    fn keyword(&mut self, kw: &str) {
+        if self.options.uppercase_keywords {
+            self.word(&kw.to_ascii_uppercase());
+        } else {
+            self.word(kw);
+        }
+    }
+
+    /// Ends the current output line, taking along comments that trailed the last word.
+    fn newline(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+        let last_line = self.next_word.checked_sub(1).map(|i| self.words[i].0);
+        let next = self.words.get(self.next_word).copied();
+        while let Some(c) = self.pending_comment_before(next) {
+            if c.own_line || Some(c.line) != last_line {
+                break;
+            }
+            self.trailing.push(c.text.clone());
+            self.next_comment += 1;
+        }
+        let pad = self.pad(self.line_indent);
+        self.out.push_str(&pad);
+        self.out.push_str(&std::mem::take(&mut self.line));
+        for comment in std::mem::take(&mut self.trailing) {
+            self.out.push(' ');
+            self.out.push_str(&comment);
+        }
+        self.out.push('\n');
+    }
+
+    fn blank_line(&mut self) {
+        self.newline();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.newline();
+        for c in &self.comments[self.next_comment..] {
+            self.out.push_str(&c.text);
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    fn program(&mut self, program: &Program) {
+        self.keyword("program");
+        self.push(" ");
+        self.word(&program.name);
+        self.push(";");
+        self.newline();
+        self.block(&program.block);
+        self.push(".");
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.decls(block);
+        if !block.procedures.is_empty() {
+            self.blank_line();
+        }
+        self.statement(&block.statement);
+    }
+
+    fn decls(&mut self, block: &Block) {
+        if !block.consts.is_empty() {
+            self.decl_list("const", &block.consts, |f, c| {
+                f.word(&c.name);
+                f.push(" = ");
+                f.word(&c.value.to_string());
+            });
+        }
+        if !block.vars.is_empty() {
+            self.decl_list("var", &block.vars, |f, name| f.word(name));
+        }
+        for proc_decl in &block.procedures {
+            self.blank_line();
+            self.keyword("procedure");
+            self.push(" ");
+            self.word(&proc_decl.name);
+            if !proc_decl.params.is_empty() {
+                self.push("(");
+                for (i, param) in proc_decl.params.iter().enumerate() {
+                    if i > 0 {
+                        self.push(", ");
+                    }
+                    self.word(param);
+                }
+                self.push(")");
+            }
+            self.push(";");
+            self.newline();
+            // Declarations sit under the header; begin/end lines up with it
+            self.indent += 1;
+            self.decls(&proc_decl.block);
+            self.indent -= 1;
+            if !proc_decl.block.procedures.is_empty() {
+                self.blank_line();
+            }
+            self.statement(&proc_decl.block.statement);
+            self.push(";");
+            self.newline();
+        }
+    }
+
+    fn decl_list<T>(&mut self, kw: &str, items: &[T], mut item: impl FnMut(&mut Self, &T)) {
+        self.keyword(kw);
+        if self.options.packed_decls {
+            self.push(" ");
+        } else {
+            self.newline();
+            self.indent += 1;
+        }
+        for (i, it) in items.iter().enumerate() {
+            if i > 0 {
+                if self.options.packed_decls {
+                    self.push(", ");
+                } else {
+                    self.push(",");
+                    self.newline();
+                }
+            }
+            item(self, it);
+        }
+        self.push(";");
+        self.newline();
+        if !self.options.packed_decls {
+            self.indent -= 1;
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::Assignment { name, expr, .. } => {
+                self.word(name);
+                self.push(" := ");
+                self.expr(expr, true);
+            }
+            Statement::Call { name, args, .. } => {
+                self.keyword("call");
+                self.push(" ");
+                self.word(name);
+                if !args.is_empty() {
+                    self.expr_list(args);
+                }
+            }
+            Statement::BeginEnd { statements } => {
+                self.keyword("begin");
+                self.newline();
+                self.indent += 1;
+                for (i, s) in statements.iter().enumerate() {
+                    self.statement(s);
+                    if i + 1 < statements.len() {
+                        self.push(";");
+                    }
+                    self.newline();
+                }
+                self.indent -= 1;
+                self.keyword("end");
+            }
+            Statement::If {
+                condition,
+                then_stmt,
+                else_stmt,
+                ..
+            } => {
+                self.keyword("if");
+                self.push(" ");
+                self.condition(condition);
+                self.push(" ");
+                self.keyword("then");
+                self.branch(then_stmt);
+                if let Some(else_stmt) = else_stmt {
+                    if matches!(**then_stmt, Statement::BeginEnd { .. }) {
+                        self.push(" ");
+                    } else {
+                        self.newline();
+                    }
+                    self.keyword("else");
+                    if matches!(**else_stmt, Statement::If { .. }) {
+                        self.push(" ");
+                        self.statement(else_stmt);
+                    } else {
+                        self.branch(else_stmt);
+                    }
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                self.keyword("while");
+                self.push(" ");
+                self.condition(condition);
+                self.push(" ");
+                self.keyword("do");
+                self.branch(body);
+            }
+            Statement::Read { names, .. } => {
+                self.keyword("read");
+                self.push("(");
+                for (i, name) in names.iter().enumerate() {
+                    if i > 0 {
+                        self.push(", ");
+                    }
+                    self.word(name);
+                }
+                self.push(")");
+            }
+            Statement::Write { exprs, .. } => {
+                self.keyword("write");
+                self.expr_list(exprs);
+            }
+            Statement::Empty => {}
+        }
+    }
+
+    /// Body of `then`/`else`/`do`: `begin` stays on the same line, anything else is indented below.
+    fn branch(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::BeginEnd { .. } => {
+                self.push(" ");
+                self.statement(stmt);
+            }
+            Statement::Empty => {}
+            _ => {
+                self.newline();
+                self.indent += 1;
+                self.statement(stmt);
+                self.indent -= 1;
+            }
+        }
+    }
+
+    fn expr_list(&mut self, exprs: &[Expr]) {
+        self.push("(");
+        for (i, e) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.push(", ");
+            }
+            self.expr(e, true);
+        }
+        self.push(")");
+    }
+
+    fn condition(&mut self, cond: &Condition) {
+        match cond {
+            Condition::Odd { expr } => {
+                self.keyword("odd");
+                self.push(" ");
+                self.expr(expr, true);
+            }
+            Condition::Compare { left, op, right } => {
+                self.expr(left, true);
+                self.push(match op {
+                    Operator::EQL => " = ",
+                    Operator::NEQ => " # ",
+                    Operator::LSS => " < ",
+                    Operator::LEQ => " <= ",
+                    Operator::GTR => " > ",
+                    _ => " >= ",
+                });
+                self.expr(right, true);
+            }
+        }
+    }
+
+    /// `leading` is true where the grammar allows a sign, i.e. at the start of an expression.
+    fn expr(&mut self, expr: &Expr, leading: bool) {
+        match expr {
+            Expr::Number(n) if *n < 0 && !leading => {
+                self.push("(-");
+                self.word(&n.unsigned_abs().to_string());
+                self.push(")");
+            }
+            Expr::Number(n) => {
+                if *n < 0 {
+                    self.push("-");
+                }
+                self.word(&n.unsigned_abs().to_string());
+            }
+            Expr::Identifier(name) => self.word(name),
+            Expr::Unary { expr, .. } => {
+                if !leading {
+                    self.push("(");
+                }
+                self.push("-");
+                self.operand(expr, precedence(expr) < 2);
+                if !leading {
+                    self.push(")");
+                }
+            }
+            Expr::Binary { left, op, right } => {
+                let prec = op_precedence(op);
+                if prec == 1 {
+                    self.expr(left, leading);
+                } else {
+                    self.operand(left, precedence(left) < prec);
+                }
+                self.push(match op {
+                    Operator::ADD => " + ",
+                    Operator::SUB => " - ",
+                    Operator::MUL => " * ",
+                    _ => " / ",
+                });
+                // Operators are left-associative, so an equal-precedence right side needs parens
+                self.operand(right, precedence(right) <= prec);
+            }
+        }
+    }
+
+    fn operand(&mut self, expr: &Expr, parens: bool) {
+        if parens {
+            self.push("(");
+            self.expr(expr, true);
+            self.push(")");
+        } else {
+            self.expr(expr, false);
+        }
+    }
+}
+
+fn is_word(token: &TokenType) -> bool {
+    !matches!(
+        token,
+        TokenType::Plus
+            | TokenType::Minus
+            | TokenType::Multiply
+            | TokenType::Divide
+            | TokenType::Equals
+            | TokenType::Hash
+            | TokenType::LessThan
+            | TokenType::LessEqual
+            | TokenType::GreaterThan
+            | TokenType::GreaterEqual
+            | TokenType::Assignment
+            | TokenType::Comma
+            | TokenType::Semicolon
+            | TokenType::Period
+            | TokenType::LParen
+            | TokenType::RParen
+            | TokenType::Unknown
+            | TokenType::Eof
+    )
+}
+
+fn op_precedence(op: &Operator) -> u8 {
+    match op {
+        Operator::MUL | Operator::DIV => 2,
+        _ => 1,
+    }
+}
+
+/// Binding strength of an expression as an operand; atoms never need parentheses.
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Binary { op, .. } => op_precedence(op),
+        Expr::Unary { .. } => 0,
+        Expr::Number(n) if *n < 0 => 0,
+        _ => 3,
+    }
+}
+
+/// Parses `source` and prints it in canonical layout.
+pub fn format_source(source: &str, options: &FormatOptions) -> Result<String, CompileError> {
+    let program = compiler::parse(source, false)?;
+    let mut formatter = Formatter::new(source, options);
+    formatter.program(&program);
+    Ok(formatter.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn fmt(source: &str) -> String {
+        format_source(source, &FormatOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_layout() {
+        let source = "PROGRAM t; CONST n:=3; VAR x,y;
+PROCEDURE p(a); BEGIN IF a>n THEN WRITE(a) ELSE BEGIN x:=-(a+1)*2; y:=x-(a-1) END END;
+BEGIN READ x; WHILE x#0 DO CALL p(x) END.";
+        assert_eq!(
+            fmt(source),
+            "program t;
+const n = 3;
+var x, y;
+
+procedure p(a);
+begin
+  if a > n then
+    write(a)
+  else begin
+    x := -(a + 1) * 2;
+    y := x - (a - 1)
+  end
+end;
+
+begin
+  read(x);
+  while x # 0 do
+    call p(x)
+end.
+"
+        );
+    }
+
+    #[test]
+    fn test_comments_are_kept() {
+        let source = "{ header }
+program t;
+var x; (* the only var *)
+begin
+  { read it }
+  read(x);
+  x := x { trailing } + 1
+end.
+{ eof }";
+        let out = fmt(source);
+        assert_eq!(
+            out,
+            "{ header }
+program t;
+var x; (* the only var *)
+begin
+  { read it }
+  read(x);
+  x := x + 1 { trailing }
+end.
+{ eof }
+"
+        );
+    }
+
+    #[test]
+    fn test_idempotent_on_samples() {
+        let options = [
+            FormatOptions::default(),
+            FormatOptions {
+                uppercase_keywords: true,
+                packed_decls: false,
+                indent_width: 4,
+            },
+        ];
+        for dir in ["testcase", "samples"] {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if !path.is_file() {
+                    continue;
+                }
+                let source = fs::read_to_string(&path).unwrap();
+                let Ok(original) = compiler::parse(&source, false) else {
+                    continue;
+                };
+                for opts in &options {
+                    let once = format_source(&source, opts).unwrap();
+                    let twice = format_source(&once, opts).unwrap();
+                    assert_eq!(once, twice, "{}", path.display());
+                    let reparsed = compiler::parse(&once, false).unwrap();
+                    assert_eq!(
+                        format!("{:?}", strip_lines(reparsed)),
+                        format!("{:?}", strip_lines(original.clone())),
+                        "{}",
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+
+    /// The formatter moves code between lines, so compare ASTs without positions.
+    fn strip_lines(program: Program) -> String {
+        let json = serde_json::to_string(&program).unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        fn strip(v: &mut serde_json::Value) {
+            match v {
+                serde_json::Value::Object(map) => {
+                    map.remove("line");
+                    map.values_mut().for_each(strip);
+                }
+                serde_json::Value::Array(items) => items.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+        strip(&mut value);
+        value.to_string()
+    }
+}
@@ -1,12 +1,15 @@
 use crate::ast::{Block as AstBlock, Program, Statement};
+use crate::cfg::{self, Cfg, Terminator};
 use crate::codegen::CodeGenerator;
+use crate::debug_info::DebugInfo;
 use crate::lexer::Lexer;
-use crate::optimizer::optimize_ast;
+use crate::optimizer::{OptimizeOptions, optimize_ast_with};
 use crate::parser::Parser;
+use crate::peephole;
 use crate::semantic::SemanticAnalyzer;
 use crate::symbol_table::SymbolTable;
 use crate::types::Instruction;
-use crate::vm::{VM, VMState};
+use crate::vm::{Arithmetic, Overflow, VM, VMState};
 use eframe::egui;
 use std::time::{Duration, Instant};
 
@@ -16,6 +19,7 @@ enum Tab {
     Tokens,
     Ast,
     Symbols,
+    Cfg,
     Optimization,
     Runtime,
 }
@@ -96,8 +100,11 @@ pub struct Pl0Gui {
     tokens: Vec<(usize, usize, crate::types::TokenType)>,
     ast: Option<Program>,
     symbol_table: Option<SymbolTable>,
+    cfgs: Vec<Cfg>,
     raw_code: Vec<Instruction>,
     opt_code: Vec<Instruction>,
+    raw_debug: DebugInfo,
+    opt_debug: DebugInfo,
     vm: VM,
 
     // UI State
@@ -107,6 +114,7 @@ pub struct Pl0Gui {
     last_tick: Instant,
     input_buffer: String,
     use_optimized_vm: bool,
+    arithmetic: Arithmetic,
 
     // Visualization
     viz_root: Option<VizNode>,
@@ -156,8 +164,11 @@ end";
             tokens: Vec::new(),
             ast: None,
             symbol_table: None,
+            cfgs: Vec::new(),
             raw_code: vec![],
             opt_code: vec![],
+            raw_debug: DebugInfo::default(),
+            opt_debug: DebugInfo::default(),
             vm: VM::new(vec![]),
             current_tab: Tab::Editor,
             status_message: "Ready".to_string(),
@@ -165,6 +176,7 @@ end";
             last_tick: Instant::now(),
             input_buffer: String::new(),
             use_optimized_vm: true,
+            arithmetic: Arithmetic::default(),
             viz_root: None,
             diagnostics: Vec::new(),
         };
@@ -190,11 +202,21 @@ end";
         msg
     }
 
+    fn fresh_vm(&self) -> VM {
+        let vm = if self.use_optimized_vm {
+            VM::new(self.opt_code.clone()).with_debug_info(Some(self.opt_debug.clone()))
+        } else {
+            VM::new(self.raw_code.clone()).with_debug_info(Some(self.raw_debug.clone()))
+        };
+        vm.with_arithmetic(self.arithmetic)
+    }
+
     fn compile(&mut self) {
         self.status_message = "Compiling...".to_string();
         self.diagnostics.clear();
         self.tokens.clear();
         self.symbol_table = None;
+        self.cfgs.clear();
 
         // 0. Lexical Analysis (Visualization)
         let mut lexer_viz = Lexer::new(&self.source_code);
@@ -239,6 +261,7 @@ end";
 
                 self.symbol_table = Some(sym_table.clone()); // Save for visualization
                 self.ast = Some(raw_program.clone());
+                self.cfgs = cfg::build_program(&raw_program);
 
                 // Build Visualization Tree
                 let mut root = build_viz_tree(&raw_program);
@@ -247,9 +270,14 @@ end";
 
                 let mut generator = CodeGenerator::new();
                 self.raw_code = generator.generate(&raw_program, &mut sym_table);
+                self.raw_debug = generator.debug_info().clone();
 
                 // 2. Optimize AST & Generate Optimized Code
-                optimize_ast(&mut program);
+                let options = OptimizeOptions {
+                    arithmetic: self.arithmetic,
+                    ..Default::default()
+                };
+                let stats = optimize_ast_with(&mut program, &options);
                 let mut opt_sym_table = SymbolTable::new();
                 let mut opt_analyzer = SemanticAnalyzer::new(&mut opt_sym_table);
 
@@ -262,15 +290,14 @@ end";
                 let mut opt_generator = CodeGenerator::new();
                 let code_from_ast = opt_generator.generate(&program, &mut opt_sym_table);
 
-                // 3. Peephole Optimization (Removed)
-                self.opt_code = code_from_ast;
-                let code_to_run = if self.use_optimized_vm {
-                    self.opt_code.clone()
-                } else {
-                    self.raw_code.clone()
-                };
-                self.vm = VM::new(code_to_run);
-                self.status_message = "Compilation Successful".to_string();
+                // 3. Peephole Optimization
+                self.opt_debug = opt_generator.debug_info().clone();
+                self.opt_code = peephole::optimize(code_from_ast, &mut self.opt_debug);
+                self.vm = self.fresh_vm();
+                self.status_message = format!(
+                    "Compilation Successful (optimizer inlined {} calls, unrolled {} loops, removed {} dead stores, {} variable slots)",
+                    stats.inlined_calls, stats.unrolled_loops, stats.dead_stores, stats.removed_slots
+                );
             }
             Err(_) => {
                 // This branch might be unreachable now if we handle errors in Ok, 
@@ -300,6 +327,7 @@ impl eframe::App for Pl0Gui {
                 ui.selectable_value(&mut self.current_tab, Tab::Tokens, "🔤 Tokens");
                 ui.selectable_value(&mut self.current_tab, Tab::Ast, "🌳 AST");
                 ui.selectable_value(&mut self.current_tab, Tab::Symbols, "📚 Symbols");
+                ui.selectable_value(&mut self.current_tab, Tab::Cfg, "🔀 CFG");
                 ui.selectable_value(&mut self.current_tab, Tab::Optimization, "⚡ Optimization");
                 ui.selectable_value(&mut self.current_tab, Tab::Runtime, "🚀 Runtime");
 
@@ -319,6 +347,7 @@ impl eframe::App for Pl0Gui {
             Tab::Tokens => self.show_tokens(ui),
             Tab::Ast => self.show_ast(ui),
             Tab::Symbols => self.show_symbols(ui),
+            Tab::Cfg => self.show_cfg(ui),
             Tab::Optimization => self.show_optimization(ui),
             Tab::Runtime => self.show_runtime(ui, ctx),
         });
@@ -497,6 +526,58 @@ impl Pl0Gui {
 
 
 
+    fn show_cfg(&self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Control-Flow Graphs");
+            if !self.cfgs.is_empty() && ui.button("📋 Copy DOT").clicked() {
+                ui.ctx().copy_text(cfg::to_dot(&self.cfgs));
+            }
+        });
+        if self.cfgs.is_empty() {
+            ui.label("No CFG available. Fix compilation errors.");
+            return;
+        }
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (index, graph) in self.cfgs.iter().enumerate() {
+                egui::CollapsingHeader::new(format!("Procedure {}", graph.name))
+                    .id_salt(format!("cfg_{}", index))
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        for id in graph.reverse_postorder() {
+                            let block = &graph.blocks[id];
+                            ui.group(|ui| {
+                                let title = if id == graph.entry {
+                                    format!("B{} (entry)", id)
+                                } else if id == graph.exit {
+                                    format!("B{} (exit)", id)
+                                } else {
+                                    format!("B{}", id)
+                                };
+                                ui.label(egui::RichText::new(title).strong());
+                                for node in &block.nodes {
+                                    ui.monospace(node.to_string());
+                                }
+                                let edges = match &block.terminator {
+                                    Terminator::Goto(to) => format!("→ B{}", to),
+                                    Terminator::Branch {
+                                        condition,
+                                        then_block,
+                                        else_block,
+                                        ..
+                                    } => format!(
+                                        "if {} → B{} else → B{}",
+                                        condition, then_block, else_block
+                                    ),
+                                    Terminator::Return => "return".to_string(),
+                                };
+                                ui.colored_label(egui::Color32::LIGHT_BLUE, edges);
+                            });
+                        }
+                    });
+            }
+        });
+    }
+
     fn show_optimization(&self, ui: &mut egui::Ui) {
         let diffs = compute_diff(&self.raw_code, &self.opt_code);
 
@@ -569,12 +650,7 @@ impl Pl0Gui {
                 self.auto_run = !self.auto_run;
             }
             if ui.button("Reset").clicked() {
-                let code_to_run = if self.use_optimized_vm {
-                    self.opt_code.clone()
-                } else {
-                    self.raw_code.clone()
-                };
-                self.vm = VM::new(code_to_run);
+                self.vm = self.fresh_vm();
                 self.auto_run = false;
             }
 
@@ -583,12 +659,14 @@ impl Pl0Gui {
                 .checkbox(&mut self.use_optimized_vm, "Use Optimized Code")
                 .changed()
             {
-                let code_to_run = if self.use_optimized_vm {
-                    self.opt_code.clone()
-                } else {
-                    self.raw_code.clone()
-                };
-                self.vm = VM::new(code_to_run);
+                self.vm = self.fresh_vm();
+                self.auto_run = false;
+            }
+
+            ui.separator();
+            // The optimized code depends on the arithmetic, so build it again
+            if arithmetic_controls(ui, &mut self.arithmetic) {
+                self.compile();
                 self.auto_run = false;
             }
         });
@@ -601,6 +679,11 @@ impl Pl0Gui {
             ui.label(format!("SP: {:<3}", self.vm.t));
             ui.label(format!("IR: {:?}", self.vm.i));
 
+            if let Some(loc) = self.vm.location(self.vm.p) {
+                ui.separator();
+                ui.label(format!("Source: {}", loc));
+            }
+
             ui.separator();
             ui.label(format!("State: {:?}", self.vm.state));
 
@@ -798,7 +881,7 @@ impl Pl0Gui {
                                     && ui.input(|i| i.key_pressed(egui::Key::Enter))))
                                 && !self.input_buffer.is_empty()
                                     && let Ok(val) = self.input_buffer.trim().parse::<i64>() {
-                                        self.vm.input_queue.push(val);
+                                        self.vm.input.push_back(val);
                                         // Echo input to output
                                         self.vm.output.push(format!("> {}", val));
 
@@ -833,6 +916,26 @@ impl Pl0Gui {
     }
 }
 
+/// Overflow mode and word size pickers; true if either changed, so the VM needs a restart.
+pub fn arithmetic_controls(ui: &mut egui::Ui, arithmetic: &mut Arithmetic) -> bool {
+    let before = *arithmetic;
+    egui::ComboBox::from_label("On Overflow")
+        .selected_text(arithmetic.overflow.name())
+        .show_ui(ui, |ui| {
+            for mode in Overflow::ALL {
+                ui.selectable_value(&mut arithmetic.overflow, mode, mode.name());
+            }
+        });
+    egui::ComboBox::from_label("Word Size")
+        .selected_text(format!("{} bits", arithmetic.word_bits))
+        .show_ui(ui, |ui| {
+            for bits in Arithmetic::WORD_SIZES {
+                ui.selectable_value(&mut arithmetic.word_bits, bits, format!("{} bits", bits));
+            }
+        });
+    *arithmetic != before
+}
+
 fn build_viz_tree(program: &Program) -> VizNode {
     let mut root = VizNode::new("Program", egui::Color32::from_rgb(200, 200, 255));
     root.children.push(build_block_node(&program.block));
@@ -2,6 +2,29 @@ use crate::types::TokenType;
 use std::iter::Peekable;
 use std::str::Chars;
 
+/// A `{ ... }` or `(* ... *)` comment, kept so tools like the formatter can put it back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub line: usize,
+    pub col: usize,
+    /// The full comment including its delimiters.
+    pub text: String,
+    /// Nothing but whitespace precedes the comment on its line.
+    pub own_line: bool,
+}
+
+/// Whether `c` may follow the first letter of an identifier.
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Whether `text` scans as a single identifier (or keyword): a letter, then
+/// letters, digits and underscores.
+pub fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic()) && chars.all(is_identifier_char)
+}
+
 pub struct Lexer<'a> {
     input: Peekable<Chars<'a>>,
     pub current_token: TokenType,
@@ -9,6 +32,8 @@ pub struct Lexer<'a> {
     pub col: usize,
     pub token_line: usize,
     pub token_col: usize,
+    pub comments: Vec<Comment>,
+    at_line_start: bool,
 }
 
 impl<'a> Lexer<'a> {
@@ -20,6 +45,8 @@ impl<'a> Lexer<'a> {
             col: 1,
             token_line: 1,
             token_col: 1,
+            comments: Vec::new(),
+            at_line_start: true,
         };
         lexer.next_token(); // Prime the first token
         lexer
@@ -30,6 +57,7 @@ impl<'a> Lexer<'a> {
         if c == '\n' {
             self.line += 1;
             self.col = 1;
+            self.at_line_start = true;
         } else {
             self.col += 1;
         }
@@ -37,10 +65,15 @@ impl<'a> Lexer<'a> {
     }
 
     pub fn next_token(&mut self) {
-        self.skip_whitespace();
+        if !self.skip_trivia() {
+            // Unterminated comment; scan_comment left the position at its start
+            self.current_token = TokenType::Unknown;
+            return;
+        }
 
         self.token_line = self.line;
         self.token_col = self.col;
+        self.at_line_start = false;
 
         if let Some(&c) = self.input.peek() {
             match c {
@@ -130,20 +163,65 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn skip_whitespace(&mut self) {
+    /// Skips whitespace and comments; returns false if input ends inside a comment.
+    fn skip_trivia(&mut self) -> bool {
         while let Some(&c) = self.input.peek() {
             if c.is_whitespace() {
                 self.read_char();
+            } else if c == '{' {
+                if !self.scan_comment("}") {
+                    return false;
+                }
+            } else if c == '(' && self.input.clone().nth(1) == Some('*') {
+                if !self.scan_comment("*)") {
+                    return false;
+                }
             } else {
                 break;
             }
         }
+        true
+    }
+
+    fn scan_comment(&mut self, close: &str) -> bool {
+        let line = self.line;
+        let col = self.col;
+        let own_line = self.at_line_start;
+        let mut text = String::new();
+        // The opening delimiter is one or two characters, and never part of the close
+        let open_len = if close == "}" { 1 } else { 2 };
+        for _ in 0..open_len {
+            text.push(self.read_char().unwrap());
+        }
+        loop {
+            match self.read_char() {
+                Some(c) => {
+                    text.push(c);
+                    if text.len() >= open_len + close.len() && text.ends_with(close) {
+                        break;
+                    }
+                }
+                None => {
+                    self.token_line = line;
+                    self.token_col = col;
+                    return false;
+                }
+            }
+        }
+        self.comments.push(Comment {
+            line,
+            col,
+            text,
+            own_line,
+        });
+        self.at_line_start = false;
+        true
     }
 
     fn scan_identifier_or_keyword(&mut self) {
         let mut ident = String::new();
         while let Some(&c) = self.input.peek() {
-            if c.is_alphanumeric() || c == '_' {
+            if is_identifier_char(c) {
                 ident.push(c);
                 self.read_char();
             } else {
@@ -151,7 +229,7 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        self.current_token = match ident.as_str() {
+        self.current_token = match ident.to_ascii_lowercase().as_str() {
             "program" => TokenType::Program,
             "const" => TokenType::Const,
             "var" => TokenType::Var,
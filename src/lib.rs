@@ -1,10 +1,21 @@
 pub mod ast;
+pub mod cfg;
 pub mod codegen;
+pub mod compiler;
+pub mod debug_info;
+pub mod debugger;
+pub mod exit;
+pub mod formatter;
 pub mod gui;
 pub mod lexer;
+pub mod listing;
+pub mod module;
 pub mod optimizer;
 pub mod parser;
+pub mod peephole;
 pub mod semantic;
+pub mod sexpr;
 pub mod symbol_table;
 pub mod types;
+pub mod verifier;
 pub mod vm;
@@ -0,0 +1,124 @@
+//! Wirth-style annotated listings: every source line followed by the
+//! P-code generated for it.
+
+use crate::debug_info::DebugInfo;
+use crate::types::{Instruction, OpCode, Operator};
+use std::collections::{BTreeMap, HashSet};
+
+/// Name of the variable an `LOD`/`STO` at `addr` refers to, following `l` static links.
+fn variable_name(debug: &DebugInfo, addr: usize, instr: &Instruction) -> Option<String> {
+    let mut proc_idx = debug.procedure_at(addr)?;
+    for _ in 0..instr.l {
+        proc_idx = debug.procedures[proc_idx].parent?;
+    }
+    let proc_info = &debug.procedures[proc_idx];
+    proc_info
+        .vars
+        .iter()
+        .find(|v| v.addr == instr.a)
+        .map(|v| v.name.clone())
+}
+
+fn describe(debug: &DebugInfo, addr: usize, instr: &Instruction) -> Option<String> {
+    match instr.f {
+        OpCode::JMP | OpCode::JPC => Some(format!("-> L{}", instr.a)),
+        OpCode::CAL => {
+            let target = instr.a as usize;
+            match debug.procedures.iter().find(|p| p.entry == target) {
+                Some(p) => Some(format!("call {}", p.name)),
+                None => Some(format!("call L{}", target)),
+            }
+        }
+        OpCode::LOD | OpCode::STO | OpCode::RED => variable_name(debug, addr, instr),
+        OpCode::OPR => Operator::from_i64(instr.a).map(|op| format!("{:?}", op)),
+        OpCode::INT if debug.procedures.iter().any(|p| p.body == addr) => {
+            Some("allocate frame".to_string())
+        }
+        _ => None,
+    }
+}
+
+fn procedure_header(debug: &DebugInfo, idx: usize) -> Vec<String> {
+    let p = &debug.procedures[idx];
+    let mut lines = vec![format!(
+        "; procedure {} (level {}, entry {}, body {}, exit {})",
+        p.name, p.level, p.entry, p.body, p.exit
+    )];
+    if !p.vars.is_empty() {
+        let layout: Vec<String> = p
+            .vars
+            .iter()
+            .map(|v| format!("{}@{}", v.name, v.addr))
+            .collect();
+        lines.push(format!("; frame: SL@0 DL@1 RA@2 {}", layout.join(" ")));
+    }
+    lines
+}
+
+/// Renders `source` interleaved with `code`, using the line table in `debug`.
+pub fn render(source: &str, code: &[Instruction], debug: &DebugInfo) -> String {
+    let targets: HashSet<usize> = code
+        .iter()
+        .filter(|i| matches!(i.f, OpCode::JMP | OpCode::JPC))
+        .map(|i| i.a as usize)
+        .collect();
+
+    // Group instruction addresses by the source line that produced them
+    let mut by_line: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    let mut unmapped = Vec::new();
+    for addr in 0..code.len() {
+        match debug.line_for(addr) {
+            Some(line) => by_line.entry(line).or_default().push(addr),
+            None => unmapped.push(addr),
+        }
+    }
+
+    let mut out = String::new();
+    let emit_instr = |out: &mut String, addr: usize| {
+        for (idx, p) in debug.procedures.iter().enumerate() {
+            if p.entry == addr {
+                for header in procedure_header(debug, idx) {
+                    out.push_str(&format!("{:>14}{}\n", "", header));
+                }
+            }
+        }
+        let instr = &code[addr];
+        let label = if targets.contains(&addr) {
+            format!("L{}:", addr)
+        } else {
+            String::new()
+        };
+        let text = format!("{:?} {} {}", instr.f, instr.l, instr.a);
+        match describe(debug, addr, instr) {
+            Some(comment) => out.push_str(&format!(
+                "{:>6} {:<6} {:<14} ; {}\n",
+                addr, label, text, comment
+            )),
+            None => out.push_str(&format!("{:>6} {:<6} {}\n", addr, label, text)),
+        }
+    };
+
+    for addr in unmapped {
+        emit_instr(&mut out, addr);
+    }
+
+    let source_lines: Vec<&str> = source.lines().collect();
+    for (i, text) in source_lines.iter().enumerate() {
+        let line = i + 1;
+        out.push_str(&format!("{:>5}  {}\n", line, text));
+        if let Some(addrs) = by_line.get(&line) {
+            for &addr in addrs {
+                emit_instr(&mut out, addr);
+            }
+        }
+    }
+
+    // Lines past the end of the source (should not happen, but never drop code)
+    for (_, addrs) in by_line.range(source_lines.len() + 1..) {
+        for &addr in addrs {
+            emit_instr(&mut out, addr);
+        }
+    }
+
+    out
+}
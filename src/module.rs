@@ -0,0 +1,365 @@
+//! On-disk format for compiled programs.
+//!
+//! A module file starts with the 4-byte magic `PL0M` and a little-endian `u16`
+//! format version, followed by a list of sections. Each section is a one-byte
+//! tag, a little-endian `u32` payload length and a bincode payload:
+//!
+//! | tag | section    | payload            | required |
+//! |-----|------------|--------------------|----------|
+//! | 1   | code       | `Vec<Instruction>` | yes      |
+//! | 2   | constants  | `Vec<i64>`         | no       |
+//! | 3   | strings    | `Vec<String>`      | no       |
+//! | 4   | debug      | `DebugInfo`        | no       |
+//! | 5   | arithmetic | `Arithmetic`       | no       |
+//!
+//! The arithmetic section records the word size and overflow mode the code
+//! was optimized for; the VM should run it with the same.
+//!
+//! Files that do not start with the magic are treated as the legacy text
+//! format (`LIT 0 5`, one instruction per line) by [`load`].
+
+use crate::debug_info::DebugInfo;
+use crate::types::{Instruction, OpCode};
+use crate::vm::Arithmetic;
+use bincode::Options;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::path::Path;
+
+pub const MAGIC: [u8; 4] = *b"PL0M";
+pub const FORMAT_VERSION: u16 = 2;
+
+const SECTION_CODE: u8 = 1;
+const SECTION_CONSTANTS: u8 = 2;
+const SECTION_STRINGS: u8 = 3;
+const SECTION_DEBUG: u8 = 4;
+const SECTION_ARITHMETIC: u8 = 5;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Module {
+    pub code: Vec<Instruction>,
+    pub constants: Option<Vec<i64>>,
+    pub strings: Option<Vec<String>>,
+    pub debug: Option<DebugInfo>,
+    pub arithmetic: Option<Arithmetic>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleError {
+    Io(String),
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    MissingCode,
+    DuplicateSection(u8),
+    UnknownSection(u8),
+    Corrupt(String),
+    /// A line of a text assembly file could not be parsed.
+    Asm { line: usize, message: String },
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleError::Io(e) => write!(f, "I/O error: {}", e),
+            ModuleError::BadMagic => write!(f, "not a PL/0 module (bad magic number)"),
+            ModuleError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported module format version {} (this build reads version {})",
+                v, FORMAT_VERSION
+            ),
+            ModuleError::Truncated => write!(f, "module file is truncated"),
+            ModuleError::MissingCode => write!(f, "module has no code section"),
+            ModuleError::DuplicateSection(t) => write!(f, "duplicate section with tag {}", t),
+            ModuleError::UnknownSection(t) => write!(f, "unknown section with tag {}", t),
+            ModuleError::Corrupt(e) => write!(f, "corrupt module: {}", e),
+            ModuleError::Asm { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for ModuleError {}
+
+fn codec() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .reject_trailing_bytes()
+}
+
+fn write_section<T: Serialize>(out: &mut Vec<u8>, tag: u8, value: &T) {
+    let payload = codec()
+        .serialize(value)
+        .expect("in-memory serialization cannot fail");
+    out.push(tag);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&payload);
+}
+
+fn read_section<T: DeserializeOwned>(payload: &[u8]) -> Result<T, ModuleError> {
+    codec().deserialize(payload).map_err(|e| match *e {
+        bincode::ErrorKind::Io(ref io) if io.kind() == std::io::ErrorKind::UnexpectedEof => {
+            ModuleError::Truncated
+        }
+        other => ModuleError::Corrupt(other.to_string()),
+    })
+}
+
+impl Module {
+    pub fn new(code: Vec<Instruction>) -> Self {
+        Self {
+            code,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        write_section(&mut out, SECTION_CODE, &self.code);
+        if let Some(constants) = &self.constants {
+            write_section(&mut out, SECTION_CONSTANTS, constants);
+        }
+        if let Some(strings) = &self.strings {
+            write_section(&mut out, SECTION_STRINGS, strings);
+        }
+        if let Some(debug) = &self.debug {
+            write_section(&mut out, SECTION_DEBUG, debug);
+        }
+        if let Some(arithmetic) = &self.arithmetic {
+            write_section(&mut out, SECTION_ARITHMETIC, arithmetic);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ModuleError> {
+        if bytes.len() < MAGIC.len() {
+            return Err(if MAGIC.starts_with(bytes) {
+                ModuleError::Truncated
+            } else {
+                ModuleError::BadMagic
+            });
+        }
+        if bytes[..4] != MAGIC {
+            return Err(ModuleError::BadMagic);
+        }
+        if bytes.len() < 6 {
+            return Err(ModuleError::Truncated);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != FORMAT_VERSION {
+            return Err(ModuleError::UnsupportedVersion(version));
+        }
+
+        let mut code = None;
+        let mut module = Module::default();
+        let mut rest = &bytes[6..];
+
+        while !rest.is_empty() {
+            if rest.len() < 5 {
+                return Err(ModuleError::Truncated);
+            }
+            let tag = rest[0];
+            let len = u32::from_le_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+            rest = &rest[5..];
+            if rest.len() < len {
+                return Err(ModuleError::Truncated);
+            }
+            let (payload, tail) = rest.split_at(len);
+            rest = tail;
+
+            match tag {
+                SECTION_CODE if code.is_some() => return Err(ModuleError::DuplicateSection(tag)),
+                SECTION_CODE => code = Some(read_section(payload)?),
+                SECTION_CONSTANTS if module.constants.is_some() => {
+                    return Err(ModuleError::DuplicateSection(tag));
+                }
+                SECTION_CONSTANTS => module.constants = Some(read_section(payload)?),
+                SECTION_STRINGS if module.strings.is_some() => {
+                    return Err(ModuleError::DuplicateSection(tag));
+                }
+                SECTION_STRINGS => module.strings = Some(read_section(payload)?),
+                SECTION_DEBUG if module.debug.is_some() => {
+                    return Err(ModuleError::DuplicateSection(tag));
+                }
+                SECTION_DEBUG => module.debug = Some(read_section(payload)?),
+                SECTION_ARITHMETIC if module.arithmetic.is_some() => {
+                    return Err(ModuleError::DuplicateSection(tag));
+                }
+                SECTION_ARITHMETIC => {
+                    let arithmetic: Arithmetic = read_section(payload)?;
+                    if !Arithmetic::WORD_SIZES.contains(&arithmetic.word_bits) {
+                        return Err(ModuleError::Corrupt(format!(
+                            "unsupported word size {}",
+                            arithmetic.word_bits
+                        )));
+                    }
+                    module.arithmetic = Some(arithmetic);
+                }
+                _ => return Err(ModuleError::UnknownSection(tag)),
+            }
+        }
+
+        module.code = code.ok_or(ModuleError::MissingCode)?;
+        Ok(module)
+    }
+}
+
+fn parse_opcode(s: &str) -> Option<OpCode> {
+    match s {
+        "LIT" => Some(OpCode::LIT),
+        "OPR" => Some(OpCode::OPR),
+        "LOD" => Some(OpCode::LOD),
+        "STO" => Some(OpCode::STO),
+        "CAL" => Some(OpCode::CAL),
+        "INT" => Some(OpCode::INT),
+        "JMP" => Some(OpCode::JMP),
+        "JPC" => Some(OpCode::JPC),
+        "RED" => Some(OpCode::RED),
+        "WRT" => Some(OpCode::WRT),
+        _ => None,
+    }
+}
+
+/// Parses the legacy text format written by `pl0c emit --stage=asm`.
+pub fn parse_asm(text: &str) -> Result<Vec<Instruction>, ModuleError> {
+    let mut code = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() {
+            continue;
+        }
+        let err = |message: String| ModuleError::Asm {
+            line: i + 1,
+            message,
+        };
+        if parts.len() != 3 {
+            return Err(err("expected 'OP L A'".to_string()));
+        }
+        let f = parse_opcode(parts[0]).ok_or_else(|| err(format!("unknown opcode {}", parts[0])))?;
+        let l = parts[1]
+            .parse::<usize>()
+            .map_err(|_| err(format!("invalid level '{}'", parts[1])))?;
+        let a = parts[2]
+            .parse::<i64>()
+            .map_err(|_| err(format!("invalid address '{}'", parts[2])))?;
+        code.push(Instruction::new(f, l, a));
+    }
+    Ok(code)
+}
+
+/// Loads either a binary module or a text assembly file, deciding by the magic number.
+pub fn load(bytes: &[u8]) -> Result<Module, ModuleError> {
+    if bytes.starts_with(&MAGIC) {
+        return Module::from_bytes(bytes);
+    }
+    let text = std::str::from_utf8(bytes).map_err(|_| ModuleError::BadMagic)?;
+    Ok(Module::new(parse_asm(text)?))
+}
+
+pub fn load_file(path: impl AsRef<Path>) -> Result<Module, ModuleError> {
+    let bytes = std::fs::read(path).map_err(|e| ModuleError::Io(e.to_string()))?;
+    load(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug_info::{LineEntry, ProcInfo, VarInfo};
+    use crate::vm::Overflow;
+
+    fn sample() -> Module {
+        Module {
+            code: vec![
+                Instruction::new(OpCode::JMP, 0, 1),
+                Instruction::new(OpCode::INT, 0, 3),
+                Instruction::new(OpCode::LIT, 0, 42),
+                Instruction::new(OpCode::OPR, 0, 14),
+                Instruction::new(OpCode::OPR, 0, 0),
+            ],
+            constants: Some(vec![1, 2, 3]),
+            strings: None,
+            debug: Some(DebugInfo {
+                source_file: Some("a.pl0".to_string()),
+                lines: vec![LineEntry { addr: 0, line: 1 }, LineEntry { addr: 2, line: 3 }],
+                procedures: vec![ProcInfo {
+                    name: "main".to_string(),
+                    level: 0,
+                    parent: None,
+                    entry: 0,
+                    body: 1,
+                    exit: 4,
+                    vars: vec![VarInfo {
+                        name: "x".to_string(),
+                        addr: 3,
+                    }],
+                }],
+            }),
+            arithmetic: Some(Arithmetic {
+                overflow: Overflow::Wrap,
+                word_bits: 32,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let module = sample();
+        assert_eq!(Module::from_bytes(&module.to_bytes()), Ok(module));
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        let bytes = sample().to_bytes();
+        // Cutting inside a section is always detected; cutting between
+        // sections only drops the optional trailing ones.
+        for len in 0..bytes.len() {
+            if let Ok(m) = Module::from_bytes(&bytes[..len]) {
+                assert_eq!(m.code, sample().code, "prefix {}", len);
+                assert!(m.arithmetic.is_none(), "prefix {}", len);
+            }
+        }
+        assert_eq!(Module::from_bytes(&bytes[..6]), Err(ModuleError::MissingCode));
+        assert_eq!(Module::from_bytes(&bytes[..10]), Err(ModuleError::Truncated));
+        assert_eq!(Module::from_bytes(b"LIT 0 5\n"), Err(ModuleError::BadMagic));
+
+        let mut future = bytes.clone();
+        future[4] = 99;
+        assert_eq!(
+            Module::from_bytes(&future),
+            Err(ModuleError::UnsupportedVersion(99))
+        );
+
+        // The last 4 bytes are the arithmetic section's word size
+        let mut odd_word = bytes.clone();
+        let at = odd_word.len() - 4;
+        odd_word[at..].copy_from_slice(&48u32.to_le_bytes());
+        assert!(matches!(
+            Module::from_bytes(&odd_word),
+            Err(ModuleError::Corrupt(_))
+        ));
+
+        // Opcode discriminant out of range inside the code section
+        let mut corrupt = bytes.clone();
+        corrupt[6 + 5 + 8] = 200;
+        assert!(matches!(
+            Module::from_bytes(&corrupt),
+            Err(ModuleError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_text_asm() {
+        let module = load(b"LIT 0 5\nOPR 0 14\n\nOPR 0 0\n").unwrap();
+        assert_eq!(module.code.len(), 3);
+        assert_eq!(
+            load(b"LIT 0 5\nFOO 0 1\n"),
+            Err(ModuleError::Asm {
+                line: 2,
+                message: "unknown opcode FOO".to_string()
+            })
+        );
+    }
+}
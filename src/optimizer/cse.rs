@@ -0,0 +1,245 @@
+//! Global common subexpression elimination.
+//!
+//! After `x := e`, the variable `x` holds the value of `e` until `x` or one of
+//! the variables in `e` changes. An "available copies" analysis tracks these
+//! `(e, x)` pairs across the whole procedure, and any later evaluation of `e`
+//! at a point where the pair is available on every path is replaced by `x`.
+
+use super::dataflow::{self, Analysis, Direction, Effects, expr_vars};
+use super::modref;
+use crate::ast::{Block, Condition, Expr, Program, Statement};
+use crate::cfg::{self, Cfg, Node, StmtId, Terminator};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+type HeldExpr = (Expr, String);
+
+struct AvailableCopies {
+    effects: Effects,
+}
+
+fn uses_var(expr: &Expr, var: &str) -> bool {
+    let mut used = BTreeSet::new();
+    expr_vars(expr, &mut used);
+    used.contains(var)
+}
+
+fn copy_of(stmt: &Statement) -> Option<HeldExpr> {
+    match stmt {
+        Statement::Assignment { name, expr, .. }
+            if matches!(expr, Expr::Binary { .. } | Expr::Unary { .. })
+                && !uses_var(expr, name) =>
+        {
+            Some((expr.clone(), name.clone()))
+        }
+        _ => None,
+    }
+}
+
+impl Analysis for AvailableCopies {
+    type Fact = HashSet<HeldExpr>;
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, _cfg: &Cfg) -> Self::Fact {
+        HashSet::new()
+    }
+
+    fn top(&self, cfg: &Cfg) -> Self::Fact {
+        cfg.blocks
+            .iter()
+            .flat_map(|b| &b.nodes)
+            .filter_map(|n| copy_of(&n.stmt))
+            .collect()
+    }
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact {
+        a.intersection(b).cloned().collect()
+    }
+
+    fn transfer(&self, node: &Node, fact: &Self::Fact) -> Self::Fact {
+        let defs = self.effects.may_defs(&node.stmt);
+        let mut out: Self::Fact = fact
+            .iter()
+            .filter(|(e, holder)| !defs.contains(holder) && !defs.iter().any(|d| uses_var(e, d)))
+            .cloned()
+            .collect();
+        out.extend(copy_of(&node.stmt));
+        out
+    }
+}
+
+/// Replaces the largest available subexpressions of `expr`. Returns the number of replacements.
+fn rewrite_expr(expr: &mut Expr, available: &HashMap<Expr, String>) -> usize {
+    if let Some(holder) = available.get(expr) {
+        *expr = Expr::Identifier(holder.clone());
+        return 1;
+    }
+    match expr {
+        Expr::Binary { left, right, .. } => {
+            rewrite_expr(left, available) + rewrite_expr(right, available)
+        }
+        Expr::Unary { expr, .. } => rewrite_expr(expr, available),
+        _ => 0,
+    }
+}
+
+fn rewrite_condition(cond: &mut Condition, available: &HashMap<Expr, String>) -> usize {
+    match cond {
+        Condition::Odd { expr } => rewrite_expr(expr, available),
+        Condition::Compare { left, right, .. } => {
+            rewrite_expr(left, available) + rewrite_expr(right, available)
+        }
+    }
+}
+
+/// Walks a procedure body in the same pre-order as the CFG builder, so `next_id` matches [`StmtId`]s.
+fn rewrite_statement(
+    stmt: &mut Statement,
+    next_id: &mut StmtId,
+    facts: &HashMap<StmtId, HashMap<Expr, String>>,
+) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let empty = HashMap::new();
+    // Statements in unreachable blocks have no facts
+    let available = facts.get(&id).unwrap_or(&empty);
+    match stmt {
+        Statement::Assignment { expr, .. } => rewrite_expr(expr, available),
+        Statement::Write { exprs, .. } | Statement::Call { args: exprs, .. } => {
+            exprs.iter_mut().map(|e| rewrite_expr(e, available)).sum()
+        }
+        Statement::BeginEnd { statements } => statements
+            .iter_mut()
+            .map(|s| rewrite_statement(s, next_id, facts))
+            .sum(),
+        Statement::If {
+            condition,
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            let mut count = rewrite_condition(condition, available);
+            count += rewrite_statement(then_stmt, next_id, facts);
+            if let Some(else_stmt) = else_stmt {
+                count += rewrite_statement(else_stmt, next_id, facts);
+            }
+            count
+        }
+        Statement::While {
+            condition, body, ..
+        } => rewrite_condition(condition, available) + rewrite_statement(body, next_id, facts),
+        Statement::Read { .. } | Statement::Empty => 0,
+    }
+}
+
+fn procedure_facts(cfg: &Cfg, effects: &Effects) -> HashMap<StmtId, HashMap<Expr, String>> {
+    let analysis = AvailableCopies {
+        effects: effects.clone(),
+    };
+    let solution = dataflow::solve(&analysis, cfg);
+    let mut reachable = HashSet::new();
+    for b in cfg.reverse_postorder() {
+        reachable.extend(cfg.blocks[b].nodes.iter().map(|n| n.id));
+        if let Terminator::Branch { id, .. } = cfg.blocks[b].terminator {
+            reachable.insert(id);
+        }
+    }
+    dataflow::node_facts(&analysis, cfg, &solution)
+        .into_iter()
+        .filter(|(id, _)| reachable.contains(id))
+        .map(|(id, copies)| {
+            let mut by_expr: HashMap<Expr, String> = HashMap::new();
+            for (expr, holder) in copies {
+                // Several variables may hold the same value; pick one deterministically
+                let entry = by_expr.entry(expr).or_insert_with(|| holder.clone());
+                if holder < *entry {
+                    *entry = holder;
+                }
+            }
+            (id, by_expr)
+        })
+        .collect()
+}
+
+fn rewrite_block(block: &mut Block, cfgs: &[Cfg], effects: &[Effects], index: &mut usize) -> usize {
+    let facts = procedure_facts(&cfgs[*index], &effects[*index]);
+    *index += 1;
+    let mut next_id = 0;
+    let mut count = rewrite_statement(&mut block.statement, &mut next_id, &facts);
+    for proc_decl in &mut block.procedures {
+        count += rewrite_block(&mut proc_decl.block, cfgs, effects, index);
+    }
+    count
+}
+
+/// Runs global CSE over every procedure. Returns the number of expressions replaced.
+pub fn run(program: &mut Program) -> usize {
+    let cfgs = cfg::build_program(program);
+    let effects = modref::program_effects(&cfgs);
+    let mut index = 0;
+    rewrite_block(&mut program.block, &cfgs, &effects, &mut index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+
+    fn optimized(source: &str) -> (Program, usize) {
+        let mut program = compiler::parse(source, false).unwrap();
+        let count = run(&mut program);
+        (program, count)
+    }
+
+    #[test]
+    fn test_across_branches() {
+        let (program, count) = optimized(
+            "program t; var a, b, c, d;
+begin
+  read(a, b);
+  c := a * b;
+  if a > 0 then d := a * b + 1 else d := 2;
+  write(a * b)
+end.",
+        );
+        assert_eq!(count, 2);
+        let source = crate::sexpr::to_string(&program);
+        assert!(source.contains("(:= d (+ c 1)"), "{}", source);
+        assert!(source.contains("(write c"), "{}", source);
+    }
+
+    #[test]
+    fn test_killed_by_holder_or_operand_change() {
+        let (_, count) = optimized(
+            "program t; var a, b, c, d;
+procedure p; begin a := 0 end;
+begin
+  read(a, b);
+  c := a + b;
+  c := 1;
+  d := a + b;
+  b := 2;
+  write(a + b);
+  d := a + b;
+  call p;
+  write(a + b)
+end.",
+        );
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_loop_condition() {
+        let (program, count) = optimized(
+            "program t; var n, m, i;
+begin
+  read(n);
+  m := n * n;
+  i := 0;
+  while i < n * n do i := i + 1;
+  write(i)
+end.",
+        );
+        assert_eq!(count, 1);
+        assert!(crate::sexpr::to_string(&program).contains("(while (< i m)"));
+    }
+}
@@ -0,0 +1,736 @@
+//! A generic worklist dataflow solver over [`Cfg`]s, and the classic analyses
+//! built on it: reaching definitions, live variables and available expressions.
+//!
+//! Facts are attached to two program points per block: `block_in` before the
+//! first node and `block_out` as control leaves the block, after the branch
+//! condition (if any) has been evaluated. Calls are treated conservatively:
+//! the callee may read and write every variable visible at the call site.
+
+use super::modref::{self, CallEffect, CallEffects};
+use crate::ast::{Condition, Expr, Program, Statement};
+use crate::cfg::{self, BasicBlock, Cfg, Node, StmtId, Terminator};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+pub trait Analysis {
+    type Fact: Clone + PartialEq;
+    const DIRECTION: Direction;
+
+    /// Fact at the procedure entry (forward) or exit (backward).
+    fn boundary(&self, cfg: &Cfg) -> Self::Fact;
+    /// Initial fact for every other point: the identity of `meet`.
+    fn top(&self, cfg: &Cfg) -> Self::Fact;
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact;
+    /// Effect of one statement, in the direction of the analysis.
+    fn transfer(&self, node: &Node, fact: &Self::Fact) -> Self::Fact;
+    fn transfer_condition(&self, _condition: &Condition, fact: &Self::Fact) -> Self::Fact {
+        fact.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Solution<F> {
+    pub block_in: Vec<F>,
+    pub block_out: Vec<F>,
+}
+
+fn condition_of(block: &BasicBlock) -> Option<&Condition> {
+    match &block.terminator {
+        Terminator::Branch { condition, .. } => Some(condition),
+        _ => None,
+    }
+}
+
+fn transfer_block<A: Analysis>(analysis: &A, block: &BasicBlock, fact: &A::Fact) -> A::Fact {
+    let mut fact = fact.clone();
+    match A::DIRECTION {
+        Direction::Forward => {
+            for node in &block.nodes {
+                fact = analysis.transfer(node, &fact);
+            }
+            if let Some(cond) = condition_of(block) {
+                fact = analysis.transfer_condition(cond, &fact);
+            }
+        }
+        Direction::Backward => {
+            if let Some(cond) = condition_of(block) {
+                fact = analysis.transfer_condition(cond, &fact);
+            }
+            for node in block.nodes.iter().rev() {
+                fact = analysis.transfer(node, &fact);
+            }
+        }
+    }
+    fact
+}
+
+/// Iterates the analysis to its fixed point.
+pub fn solve<A: Analysis>(analysis: &A, cfg: &Cfg) -> Solution<A::Fact> {
+    let n = cfg.blocks.len();
+    let preds = cfg.predecessors();
+    let succs: Vec<Vec<usize>> = cfg.blocks.iter().map(|b| b.successors()).collect();
+    let top = analysis.top(cfg);
+    let mut block_in = vec![top.clone(); n];
+    let mut block_out = vec![top.clone(); n];
+
+    let mut order = cfg.reverse_postorder();
+    if A::DIRECTION == Direction::Backward {
+        order.reverse();
+    }
+    let mut queued = vec![false; n];
+    for &b in &order {
+        queued[b] = true;
+    }
+    let mut worklist: VecDeque<usize> = order.into();
+
+    while let Some(b) = worklist.pop_front() {
+        queued[b] = false;
+        // Forward analyses flow from predecessors' outs into this in; backward ones the reverse
+        let (sources, boundary_block, dependents) = match A::DIRECTION {
+            Direction::Forward => (&preds[b], cfg.entry, &succs[b]),
+            Direction::Backward => (&succs[b], cfg.exit, &preds[b]),
+        };
+        let mut incoming = (b == boundary_block).then(|| analysis.boundary(cfg));
+        for &s in sources {
+            let other = match A::DIRECTION {
+                Direction::Forward => &block_out[s],
+                Direction::Backward => &block_in[s],
+            };
+            incoming = Some(match incoming {
+                Some(fact) => analysis.meet(&fact, other),
+                None => other.clone(),
+            });
+        }
+        let incoming = incoming.unwrap_or_else(|| top.clone());
+        let outgoing = transfer_block(analysis, &cfg.blocks[b], &incoming);
+
+        let (start, end) = match A::DIRECTION {
+            Direction::Forward => (&mut block_in[b], &mut block_out[b]),
+            Direction::Backward => (&mut block_out[b], &mut block_in[b]),
+        };
+        *start = incoming;
+        if *end != outgoing {
+            *end = outgoing;
+            for &d in dependents {
+                if !queued[d] {
+                    queued[d] = true;
+                    worklist.push_back(d);
+                }
+            }
+        }
+    }
+
+    Solution {
+        block_in,
+        block_out,
+    }
+}
+
+/// The fact at each statement: just before it for forward analyses, just after
+/// it for backward ones. `if`/`while` ids map to the point where the condition
+/// is evaluated.
+pub fn node_facts<A: Analysis>(
+    analysis: &A,
+    cfg: &Cfg,
+    solution: &Solution<A::Fact>,
+) -> HashMap<StmtId, A::Fact> {
+    let mut facts = HashMap::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        match A::DIRECTION {
+            Direction::Forward => {
+                let mut fact = solution.block_in[b].clone();
+                for node in &block.nodes {
+                    facts.insert(node.id, fact.clone());
+                    fact = analysis.transfer(node, &fact);
+                }
+                if let Terminator::Branch { id, .. } = block.terminator {
+                    facts.insert(id, fact);
+                }
+            }
+            Direction::Backward => {
+                let mut fact = solution.block_out[b].clone();
+                if let Terminator::Branch { id, condition, .. } = &block.terminator {
+                    facts.insert(*id, fact.clone());
+                    fact = analysis.transfer_condition(condition, &fact);
+                }
+                for node in block.nodes.iter().rev() {
+                    facts.insert(node.id, fact.clone());
+                    fact = analysis.transfer(node, &fact);
+                }
+            }
+        }
+    }
+    facts
+}
+
+pub(crate) fn expr_vars(expr: &Expr, out: &mut BTreeSet<String>) {
+    match expr {
+        Expr::Binary { left, right, .. } => {
+            expr_vars(left, out);
+            expr_vars(right, out);
+        }
+        Expr::Unary { expr, .. } => expr_vars(expr, out),
+        Expr::Identifier(name) => {
+            out.insert(name.clone());
+        }
+        Expr::Number(_) => {}
+    }
+}
+
+pub(crate) fn condition_vars(cond: &Condition, out: &mut BTreeSet<String>) {
+    match cond {
+        Condition::Odd { expr } => expr_vars(expr, out),
+        Condition::Compare { left, right, .. } => {
+            expr_vars(left, out);
+            expr_vars(right, out);
+        }
+    }
+}
+
+/// Variables read and written by statements of one procedure.
+#[derive(Debug, Clone)]
+pub struct Effects {
+    vars: BTreeSet<String>,
+    /// Mod/ref summaries of the callees; without them a call may touch every variable.
+    calls: Option<CallEffects>,
+}
+
+impl Effects {
+    pub fn new(cfg: &Cfg) -> Self {
+        Self {
+            vars: cfg.variables().into_iter().collect(),
+            calls: None,
+        }
+    }
+
+    pub fn with_calls(cfg: &Cfg, calls: CallEffects) -> Self {
+        Self {
+            vars: cfg.variables().into_iter().collect(),
+            calls: Some(calls),
+        }
+    }
+
+    pub fn is_variable(&self, name: &str) -> bool {
+        self.vars.contains(name)
+    }
+
+    /// What calling `name` does, or `None` if nothing is known about it.
+    pub fn call(&self, name: &str) -> Option<&CallEffect> {
+        self.calls.as_ref().and_then(|calls| calls.get(name))
+    }
+
+    /// Variables whose value the statement may read (constants are filtered out).
+    pub fn uses(&self, stmt: &Statement) -> BTreeSet<String> {
+        let mut used = BTreeSet::new();
+        match stmt {
+            Statement::Assignment { expr, .. } => expr_vars(expr, &mut used),
+            Statement::Write { exprs, .. } => exprs.iter().for_each(|e| expr_vars(e, &mut used)),
+            Statement::Call { name, args, .. } => {
+                args.iter().for_each(|e| expr_vars(e, &mut used));
+                match self.call(name) {
+                    Some(effect) => used.extend(effect.reads.iter().cloned()),
+                    None => used.extend(self.vars.iter().cloned()),
+                }
+            }
+            _ => {}
+        }
+        used.retain(|v| self.vars.contains(v));
+        used
+    }
+
+    pub fn condition_uses(&self, cond: &Condition) -> BTreeSet<String> {
+        let mut used = BTreeSet::new();
+        condition_vars(cond, &mut used);
+        used.retain(|v| self.vars.contains(v));
+        used
+    }
+
+    /// Variables the statement certainly overwrites.
+    pub fn kills(&self, stmt: &Statement) -> Vec<String> {
+        match stmt {
+            Statement::Assignment { name, .. } => vec![name.clone()],
+            Statement::Read { names, .. } => names.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Variables the statement may overwrite: its kills, plus whatever a call may write.
+    pub fn may_defs(&self, stmt: &Statement) -> Vec<String> {
+        match stmt {
+            Statement::Call { name, .. } => match self.call(name) {
+                Some(effect) => effect.writes.iter().cloned().collect(),
+                None => self.vars.iter().cloned().collect(),
+            },
+            _ => self.kills(stmt),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DefSite {
+    /// The value the variable has when the procedure starts.
+    Entry,
+    Stmt(StmtId),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Definition {
+    pub var: String,
+    pub site: DefSite,
+}
+
+pub struct ReachingDefinitions {
+    pub effects: Effects,
+}
+
+impl Analysis for ReachingDefinitions {
+    type Fact = BTreeSet<Definition>;
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, _cfg: &Cfg) -> Self::Fact {
+        self.effects
+            .vars
+            .iter()
+            .map(|v| Definition {
+                var: v.clone(),
+                site: DefSite::Entry,
+            })
+            .collect()
+    }
+
+    fn top(&self, _cfg: &Cfg) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact {
+        a.union(b).cloned().collect()
+    }
+
+    fn transfer(&self, node: &Node, fact: &Self::Fact) -> Self::Fact {
+        let kills = self.effects.kills(&node.stmt);
+        let mut out: Self::Fact = fact
+            .iter()
+            .filter(|d| !kills.contains(&d.var))
+            .cloned()
+            .collect();
+        for var in self.effects.may_defs(&node.stmt) {
+            out.insert(Definition {
+                var,
+                site: DefSite::Stmt(node.id),
+            });
+        }
+        out
+    }
+}
+
+pub struct LiveVariables {
+    pub effects: Effects,
+}
+
+impl Analysis for LiveVariables {
+    type Fact = BTreeSet<String>;
+    const DIRECTION: Direction = Direction::Backward;
+
+    /// Outer variables may be read after the procedure returns; its own locals and params die.
+    fn boundary(&self, cfg: &Cfg) -> Self::Fact {
+        cfg.outer_vars.iter().cloned().collect()
+    }
+
+    fn top(&self, _cfg: &Cfg) -> Self::Fact {
+        BTreeSet::new()
+    }
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact {
+        a.union(b).cloned().collect()
+    }
+
+    fn transfer(&self, node: &Node, fact: &Self::Fact) -> Self::Fact {
+        let mut live = fact.clone();
+        for var in self.effects.kills(&node.stmt) {
+            live.remove(&var);
+        }
+        live.extend(self.effects.uses(&node.stmt));
+        live
+    }
+
+    fn transfer_condition(&self, condition: &Condition, fact: &Self::Fact) -> Self::Fact {
+        let mut live = fact.clone();
+        live.extend(self.effects.condition_uses(condition));
+        live
+    }
+}
+
+/// Every operator expression evaluated by `expr`, innermost first.
+pub(crate) fn subexpressions(expr: &Expr, out: &mut Vec<Expr>) {
+    match expr {
+        Expr::Binary { left, right, .. } => {
+            subexpressions(left, out);
+            subexpressions(right, out);
+        }
+        Expr::Unary { expr: inner, .. } => subexpressions(inner, out),
+        _ => return,
+    }
+    out.push(expr.clone());
+}
+
+fn node_exprs(stmt: &Statement) -> Vec<Expr> {
+    let mut exprs = Vec::new();
+    match stmt {
+        Statement::Assignment { expr, .. } => subexpressions(expr, &mut exprs),
+        Statement::Write { exprs: es, .. } | Statement::Call { args: es, .. } => {
+            es.iter().for_each(|e| subexpressions(e, &mut exprs))
+        }
+        _ => {}
+    }
+    exprs
+}
+
+fn condition_exprs(cond: &Condition) -> Vec<Expr> {
+    let mut exprs = Vec::new();
+    match cond {
+        Condition::Odd { expr } => subexpressions(expr, &mut exprs),
+        Condition::Compare { left, right, .. } => {
+            subexpressions(left, &mut exprs);
+            subexpressions(right, &mut exprs);
+        }
+    }
+    exprs
+}
+
+fn uses_any(expr: &Expr, vars: &[String]) -> bool {
+    let mut used = BTreeSet::new();
+    expr_vars(expr, &mut used);
+    vars.iter().any(|v| used.contains(v))
+}
+
+pub struct AvailableExpressions {
+    pub effects: Effects,
+}
+
+impl Analysis for AvailableExpressions {
+    type Fact = HashSet<Expr>;
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, _cfg: &Cfg) -> Self::Fact {
+        HashSet::new()
+    }
+
+    /// Every operator expression in the procedure; meet is intersection.
+    fn top(&self, cfg: &Cfg) -> Self::Fact {
+        let mut all = HashSet::new();
+        for block in &cfg.blocks {
+            for node in &block.nodes {
+                all.extend(node_exprs(&node.stmt));
+            }
+            if let Some(cond) = condition_of(block) {
+                all.extend(condition_exprs(cond));
+            }
+        }
+        all
+    }
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact {
+        a.intersection(b).cloned().collect()
+    }
+
+    fn transfer(&self, node: &Node, fact: &Self::Fact) -> Self::Fact {
+        let mut out = fact.clone();
+        out.extend(node_exprs(&node.stmt));
+        let defs = self.effects.may_defs(&node.stmt);
+        out.retain(|e| !uses_any(e, &defs));
+        out
+    }
+
+    fn transfer_condition(&self, condition: &Condition, fact: &Self::Fact) -> Self::Fact {
+        let mut out = fact.clone();
+        out.extend(condition_exprs(condition));
+        out
+    }
+}
+
+fn format_defs(defs: &BTreeSet<Definition>) -> String {
+    defs.iter()
+        .map(|d| match d.site {
+            DefSite::Entry => format!("{}@entry", d.var),
+            DefSite::Stmt(id) => format!("{}@{}", d.var, id),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn format_vars(vars: &BTreeSet<String>) -> String {
+    vars.iter().cloned().collect::<Vec<_>>().join(" ")
+}
+
+fn format_exprs(exprs: &HashSet<Expr>) -> String {
+    let mut list: Vec<String> = exprs.iter().map(|e| e.to_string()).collect();
+    list.sort();
+    list.join(", ")
+}
+
+/// Per-block listing of all three analyses, for teaching and debugging.
+pub fn dump(cfg: &Cfg, effects: &Effects) -> String {
+    let reaching = ReachingDefinitions {
+        effects: effects.clone(),
+    };
+    let live = LiveVariables {
+        effects: effects.clone(),
+    };
+    let avail = AvailableExpressions {
+        effects: effects.clone(),
+    };
+    let r = solve(&reaching, cfg);
+    let l = solve(&live, cfg);
+    let a = solve(&avail, cfg);
+
+    let mut out = format!("procedure {}\n", cfg.name);
+    for b in cfg.reverse_postorder() {
+        let block = &cfg.blocks[b];
+        let tag = if b == cfg.entry {
+            " (entry)"
+        } else if b == cfg.exit {
+            " (exit)"
+        } else {
+            ""
+        };
+        out.push_str(&format!("  B{}{}\n", b, tag));
+        for node in &block.nodes {
+            out.push_str(&format!("    [{}] {}\n", node.id, node));
+        }
+        match &block.terminator {
+            Terminator::Goto(to) => out.push_str(&format!("    goto B{}\n", to)),
+            Terminator::Branch {
+                id,
+                condition,
+                then_block,
+                else_block,
+                ..
+            } => out.push_str(&format!(
+                "    [{}] if {} then B{} else B{}\n",
+                id, condition, then_block, else_block
+            )),
+            Terminator::Return => out.push_str("    return\n"),
+        }
+        let rows = [
+            ("reach in: ", format_defs(&r.block_in[b])),
+            ("reach out:", format_defs(&r.block_out[b])),
+            ("live in:  ", format_vars(&l.block_in[b])),
+            ("live out: ", format_vars(&l.block_out[b])),
+            ("avail in: ", format_exprs(&a.block_in[b])),
+            ("avail out:", format_exprs(&a.block_out[b])),
+        ];
+        for (label, value) in rows {
+            out.push_str(format!("    {} {}", label, value).trim_end());
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// [`dump`] for every procedure, with calls described by their mod/ref summaries.
+pub fn dump_program(program: &Program) -> String {
+    let cfgs = cfg::build_program(program);
+    let modref = modref::analyze(&cfgs);
+    (0..cfgs.len())
+        .map(|i| dump(&cfgs[i], &modref.effects(&cfgs, i)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A read of a local variable that may happen before any assignment to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UninitializedUse {
+    pub line: usize,
+    pub var: String,
+    /// No assignment reaches the read at all, rather than only on some paths.
+    pub definitely: bool,
+}
+
+/// First suspicious read of each local of one procedure, found with reaching definitions.
+pub fn uninitialized_uses(cfg: &Cfg, effects: &Effects) -> Vec<UninitializedUse> {
+    let analysis = ReachingDefinitions {
+        effects: effects.clone(),
+    };
+    let solution = solve(&analysis, cfg);
+    let facts = node_facts(&analysis, cfg, &solution);
+
+    let mut calls = HashSet::new();
+    let mut uses = Vec::new();
+    for block in &cfg.blocks {
+        for node in &block.nodes {
+            if matches!(node.stmt, Statement::Call { .. }) {
+                calls.insert(node.id);
+            }
+            let line = match &node.stmt {
+                Statement::Assignment { line, .. }
+                | Statement::Write { line, .. }
+                | Statement::Call { line, .. }
+                | Statement::Read { line, .. } => *line,
+                _ => 0,
+            };
+            let mut used = BTreeSet::new();
+            match &node.stmt {
+                // A call's blanket "uses everything" is not a real read
+                Statement::Call { args, .. } => args.iter().for_each(|e| expr_vars(e, &mut used)),
+                other => used = analysis.effects.uses(other),
+            }
+            uses.push((node.id, line, used));
+        }
+        if let Terminator::Branch {
+            id,
+            line,
+            condition,
+            ..
+        } = &block.terminator
+        {
+            uses.push((*id, *line, analysis.effects.condition_uses(condition)));
+        }
+    }
+    uses.sort_by_key(|(id, _, _)| *id);
+
+    let mut found = Vec::new();
+    let mut reported = HashSet::new();
+    for (id, line, used) in uses {
+        for var in used {
+            if !cfg.locals.contains(&var) || reported.contains(&var) {
+                continue;
+            }
+            let defs: Vec<&Definition> = facts[&id].iter().filter(|d| d.var == var).collect();
+            if !defs.iter().any(|d| d.site == DefSite::Entry) {
+                continue;
+            }
+            // A call might initialize the variable, so stay quiet rather than guess
+            if defs
+                .iter()
+                .any(|d| matches!(d.site, DefSite::Stmt(s) if calls.contains(&s)))
+            {
+                continue;
+            }
+            found.push(UninitializedUse {
+                line,
+                var: var.clone(),
+                definitely: defs.len() == 1,
+            });
+            reported.insert(var);
+        }
+    }
+    found
+}
+
+/// Warnings for [`uninitialized_uses`] in every procedure.
+pub fn uninitialized_warnings(program: &Program) -> Vec<String> {
+    let cfgs = cfg::build_program(program);
+    let modref = modref::analyze(&cfgs);
+    let mut warnings = Vec::new();
+    for (index, cfg) in cfgs.iter().enumerate() {
+        for u in uninitialized_uses(cfg, &modref.effects(&cfgs, index)) {
+            let what = if u.definitely { "is" } else { "may be" };
+            warnings.push(format!(
+                "line {}: variable '{}' {} used before it is assigned (in {})",
+                u.line, u.var, what, cfg.name
+            ));
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+
+    fn main_cfg(source: &str) -> Cfg {
+        let program = compiler::parse(source, false).unwrap();
+        cfg::build_program(&program).remove(0)
+    }
+
+    #[test]
+    fn test_liveness_and_reaching() {
+        let cfg = main_cfg(
+            "program t; var x, y, z;
+begin
+  x := 1;
+  y := 2;
+  while x < 10 do x := x + y;
+  write(x)
+end.",
+        );
+        let live = LiveVariables {
+            effects: Effects::new(&cfg),
+        };
+        let solution = solve(&live, &cfg);
+        let after = node_facts(&live, &cfg, &solution);
+        // After `x := 1` (id 1) both x and y are needed by the loop; z never is
+        assert_eq!(after[&1], BTreeSet::from(["x".to_string()]));
+        assert_eq!(
+            after[&2],
+            BTreeSet::from(["x".to_string(), "y".to_string()])
+        );
+        assert!(solution.block_in[cfg.entry].is_empty());
+
+        let reaching = ReachingDefinitions {
+            effects: Effects::new(&cfg),
+        };
+        let solution = solve(&reaching, &cfg);
+        let before = node_facts(&reaching, &cfg, &solution);
+        // At the write, x comes either from before the loop or from its body
+        let x_defs: Vec<_> = before[&5].iter().filter(|d| d.var == "x").collect();
+        assert_eq!(x_defs.len(), 2);
+    }
+
+    #[test]
+    fn test_available_expressions() {
+        let cfg = main_cfg(
+            "program t; var a, b, c, d;
+begin
+  read(a, b);
+  c := a + b;
+  if a > 0 then d := a + b else b := 1;
+  write(a + b)
+end.",
+        );
+        let avail = AvailableExpressions {
+            effects: Effects::new(&cfg),
+        };
+        let solution = solve(&avail, &cfg);
+        let before = node_facts(&avail, &cfg, &solution);
+        let sum = Expr::Binary {
+            left: Box::new(Expr::Identifier("a".to_string())),
+            op: crate::types::Operator::ADD,
+            right: Box::new(Expr::Identifier("b".to_string())),
+        };
+        // Available at the `then` branch, but the else branch kills it before the join
+        assert!(before[&4].contains(&sum));
+        assert!(!before[&6].contains(&sum));
+    }
+
+    #[test]
+    fn test_uninitialized_warnings() {
+        let program = compiler::parse(
+            "program t; var x, y, z;
+procedure p; begin z := 1 end;
+begin
+  write(x);
+  if y > 0 then x := 1;
+  write(x);
+  call p;
+  write(z)
+end.",
+            false,
+        )
+        .unwrap();
+        let warnings = uninitialized_warnings(&program);
+        assert_eq!(
+            warnings,
+            vec![
+                "line 4: variable 'x' is used before it is assigned (in t)".to_string(),
+                "line 5: variable 'y' is used before it is assigned (in t)".to_string(),
+            ]
+        );
+    }
+}
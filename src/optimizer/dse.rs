@@ -0,0 +1,239 @@
+//! Dead store and unused variable elimination.
+//!
+//! An assignment is dead when liveness says its target is not read again
+//! before being overwritten or going out of scope. Variables of enclosing
+//! procedures are live when a procedure returns, and every visible variable
+//! is live before a call, so stores that nested procedures can observe are
+//! kept. Variables that no statement mentions afterwards are dropped from
+//! their declaration, which shrinks the frame `INT` allocates.
+
+use super::dataflow::{self, Effects, LiveVariables};
+use super::may_trap;
+use super::modref;
+use crate::ast::{Block, Condition, Expr, Program, Statement};
+use crate::cfg::{self, Cfg, StmtId};
+use crate::vm::Arithmetic;
+use std::collections::{BTreeSet, HashSet};
+
+/// Ids of assignments whose value is never read.
+fn dead_stores(cfg: &Cfg, effects: &Effects, arithmetic: Arithmetic) -> HashSet<StmtId> {
+    let analysis = LiveVariables {
+        effects: effects.clone(),
+    };
+    let solution = dataflow::solve(&analysis, cfg);
+    let live_after = dataflow::node_facts(&analysis, cfg, &solution);
+    let mut dead = HashSet::new();
+    for b in cfg.reverse_postorder() {
+        for node in &cfg.blocks[b].nodes {
+            // Removing a division could remove a runtime error
+            if let Statement::Assignment { name, expr, .. } = &node.stmt
+                && !live_after[&node.id].contains(name)
+                && !may_trap(expr, arithmetic)
+            {
+                dead.insert(node.id);
+            }
+        }
+    }
+    dead
+}
+
+/// Replaces dead stores with `Empty`, walking in the CFG builder's pre-order.
+fn remove_stores(stmt: &mut Statement, next_id: &mut StmtId, dead: &HashSet<StmtId>) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    match stmt {
+        Statement::Assignment { .. } if dead.contains(&id) => {
+            *stmt = Statement::Empty;
+            1
+        }
+        Statement::BeginEnd { statements } => statements
+            .iter_mut()
+            .map(|s| remove_stores(s, next_id, dead))
+            .sum(),
+        Statement::If {
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            let mut count = remove_stores(then_stmt, next_id, dead);
+            if let Some(else_stmt) = else_stmt {
+                count += remove_stores(else_stmt, next_id, dead);
+            }
+            count
+        }
+        Statement::While { body, .. } => remove_stores(body, next_id, dead),
+        _ => 0,
+    }
+}
+
+fn remove_dead_stores(
+    block: &mut Block,
+    cfgs: &[Cfg],
+    effects: &[Effects],
+    arithmetic: Arithmetic,
+    index: &mut usize,
+) -> usize {
+    let dead = dead_stores(&cfgs[*index], &effects[*index], arithmetic);
+    *index += 1;
+    let mut next_id = 0;
+    let mut count = remove_stores(&mut block.statement, &mut next_id, &dead);
+    for proc_decl in &mut block.procedures {
+        count += remove_dead_stores(&mut proc_decl.block, cfgs, effects, arithmetic, index);
+    }
+    count
+}
+
+fn statement_names(stmt: &Statement, names: &mut BTreeSet<String>) {
+    let cond_names = |cond: &Condition, names: &mut BTreeSet<String>| {
+        dataflow::condition_vars(cond, names);
+    };
+    let exprs_names = |exprs: &[Expr], names: &mut BTreeSet<String>| {
+        exprs.iter().for_each(|e| dataflow::expr_vars(e, names));
+    };
+    match stmt {
+        Statement::Assignment { name, expr, .. } => {
+            names.insert(name.clone());
+            dataflow::expr_vars(expr, names);
+        }
+        Statement::Read { names: targets, .. } => names.extend(targets.iter().cloned()),
+        Statement::Write { exprs, .. } | Statement::Call { args: exprs, .. } => {
+            exprs_names(exprs, names)
+        }
+        Statement::BeginEnd { statements } => {
+            statements.iter().for_each(|s| statement_names(s, names));
+        }
+        Statement::If {
+            condition,
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            cond_names(condition, names);
+            statement_names(then_stmt, names);
+            if let Some(s) = else_stmt {
+                statement_names(s, names);
+            }
+        }
+        Statement::While {
+            condition, body, ..
+        } => {
+            cond_names(condition, names);
+            statement_names(body, names);
+        }
+        Statement::Empty => {}
+    }
+}
+
+/// Names that statements in `block` or its nested procedures use to refer to
+/// something declared outside the nested procedure that uses them.
+fn free_names(block: &Block, params: &[String]) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    statement_names(&block.statement, &mut names);
+    for proc_decl in &block.procedures {
+        names.extend(free_names(&proc_decl.block, &proc_decl.params));
+    }
+    names.retain(|n| {
+        !params.contains(n)
+            && !block.vars.contains(n)
+            && !block.consts.iter().any(|c| &c.name == n)
+            && !block.procedures.iter().any(|p| &p.name == n)
+    });
+    names
+}
+
+/// Drops unreferenced variables from every declaration. Returns the number removed.
+fn remove_unused_vars(block: &mut Block) -> usize {
+    let mut used = BTreeSet::new();
+    statement_names(&block.statement, &mut used);
+    for proc_decl in &block.procedures {
+        used.extend(free_names(&proc_decl.block, &proc_decl.params));
+    }
+    let before = block.vars.len();
+    block.vars.retain(|v| used.contains(v));
+    let mut count = before - block.vars.len();
+    for proc_decl in &mut block.procedures {
+        count += remove_unused_vars(&mut proc_decl.block);
+    }
+    count
+}
+
+/// Removes dead stores until none are left, then unused variables.
+/// Returns `(stores removed, variable slots removed)`.
+pub fn run(program: &mut Program, arithmetic: Arithmetic) -> (usize, usize) {
+    let mut stores = 0;
+    loop {
+        let cfgs = cfg::build_program(program);
+        let effects = modref::program_effects(&cfgs);
+        let mut index = 0;
+        let removed =
+            remove_dead_stores(&mut program.block, &cfgs, &effects, arithmetic, &mut index);
+        if removed == 0 {
+            break;
+        }
+        stores += removed;
+    }
+    (stores, remove_unused_vars(&mut program.block))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, sexpr};
+
+    fn eliminated(source: &str) -> (String, usize, usize) {
+        let mut program = compiler::parse(source, false).unwrap();
+        let (stores, slots) = run(&mut program, Arithmetic::default());
+        (sexpr::to_string(&program), stores, slots)
+    }
+
+    #[test]
+    fn test_dead_stores_and_slots() {
+        let (text, stores, slots) = eliminated(
+            "program t; var a, b, c, unused;
+begin
+  a := 1;
+  b := 2;
+  a := 3;
+  c := b;
+  write(a)
+end.",
+        );
+        // a := 1 is overwritten; c is never read, and then neither is b
+        assert_eq!((stores, slots), (3, 3), "{}", text);
+        assert!(text.contains("(var a)"), "{}", text);
+    }
+
+    #[test]
+    fn test_stores_visible_elsewhere_are_kept() {
+        let (text, stores, slots) = eliminated(
+            "program t; var g, h, d;
+procedure show; begin write(g) end;
+procedure set; var l; begin l := 5; h := 7 end;
+begin
+  g := 1;
+  call show;
+  call set;
+  d := 10 / h
+end.",
+        );
+        // g is read by the callee, h belongs to main, and a division may trap
+        assert_eq!((stores, slots), (1, 1), "{}", text);
+        assert!(text.contains("(:= g 1"), "{}", text);
+        assert!(text.contains("(:= h 7"), "{}", text);
+        assert!(text.contains("(:= d (/ 10 h)"), "{}", text);
+        assert!(!text.contains("(var l)"), "{}", text);
+    }
+
+    #[test]
+    fn test_shadowed_names_do_not_count() {
+        let (text, _, slots) = eliminated(
+            "program t; var x;
+procedure p; var x; begin read(x); write(x) end;
+begin
+  call p
+end.",
+        );
+        assert_eq!(slots, 1, "{}", text);
+        assert!(text.contains("(var x)"), "{}", text);
+    }
+}
@@ -0,0 +1,529 @@
+//! Procedure inlining.
+//!
+//! A call to a small, non-recursive procedure without nested procedures is
+//! replaced by its body. Parameters and locals become fresh variables of the
+//! caller (assigned from the arguments first), constants become literals, and
+//! every other name in the body must mean the same thing at the call site as
+//! in the callee; otherwise the call is left alone. Frame offsets and static
+//! levels are not touched here: the semantic analyzer assigns new ones when it
+//! runs again on the optimized tree.
+
+use super::dataflow;
+use super::modref;
+use crate::ast::{Block, Condition, ConstDecl, Expr, Program, Statement};
+use crate::cfg;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Largest callee body, in AST nodes, that is inlined by default.
+pub const DEFAULT_INLINE_LIMIT: usize = 16;
+
+/// Rounds of inlining; each round can expose calls inside freshly inlined bodies.
+const MAX_ROUNDS: usize = 4;
+
+/// Declarations of one procedure, in the pre-order of [`cfg::build_program`].
+struct Scope {
+    name: String,
+    parent: Option<usize>,
+    params: Vec<String>,
+    vars: Vec<String>,
+    consts: Vec<ConstDecl>,
+    procs: Vec<String>,
+    body: Statement,
+}
+
+/// What a name refers to, identified by the scope that declares it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Binding {
+    Var(usize),
+    Const(usize),
+    Proc(usize),
+}
+
+fn collect_scopes(
+    name: &str,
+    params: &[String],
+    block: &Block,
+    parent: Option<usize>,
+    scopes: &mut Vec<Scope>,
+) {
+    let index = scopes.len();
+    scopes.push(Scope {
+        name: name.to_string(),
+        parent,
+        params: params.to_vec(),
+        vars: block.vars.clone(),
+        consts: block.consts.clone(),
+        procs: block.procedures.iter().map(|p| p.name.clone()).collect(),
+        body: block.statement.clone(),
+    });
+    for p in &block.procedures {
+        collect_scopes(&p.name, &p.params, &p.block, Some(index), scopes);
+    }
+}
+
+fn resolve(scopes: &[Scope], index: usize, name: &str) -> Option<Binding> {
+    let mut scope = Some(index);
+    while let Some(s) = scope {
+        let sc = &scopes[s];
+        if sc.params.iter().chain(&sc.vars).any(|v| v == name) {
+            return Some(Binding::Var(s));
+        }
+        if sc.consts.iter().any(|c| c.name == name) {
+            return Some(Binding::Const(s));
+        }
+        if sc.procs.iter().any(|p| p == name) {
+            return Some(Binding::Proc(s));
+        }
+        scope = sc.parent;
+    }
+    None
+}
+
+/// Scope index of the procedure `call name` in scope `index` refers to.
+fn resolve_callee(scopes: &[Scope], index: usize, name: &str) -> Option<usize> {
+    match resolve(scopes, index, name)? {
+        Binding::Proc(owner) => {
+            (0..scopes.len()).find(|&k| scopes[k].parent == Some(owner) && scopes[k].name == name)
+        }
+        _ => None,
+    }
+}
+
+fn expr_size(expr: &Expr) -> usize {
+    match expr {
+        Expr::Binary { left, right, .. } => 1 + expr_size(left) + expr_size(right),
+        Expr::Unary { expr, .. } => 1 + expr_size(expr),
+        _ => 1,
+    }
+}
+
+/// Number of statement and expression nodes.
+pub(super) fn size(stmt: &Statement) -> usize {
+    1 + match stmt {
+        Statement::Assignment { expr, .. } => expr_size(expr),
+        Statement::Write { exprs, .. } | Statement::Call { args: exprs, .. } => {
+            exprs.iter().map(expr_size).sum()
+        }
+        Statement::Read { names, .. } => names.len(),
+        Statement::BeginEnd { statements } => statements.iter().map(size).sum(),
+        Statement::If {
+            then_stmt,
+            else_stmt,
+            ..
+        } => 1 + size(then_stmt) + else_stmt.as_deref().map_or(0, size),
+        Statement::While { body, .. } => 1 + size(body),
+        Statement::Empty => 0,
+    }
+}
+
+/// Every name a statement mentions: variables, constants and called procedures.
+fn names(stmt: &Statement, out: &mut BTreeSet<String>) {
+    match stmt {
+        Statement::Assignment { name, expr, .. } => {
+            out.insert(name.clone());
+            dataflow::expr_vars(expr, out);
+        }
+        Statement::Read { names, .. } => out.extend(names.iter().cloned()),
+        Statement::Write { exprs, .. } => exprs.iter().for_each(|e| dataflow::expr_vars(e, out)),
+        Statement::Call { name, args, .. } => {
+            out.insert(name.clone());
+            args.iter().for_each(|e| dataflow::expr_vars(e, out));
+        }
+        Statement::BeginEnd { statements } => statements.iter().for_each(|s| names(s, out)),
+        Statement::If {
+            condition,
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            dataflow::condition_vars(condition, out);
+            names(then_stmt, out);
+            if let Some(s) = else_stmt {
+                names(s, out);
+            }
+        }
+        Statement::While {
+            condition, body, ..
+        } => {
+            dataflow::condition_vars(condition, out);
+            names(body, out);
+        }
+        Statement::Empty => {}
+    }
+}
+
+fn rename_expr(expr: &mut Expr, map: &HashMap<String, Expr>) {
+    match expr {
+        Expr::Identifier(name) => {
+            if let Some(new) = map.get(name) {
+                *expr = new.clone();
+            }
+        }
+        Expr::Binary { left, right, .. } => {
+            rename_expr(left, map);
+            rename_expr(right, map);
+        }
+        Expr::Unary { expr, .. } => rename_expr(expr, map),
+        Expr::Number(_) => {}
+    }
+}
+
+fn rename_var(name: &mut String, map: &HashMap<String, Expr>) {
+    if let Some(Expr::Identifier(new)) = map.get(name) {
+        *name = new.clone();
+    }
+}
+
+fn rename_condition(cond: &mut Condition, map: &HashMap<String, Expr>) {
+    match cond {
+        Condition::Odd { expr } => rename_expr(expr, map),
+        Condition::Compare { left, right, .. } => {
+            rename_expr(left, map);
+            rename_expr(right, map);
+        }
+    }
+}
+
+fn rename(stmt: &mut Statement, map: &HashMap<String, Expr>) {
+    match stmt {
+        Statement::Assignment { name, expr, .. } => {
+            rename_var(name, map);
+            rename_expr(expr, map);
+        }
+        Statement::Read { names, .. } => names.iter_mut().for_each(|n| rename_var(n, map)),
+        Statement::Write { exprs, .. } | Statement::Call { args: exprs, .. } => {
+            exprs.iter_mut().for_each(|e| rename_expr(e, map))
+        }
+        Statement::BeginEnd { statements } => statements.iter_mut().for_each(|s| rename(s, map)),
+        Statement::If {
+            condition,
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            rename_condition(condition, map);
+            rename(then_stmt, map);
+            if let Some(s) = else_stmt {
+                rename(s, map);
+            }
+        }
+        Statement::While {
+            condition, body, ..
+        } => {
+            rename_condition(condition, map);
+            rename(body, map);
+        }
+        Statement::Empty => {}
+    }
+}
+
+struct Inliner {
+    scopes: Vec<Scope>,
+    /// Callees that may be inlined anywhere their names resolve the same.
+    candidates: HashSet<usize>,
+    /// Every identifier in the program, so fresh names never collide.
+    taken: HashSet<String>,
+    /// Fresh names per (caller, callee); inlined bodies of one callee never overlap in time.
+    renames: HashMap<(usize, usize), HashMap<String, Expr>>,
+    inlined: usize,
+}
+
+impl Inliner {
+    fn fresh(&mut self, callee: &str, name: &str) -> String {
+        super::fresh_name(&mut self.taken, &format!("{}_{}", callee, name))
+    }
+
+    /// True if every name the callee borrows from outside means the same in `caller`.
+    fn same_bindings(&self, caller: usize, callee: usize) -> bool {
+        let sc = &self.scopes[callee];
+        let mut used = BTreeSet::new();
+        names(&sc.body, &mut used);
+        used.iter()
+            .filter(|n| {
+                !sc.params.contains(n)
+                    && !sc.vars.contains(n)
+                    && !sc.consts.iter().any(|c| &c.name == *n)
+            })
+            .all(|n| resolve(&self.scopes, callee, n) == resolve(&self.scopes, caller, n))
+    }
+
+    fn renames_for(
+        &mut self,
+        caller: usize,
+        callee: usize,
+        vars: &mut Vec<String>,
+    ) -> HashMap<String, Expr> {
+        if let Some(map) = self.renames.get(&(caller, callee)) {
+            return map.clone();
+        }
+        let name = self.scopes[callee].name.clone();
+        let mut map = HashMap::new();
+        let locals: Vec<String> = self.scopes[callee]
+            .params
+            .iter()
+            .chain(&self.scopes[callee].vars)
+            .cloned()
+            .collect();
+        for local in locals {
+            let new = self.fresh(&name, &local);
+            vars.push(new.clone());
+            map.insert(local, Expr::Identifier(new));
+        }
+        for c in &self.scopes[callee].consts {
+            map.insert(c.name.clone(), Expr::Number(c.value));
+        }
+        self.renames.insert((caller, callee), map.clone());
+        map
+    }
+
+    fn statement(&mut self, stmt: &mut Statement, caller: usize, vars: &mut Vec<String>) {
+        match stmt {
+            Statement::Call { name, args, line } => {
+                let Some(callee) = resolve_callee(&self.scopes, caller, name) else {
+                    return;
+                };
+                if callee == caller
+                    || !self.candidates.contains(&callee)
+                    || !self.same_bindings(caller, callee)
+                {
+                    return;
+                }
+                let map = self.renames_for(caller, callee, vars);
+                let mut statements = Vec::new();
+                for (param, arg) in self.scopes[callee].params.iter().zip(args.iter()) {
+                    if let Some(Expr::Identifier(new)) = map.get(param) {
+                        statements.push(Statement::Assignment {
+                            name: new.clone(),
+                            expr: arg.clone(),
+                            line: *line,
+                        });
+                    }
+                }
+                let mut body = self.scopes[callee].body.clone();
+                rename(&mut body, &map);
+                statements.push(body);
+                *stmt = Statement::BeginEnd { statements };
+                self.inlined += 1;
+            }
+            Statement::BeginEnd { statements } => {
+                for s in statements {
+                    self.statement(s, caller, vars);
+                }
+            }
+            Statement::If {
+                then_stmt,
+                else_stmt,
+                ..
+            } => {
+                self.statement(then_stmt, caller, vars);
+                if let Some(s) = else_stmt {
+                    self.statement(s, caller, vars);
+                }
+            }
+            Statement::While { body, .. } => self.statement(body, caller, vars),
+            _ => {}
+        }
+    }
+
+    fn block(&mut self, block: &mut Block, index: &mut usize) {
+        let caller = *index;
+        *index += 1;
+        let Block {
+            vars,
+            statement,
+            procedures,
+            ..
+        } = block;
+        self.statement(statement, caller, vars);
+        for p in procedures {
+            self.block(&mut p.block, index);
+        }
+    }
+}
+
+/// Every identifier declared or mentioned anywhere in the program.
+pub(super) fn program_names(program: &Program) -> HashSet<String> {
+    let mut scopes = Vec::new();
+    collect_scopes(&program.name, &[], &program.block, None, &mut scopes);
+    let mut taken = HashSet::new();
+    for sc in &scopes {
+        taken.insert(sc.name.clone());
+        taken.extend(sc.params.iter().cloned());
+        taken.extend(sc.vars.iter().cloned());
+        taken.extend(sc.consts.iter().map(|c| c.name.clone()));
+        let mut used = BTreeSet::new();
+        names(&sc.body, &mut used);
+        taken.extend(used);
+    }
+    taken
+}
+
+/// Drops procedures that can no longer be reached from the main program.
+fn remove_uncalled(program: &mut Program) {
+    let cfgs = cfg::build_program(program);
+    let modref = modref::analyze(&cfgs);
+    let mut reachable = vec![false; cfgs.len()];
+    let mut work = vec![0];
+    while let Some(i) = work.pop() {
+        if !reachable[i] {
+            reachable[i] = true;
+            work.extend(modref.procedures[i].calls.iter().copied());
+        }
+    }
+    fn retain(block: &mut Block, reachable: &[bool], index: &mut usize) {
+        *index += 1;
+        block.procedures.retain_mut(|p| {
+            let keep = reachable[*index];
+            if keep {
+                retain(&mut p.block, reachable, index);
+            } else {
+                // Skip the whole subtree's indices
+                let mut scopes = Vec::new();
+                collect_scopes(&p.name, &p.params, &p.block, None, &mut scopes);
+                *index += scopes.len();
+            }
+            keep
+        });
+    }
+    retain(&mut program.block, &reachable, &mut 0);
+}
+
+/// Inlines calls to procedures of at most `limit` nodes. Returns the number of calls inlined.
+pub fn run(program: &mut Program, limit: usize) -> usize {
+    if limit == 0 {
+        return 0;
+    }
+    let mut total = 0;
+    let mut taken = program_names(program);
+    for _ in 0..MAX_ROUNDS {
+        let mut scopes = Vec::new();
+        collect_scopes(&program.name, &[], &program.block, None, &mut scopes);
+        let cfgs = cfg::build_program(program);
+        let modref = modref::analyze(&cfgs);
+        let effects = modref::program_effects(&cfgs);
+
+        let mut candidates = HashSet::new();
+        for k in 1..scopes.len() {
+            // A callee reachable from itself would be expanded forever
+            let mut seen = HashSet::new();
+            let mut work: Vec<usize> = modref.procedures[k].calls.iter().copied().collect();
+            while let Some(j) = work.pop() {
+                if seen.insert(j) {
+                    work.extend(modref.procedures[j].calls.iter().copied());
+                }
+            }
+            // Locals read before assignment would see another activation's leftovers
+            if scopes[k].procs.is_empty()
+                && !seen.contains(&k)
+                && size(&scopes[k].body) <= limit
+                && dataflow::uninitialized_uses(&cfgs[k], &effects[k]).is_empty()
+            {
+                candidates.insert(k);
+            }
+        }
+        if candidates.is_empty() {
+            break;
+        }
+
+        let mut inliner = Inliner {
+            scopes,
+            candidates,
+            taken,
+            renames: HashMap::new(),
+            inlined: 0,
+        };
+        inliner.block(&mut program.block, &mut 0);
+        taken = inliner.taken;
+        if inliner.inlined == 0 {
+            break;
+        }
+        total += inliner.inlined;
+    }
+    if total > 0 {
+        remove_uncalled(program);
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, sexpr};
+
+    fn inlined(source: &str, limit: usize) -> (String, usize) {
+        let mut program = compiler::parse(source, false).unwrap();
+        let count = run(&mut program, limit);
+        // The result must still pass semantic analysis
+        let (program, _) = compiler::check_program(program).unwrap();
+        (sexpr::to_string(&program), count)
+    }
+
+    #[test]
+    fn test_params_locals_and_outer_names() {
+        let (text, count) = inlined(
+            "program t; const k = 10; var x, y, res;
+procedure multiply(a, b); const two = 2; var tmp;
+begin tmp := a * b; res := tmp * two + k end;
+begin
+  read(x, y);
+  call multiply(x, y);
+  call multiply(y + 1, x)
+end.",
+            DEFAULT_INLINE_LIMIT,
+        );
+        assert_eq!(count, 2);
+        assert!(
+            text.contains("(var x y res multiply_a multiply_b multiply_tmp)"),
+            "{}",
+            text
+        );
+        assert!(text.contains("(:= multiply_a (+ y 1)"), "{}", text);
+        assert!(
+            text.contains("(:= res (+ (* multiply_tmp 2) k)"),
+            "{}",
+            text
+        );
+        // No call is left, so the procedure itself is gone
+        assert!(!text.contains("procedure"), "{}", text);
+    }
+
+    #[test]
+    fn test_what_is_not_inlined() {
+        let cases = [
+            // recursive
+            "program t; var n; procedure r; begin if n > 0 then begin n := n - 1; call r end end; begin n := 3; call r end.",
+            // has a nested procedure
+            "program t; var n; procedure p; procedure q; begin n := 1 end; begin call q end; begin call p end.",
+            // local read before it is assigned
+            "program t; var n; procedure p; var c; begin c := c + 1; n := c end; begin call p end.",
+            // the caller's own x would capture the callee's global x
+            "program t; var x; procedure p; begin x := 1 end; procedure q; var x; begin call p; write(x) end; begin call q end.",
+        ];
+        for source in cases {
+            let (text, count) = inlined(source, 100);
+            assert!(count <= 1 && text.contains("procedure"), "{}", text);
+        }
+        let (_, count) = inlined(cases[0], 0);
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_nested_calls_and_name_clashes() {
+        let (text, count) = inlined(
+            "program t; var inc_v, r;
+procedure inc(v); begin r := r + v end;
+procedure twice(v); begin call inc(v); call inc(v) end;
+begin
+  r := 0;
+  call twice(5);
+  write(r)
+end.",
+            DEFAULT_INLINE_LIMIT,
+        );
+        // twice is inlined into main and inc into twice; the calls to inc twice brought
+        // along follow in the next round
+        assert_eq!(count, 5, "{}", text);
+        assert!(text.contains("(var inc_v r twice_v inc_v_2)"), "{}", text);
+        assert!(!text.contains("(call"), "{}", text);
+    }
+}
@@ -0,0 +1,1120 @@
+use crate::ast::*;
+use crate::cfg;
+use crate::types::Operator;
+use crate::vm::{Arithmetic, Overflow};
+use modref::CallEffects;
+use std::collections::{BTreeSet, HashSet};
+
+mod cse;
+pub mod dataflow;
+mod dse;
+mod inline;
+pub mod modref;
+pub mod pipeline;
+mod propagate;
+mod unroll;
+
+pub use pipeline::{Pass, PassStats};
+
+/// Propagation rounds the overflow lint runs to see through variables.
+const MAX_LINT_ROUNDS: usize = 8;
+
+/// What the AST optimizer removed, for reporting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OptimizeStats {
+    pub inlined_calls: usize,
+    pub unrolled_loops: usize,
+    pub dead_stores: usize,
+    pub removed_slots: usize,
+    /// One entry per pass of the pipeline, in pipeline order.
+    pub passes: Vec<PassStats>,
+    /// `(heading, AST)` for every run of a pass named in `print_after`.
+    pub dumps: Vec<(String, String)>,
+}
+
+pub use inline::DEFAULT_INLINE_LIMIT;
+pub use unroll::DEFAULT_UNROLL_BUDGET;
+
+/// Which passes run, and the size limits of those that trade code size for speed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptimizeOptions {
+    /// Run in this order, repeatedly, until the program stops changing.
+    pub passes: Vec<Pass>,
+    /// Passes after which the AST is dumped into [`OptimizeStats::dumps`].
+    pub print_after: Vec<Pass>,
+    /// Largest procedure body, in AST nodes, that is inlined; 0 disables inlining.
+    pub inline_limit: usize,
+    /// Most AST nodes unrolling may add per loop; 0 disables unrolling.
+    pub unroll_budget: usize,
+    /// The arithmetic of the VM the code is for; constants are only folded
+    /// to words it holds, so folding never hides an overflow.
+    pub arithmetic: Arithmetic,
+}
+
+impl Default for OptimizeOptions {
+    fn default() -> Self {
+        OptimizeOptions {
+            passes: Pass::level(2),
+            print_after: Vec::new(),
+            inline_limit: DEFAULT_INLINE_LIMIT,
+            unroll_budget: DEFAULT_UNROLL_BUDGET,
+            arithmetic: Arithmetic::default(),
+        }
+    }
+}
+
+pub fn optimize_ast(program: &mut Program) -> OptimizeStats {
+    optimize_ast_with(program, &OptimizeOptions::default())
+}
+
+/// Like [`optimize_ast`], with an explicit pipeline.
+pub fn optimize_ast_with(program: &mut Program, options: &OptimizeOptions) -> OptimizeStats {
+    pipeline::run(program, options)
+}
+
+/// `base`, or `base_N` for the first N that no identifier in the program uses yet.
+fn fresh_name(taken: &mut HashSet<String>, base: &str) -> String {
+    let mut candidate = base.to_string();
+    let mut n = 1;
+    while taken.contains(&candidate) {
+        candidate = format!("{}_{}", base, n);
+        n += 1;
+    }
+    taken.insert(candidate.clone());
+    candidate
+}
+
+/// Which statement-level rewrites [`simplify`] performs.
+#[derive(Debug, Clone, Copy, Default)]
+struct Rewrites {
+    fold: bool,
+    dce: bool,
+    sr: bool,
+    licm: bool,
+}
+
+/// State of one [`simplify`] walk over a block.
+struct Context<'a> {
+    rewrites: Rewrites,
+    arithmetic: Arithmetic,
+    /// Names are unique program-wide, so a temporary never shadows an outer variable.
+    taken: &'a mut HashSet<String>,
+    /// Temporaries introduced into the block.
+    vars: Vec<String>,
+    /// Expressions folded, statements pruned, temporaries introduced and statements hoisted.
+    changes: usize,
+}
+
+impl Context<'_> {
+    fn fresh(&mut self, base: &str) -> String {
+        let name = fresh_name(self.taken, base);
+        self.vars.push(name.clone());
+        name
+    }
+}
+
+/// Folds, prunes dead branches, reduces induction variables and hoists invariants
+/// in every procedure, as selected by `rewrites`. Returns the number of changes.
+fn simplify(program: &mut Program, rewrites: Rewrites, arithmetic: Arithmetic) -> usize {
+    let cfgs = cfg::build_program(program);
+    let modref = modref::analyze(&cfgs);
+    let calls: Vec<CallEffects> = (0..cfgs.len())
+        .map(|i| modref.call_effects(&cfgs, i))
+        .collect();
+    let mut taken = inline::program_names(program);
+    let mut index = 0;
+    let mut cx = Context {
+        rewrites,
+        arithmetic,
+        taken: &mut taken,
+        vars: Vec::new(),
+        changes: 0,
+    };
+    optimize_block(&mut program.block, &calls, &mut cx, &mut index);
+    cx.changes
+}
+
+/// `calls[index]` describes the calls made by this block; nested blocks follow in pre-order.
+fn optimize_block(block: &mut Block, calls: &[CallEffects], cx: &mut Context, index: &mut usize) {
+    let own = *index;
+    *index += 1;
+    for proc in &mut block.procedures {
+        optimize_block(&mut proc.block, calls, cx, index);
+    }
+    optimize_statement(&mut block.statement, &calls[own], cx);
+    block.vars.append(&mut cx.vars);
+}
+
+/// Folds `expr` if folding is on, counting it if it changed.
+fn fold_expr(expr: &mut Expr, cx: &mut Context) {
+    if cx.rewrites.fold {
+        let before = expr.clone();
+        optimize_expr(expr, cx.arithmetic);
+        cx.changes += usize::from(*expr != before);
+    }
+}
+
+fn fold_condition(cond: &mut Condition, cx: &mut Context) {
+    if cx.rewrites.fold {
+        let before = cond.clone();
+        optimize_condition(cond, cx.arithmetic);
+        cx.changes += usize::from(*cond != before);
+    }
+}
+
+fn optimize_statement(stmt: &mut Statement, calls: &CallEffects, cx: &mut Context) {
+    match stmt {
+        Statement::Assignment { expr, .. } => fold_expr(expr, cx),
+        Statement::Call { args, .. } => {
+            for arg in args {
+                fold_expr(arg, cx);
+            }
+        }
+        Statement::BeginEnd { statements } => {
+            // 1. Optimize children
+            for s in statements.iter_mut() {
+                optimize_statement(s, calls, cx);
+            }
+
+            // 2. Filter Empty
+            if cx.rewrites.dce {
+                statements.retain(|s| !matches!(s, Statement::Empty));
+            }
+        }
+        Statement::If {
+            condition,
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            fold_condition(condition, cx);
+            optimize_statement(then_stmt, calls, cx);
+            if let Some(s) = else_stmt {
+                optimize_statement(s, calls, cx);
+            }
+
+            // Dead Code Elimination for If
+            if cx.rewrites.dce
+                && let Some(val) = evaluate_condition(condition)
+            {
+                cx.changes += 1;
+                if val {
+                    *stmt = *then_stmt.clone();
+                } else if let Some(else_s) = else_stmt {
+                    *stmt = *else_s.clone();
+                } else {
+                    *stmt = Statement::Empty;
+                }
+            }
+        }
+        Statement::While { condition, body, .. } => {
+            fold_condition(condition, cx);
+            optimize_statement(body, calls, cx);
+
+            // Dead Code Elimination for While
+            if cx.rewrites.dce && evaluate_condition(condition) == Some(false) {
+                *stmt = Statement::Empty;
+                cx.changes += 1;
+                return;
+            }
+            // Strength reduction goes first: its temporaries are initialized in front
+            // of the loop, and the updates it adds keep them out of LICM's way
+            if cx.rewrites.sr {
+                reduce_induction_vars(stmt, calls, cx);
+            }
+            // Loop Invariant Code Motion
+            if cx.rewrites.licm {
+                cx.changes += try_licm(guarded_loop(stmt), calls, cx.arithmetic);
+            }
+        }
+        Statement::Read { .. } => {}
+        Statement::Write { exprs, .. } => {
+            for expr in exprs {
+                fold_expr(expr, cx);
+            }
+        }
+        Statement::Empty => {}
+    }
+}
+
+fn evaluate_condition(cond: &Condition) -> Option<bool> {
+    match cond {
+        Condition::Odd { expr } => {
+            if let Expr::Number(val) = expr {
+                Some(val % 2 != 0)
+            } else {
+                None
+            }
+        }
+        Condition::Compare { left, op, right } => {
+            if let (Expr::Number(l), Expr::Number(r)) = (left, right) {
+                match op {
+                    Operator::EQL => Some(l == r),
+                    Operator::NEQ => Some(l != r),
+                    Operator::LSS => Some(l < r),
+                    Operator::LEQ => Some(l <= r),
+                    Operator::GTR => Some(l > r),
+                    Operator::GEQ => Some(l >= r),
+                    _ => None,
+                }
+            } else {
+                None
+            }
+        }
+    }
+}
+
+fn optimize_condition(cond: &mut Condition, arithmetic: Arithmetic) {
+    match cond {
+        Condition::Odd { expr } => optimize_expr(expr, arithmetic),
+        Condition::Compare { left, right, .. } => {
+            optimize_expr(left, arithmetic);
+            optimize_expr(right, arithmetic);
+        }
+    }
+}
+
+/// The constant `k` if `expr` is `var * k` or `k * var`.
+fn product_of(expr: &Expr, var: &str) -> Option<i64> {
+    match expr {
+        Expr::Binary {
+            left,
+            op: Operator::MUL,
+            right,
+        } => match (left.as_ref(), right.as_ref()) {
+            (Expr::Identifier(v), Expr::Number(k)) | (Expr::Number(k), Expr::Identifier(v))
+                if v == var =>
+            {
+                Some(*k)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn for_each_condition_expr(cond: &mut Condition, f: &mut impl FnMut(&mut Expr)) {
+    match cond {
+        Condition::Odd { expr } => f(expr),
+        Condition::Compare { left, right, .. } => {
+            f(left);
+            f(right);
+        }
+    }
+}
+
+/// Visits every expression in `stmt`, conditions included.
+fn for_each_expr(stmt: &mut Statement, f: &mut impl FnMut(&mut Expr)) {
+    match stmt {
+        Statement::Assignment { expr, .. } => f(expr),
+        Statement::Write { exprs, .. } | Statement::Call { args: exprs, .. } => {
+            exprs.iter_mut().for_each(&mut *f)
+        }
+        Statement::BeginEnd { statements } => {
+            statements.iter_mut().for_each(|s| for_each_expr(s, f))
+        }
+        Statement::If {
+            condition: cond,
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            for_each_condition_expr(cond, f);
+            for_each_expr(then_stmt, f);
+            if let Some(s) = else_stmt {
+                for_each_expr(s, f);
+            }
+        }
+        Statement::While {
+            condition: cond,
+            body,
+            ..
+        } => {
+            for_each_condition_expr(cond, f);
+            for_each_expr(body, f);
+        }
+        Statement::Read { .. } | Statement::Empty => {}
+    }
+}
+
+/// Collects the constants `var` is multiplied by anywhere in `expr`.
+fn collect_factors(expr: &Expr, var: &str, factors: &mut BTreeSet<i64>) {
+    if let Some(k) = product_of(expr, var) {
+        factors.insert(k);
+        return;
+    }
+    match expr {
+        Expr::Binary { left, right, .. } => {
+            collect_factors(left, var, factors);
+            collect_factors(right, var, factors);
+        }
+        Expr::Unary { expr, .. } => collect_factors(expr, var, factors),
+        _ => {}
+    }
+}
+
+fn replace_product(expr: &mut Expr, var: &str, k: i64, temp: &str) {
+    if product_of(expr, var) == Some(k) {
+        *expr = Expr::Identifier(temp.to_string());
+        return;
+    }
+    match expr {
+        Expr::Binary { left, right, .. } => {
+            replace_product(left, var, k, temp);
+            replace_product(right, var, k, temp);
+        }
+        Expr::Unary { expr, .. } => replace_product(expr, var, k, temp),
+        _ => {}
+    }
+}
+
+/// Strength reduction of induction variables. A basic induction variable `i`
+/// is assigned exactly once in the loop, at the top level of the body, by
+/// `i := i + c` or `i := i - c`. Every `i * k` in the loop is then replaced by
+/// a temporary `t` that is set to `i * k` before the loop and advanced by
+/// `t := t + c * k` right after `i` changes, so `t = i * k` wherever it is read.
+/// The initializations are guarded by the loop condition, like hoisted code.
+///
+/// The last update computes a product the loop never reads, and a product
+/// inside an `if` may be computed where it never was before. Only wrapping
+/// arithmetic makes that harmless: a product that would trap or saturate
+/// could fail, or make the running sum drift from `i * k`, so the pass only
+/// runs when the VM wraps.
+fn reduce_induction_vars(stmt: &mut Statement, calls: &CallEffects, cx: &mut Context) {
+    let arithmetic = cx.arithmetic;
+    if arithmetic.overflow != Overflow::Wrap {
+        return;
+    }
+    let Statement::While {
+        condition,
+        body,
+        line,
+    } = stmt
+    else {
+        return;
+    };
+    let mut callees = Vec::new();
+    collect_callees(body, &mut callees);
+    let mut call_writes = HashSet::new();
+    for callee in &callees {
+        match calls.get(callee) {
+            Some(effect) => call_writes.extend(effect.writes.iter().cloned()),
+            None => return,
+        }
+    }
+
+    let Statement::BeginEnd { statements } = body.as_ref() else {
+        return;
+    };
+    let mut inductions = Vec::new();
+    for (pos, s) in statements.iter().enumerate() {
+        if let Statement::Assignment {
+            name,
+            expr: Expr::Binary { left, op, right },
+            line,
+        } = s
+            && matches!(left.as_ref(), Expr::Identifier(v) if v == name)
+            && let Expr::Number(c) = right.as_ref()
+            && arithmetic.holds(*c as i128)
+            && let Some(step) = match op {
+                Operator::ADD => Some(*c),
+                Operator::SUB => c.checked_neg(),
+                _ => None,
+            }
+            && count_defs(body, name) == 1
+            && !call_writes.contains(name)
+        {
+            inductions.push((pos, name.clone(), step, *line));
+        }
+    }
+
+    let mut init = Vec::new();
+    // Later positions first, so earlier insertion points stay valid
+    for (pos, var, step, inc_line) in inductions.into_iter().rev() {
+        let mut factors = BTreeSet::new();
+        let mut collect = |e: &mut Expr| collect_factors(e, &var, &mut factors);
+        for_each_condition_expr(condition, &mut collect);
+        for_each_expr(body, &mut collect);
+        // The VM would wrap a factor it cannot hold before multiplying
+        for k in factors.into_iter().filter(|&k| arithmetic.holds(k as i128)) {
+            let Some(delta) = arithmetic.fit(step as i128 * k as i128) else {
+                continue;
+            };
+            let temp = cx.fresh(&format!("{}_x{}", var, k).replace('-', "m"));
+            cx.changes += 1;
+            let mut replace = |e: &mut Expr| replace_product(e, &var, k, &temp);
+            for_each_condition_expr(condition, &mut replace);
+            for_each_expr(body, &mut replace);
+            let Statement::BeginEnd { statements } = body.as_mut() else {
+                unreachable!();
+            };
+            statements.insert(
+                pos + 1,
+                Statement::Assignment {
+                    name: temp.clone(),
+                    expr: Expr::Binary {
+                        left: Box::new(Expr::Identifier(temp.clone())),
+                        op: Operator::ADD,
+                        right: Box::new(Expr::Number(delta)),
+                    },
+                    line: inc_line,
+                },
+            );
+            init.push(Statement::Assignment {
+                name: temp,
+                expr: Expr::Binary {
+                    left: Box::new(Expr::Identifier(var.clone())),
+                    op: Operator::MUL,
+                    right: Box::new(Expr::Number(k)),
+                },
+                line: *line,
+            });
+        }
+    }
+    if !init.is_empty() {
+        guard_loop(stmt, init);
+    }
+}
+
+/// Puts `prelude` in front of the loop `stmt`, guarded by its condition so a
+/// loop that does not run still does nothing:
+/// `while c do S` becomes `if c then begin prelude; while c do S end`.
+fn guard_loop(stmt: &mut Statement, mut prelude: Vec<Statement>) {
+    let Statement::While {
+        condition, line, ..
+    } = stmt
+    else {
+        return;
+    };
+    let guard = condition.clone();
+    let line = *line;
+    prelude.push(std::mem::replace(stmt, Statement::Empty));
+    let inner = Statement::BeginEnd {
+        statements: prelude,
+    };
+    *stmt = if evaluate_condition(&guard) == Some(true) {
+        inner
+    } else {
+        Statement::If {
+            condition: guard,
+            then_stmt: Box::new(inner),
+            else_stmt: None,
+            line,
+        }
+    };
+}
+
+/// The loop in `stmt`, which is a loop or one that [`guard_loop`] has wrapped.
+fn guarded_loop(stmt: &mut Statement) -> &mut Statement {
+    match stmt {
+        Statement::If { then_stmt, .. } => guarded_loop(then_stmt),
+        Statement::BeginEnd { statements } => guarded_loop(statements.last_mut().unwrap()),
+        _ => stmt,
+    }
+}
+
+/// Loop-invariant code motion. An assignment `x := e` at the top level of the
+/// body is hoisted when `e` is invariant, `x` is assigned nowhere else in the
+/// loop and not read before it, and `e` cannot trap earlier than it used to.
+/// Hoisted code is guarded by the loop condition, see [`guard_loop`].
+fn try_licm(stmt: &mut Statement, calls: &CallEffects, arithmetic: Arithmetic) -> usize {
+    let Statement::While {
+        condition, body, ..
+    } = stmt
+    else {
+        return 0;
+    };
+    let mut modified = HashSet::new();
+    collect_modified_vars(body, &mut modified);
+    // Callees count as part of the body; without a summary, give up
+    let mut callees = Vec::new();
+    collect_callees(body, &mut callees);
+    let mut call_writes = HashSet::new();
+    for callee in &callees {
+        match calls.get(callee) {
+            Some(effect) => call_writes.extend(effect.writes.iter().cloned()),
+            None => return 0,
+        }
+    }
+    modified.extend(call_writes.iter().cloned());
+    let mut cond_vars = BTreeSet::new();
+    dataflow::condition_vars(condition, &mut cond_vars);
+
+    let mut single = Vec::new();
+    let statements = match body.as_mut() {
+        Statement::BeginEnd { statements } => statements,
+        other => {
+            single.push(std::mem::replace(other, Statement::Empty));
+            &mut single
+        }
+    };
+
+    let mut hoisted = Vec::new();
+    let mut read_before = HashSet::new();
+    let mut i = 0;
+    while i < statements.len() {
+        let hoist = match &statements[i] {
+            Statement::Assignment { name, expr, .. } => {
+                !expr_depends_on(expr, &modified)
+                    && !cond_vars.contains(name)
+                    && !read_before.contains(name)
+                    && !call_writes.contains(name)
+                    && statements.iter().map(|s| count_defs(s, name)).sum::<usize>() == 1
+                    // Everything before it was hoisted too, so a trap happens at the same point
+                    && (!may_trap(expr, arithmetic) || i == 0)
+            }
+            _ => false,
+        };
+        if hoist {
+            let s = statements.remove(i);
+            if let Statement::Assignment { name, .. } = &s {
+                modified.remove(name);
+            }
+            hoisted.push(s);
+        } else {
+            collect_read_vars(&statements[i], calls, &mut read_before);
+            i += 1;
+        }
+    }
+
+    // Put a single-statement body back, unless it was hoisted
+    if let Some(s) = single.pop() {
+        **body = s;
+    }
+    if hoisted.is_empty() {
+        return 0;
+    }
+    let count = hoisted.len();
+    guard_loop(stmt, hoisted);
+    count
+}
+
+fn collect_callees(stmt: &Statement, callees: &mut Vec<String>) {
+    match stmt {
+        Statement::Call { name, .. } => callees.push(name.clone()),
+        Statement::BeginEnd { statements } => {
+            statements.iter().for_each(|s| collect_callees(s, callees))
+        }
+        Statement::If {
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            collect_callees(then_stmt, callees);
+            if let Some(s) = else_stmt {
+                collect_callees(s, callees);
+            }
+        }
+        Statement::While { body, .. } => collect_callees(body, callees),
+        _ => {}
+    }
+}
+
+/// Number of statements in `stmt` that assign or read into `name`.
+fn count_defs(stmt: &Statement, name: &str) -> usize {
+    match stmt {
+        Statement::Assignment { name: target, .. } => usize::from(target == name),
+        Statement::Read { names, .. } => names.iter().filter(|n| *n == name).count(),
+        Statement::BeginEnd { statements } => statements.iter().map(|s| count_defs(s, name)).sum(),
+        Statement::If {
+            then_stmt,
+            else_stmt,
+            ..
+        } => count_defs(then_stmt, name) + else_stmt.as_ref().map_or(0, |s| count_defs(s, name)),
+        Statement::While { body, .. } => count_defs(body, name),
+        _ => 0,
+    }
+}
+
+/// Variables `stmt` may read, including those read by procedures it calls.
+fn collect_read_vars(stmt: &Statement, calls: &CallEffects, read: &mut HashSet<String>) {
+    let mut vars = BTreeSet::new();
+    let mut visit = |stmt: &Statement| match stmt {
+        Statement::Assignment { expr, .. } => dataflow::expr_vars(expr, &mut vars),
+        Statement::Write { exprs, .. } => {
+            exprs.iter().for_each(|e| dataflow::expr_vars(e, &mut vars))
+        }
+        Statement::Call { name, args, .. } => {
+            args.iter().for_each(|e| dataflow::expr_vars(e, &mut vars));
+            if let Some(effect) = calls.get(name) {
+                vars.extend(effect.reads.iter().cloned());
+            }
+        }
+        Statement::If { condition, .. } | Statement::While { condition, .. } => {
+            dataflow::condition_vars(condition, &mut vars)
+        }
+        _ => {}
+    };
+    visit(stmt);
+    read.extend(vars);
+    match stmt {
+        Statement::BeginEnd { statements } => {
+            for s in statements {
+                collect_read_vars(s, calls, read);
+            }
+        }
+        Statement::If {
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            collect_read_vars(then_stmt, calls, read);
+            if let Some(s) = else_stmt {
+                collect_read_vars(s, calls, read);
+            }
+        }
+        Statement::While { body, .. } => collect_read_vars(body, calls, read),
+        _ => {}
+    }
+}
+
+/// True if evaluating `expr` can fail at runtime: it divides by something not
+/// known to be non-zero, or contains constant arithmetic that overflows, which
+/// traps when the VM is set to. A trapping VM also fails on any arithmetic
+/// whose operands are not known.
+fn may_trap(expr: &Expr, arithmetic: Arithmetic) -> bool {
+    let mut overflows = Vec::new();
+    collect_overflows(expr, arithmetic, &mut overflows);
+    !overflows.is_empty()
+        || divides_by_unknown(expr)
+        || (arithmetic.overflow == Overflow::Trap && may_overflow(expr))
+}
+
+/// True if `expr` does arithmetic on a value that is not a constant, so its
+/// result may leave the word whatever the constants are.
+fn may_overflow(expr: &Expr) -> bool {
+    match expr {
+        Expr::Binary { left, op, right } => {
+            let unknown = match (left.as_ref(), right.as_ref()) {
+                (Expr::Number(_), Expr::Number(_)) => false,
+                // Only i64::MIN / -1 leaves the word
+                (_, Expr::Number(r)) if *op == Operator::DIV => *r == -1,
+                _ => true,
+            };
+            unknown || may_overflow(left) || may_overflow(right)
+        }
+        Expr::Unary { op, expr } => {
+            (*op == Operator::NEG && !matches!(expr.as_ref(), Expr::Number(_)))
+                || may_overflow(expr)
+        }
+        _ => false,
+    }
+}
+
+fn divides_by_unknown(expr: &Expr) -> bool {
+    match expr {
+        Expr::Binary { left, op, right } => {
+            (*op == Operator::DIV && !matches!(right.as_ref(), Expr::Number(n) if *n != 0))
+                || divides_by_unknown(left)
+                || divides_by_unknown(right)
+        }
+        Expr::Unary { expr, .. } => divides_by_unknown(expr),
+        _ => false,
+    }
+}
+
+fn collect_modified_vars(stmt: &Statement, modified: &mut HashSet<String>) {
+    match stmt {
+        Statement::Assignment { name, .. } => {
+            modified.insert(name.clone());
+        }
+        Statement::Read { names, .. } => {
+            for n in names {
+                modified.insert(n.clone());
+            }
+        }
+        Statement::BeginEnd { statements } => {
+            for s in statements {
+                collect_modified_vars(s, modified);
+            }
+        }
+        Statement::If {
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            collect_modified_vars(then_stmt, modified);
+            if let Some(s) = else_stmt {
+                collect_modified_vars(s, modified);
+            }
+        }
+        Statement::While { body, .. } => {
+            collect_modified_vars(body, modified);
+        }
+        _ => {}
+    }
+}
+
+fn expr_depends_on(expr: &Expr, vars: &HashSet<String>) -> bool {
+    match expr {
+        Expr::Binary { left, right, .. } => {
+            expr_depends_on(left, vars) || expr_depends_on(right, vars)
+        }
+        Expr::Unary { expr, .. } => expr_depends_on(expr, vars),
+        Expr::Identifier(name) => vars.contains(name),
+        _ => false,
+    }
+}
+
+/// `l op r`, unless an operand or the result is not a word of `arithmetic`,
+/// or it divides by zero.
+fn fold_binary(op: Operator, l: i64, r: i64, arithmetic: Arithmetic) -> Option<i64> {
+    let (l, r) = (l as i128, r as i128);
+    let value = match op {
+        Operator::ADD => l + r,
+        Operator::SUB => l - r,
+        Operator::MUL => l * r,
+        Operator::DIV => l.checked_div(r)?,
+        _ => return None,
+    };
+    [l, r, value]
+        .iter()
+        .all(|&v| arithmetic.holds(v))
+        .then_some(value as i64)
+}
+
+/// `-val`, unless the operand or the result is not a word of `arithmetic`.
+fn fold_neg(val: i64, arithmetic: Arithmetic) -> Option<i64> {
+    let neg = -(val as i128);
+    (arithmetic.holds(val as i128) && arithmetic.holds(neg)).then_some(neg as i64)
+}
+
+/// The constant operations in `expr` that [`optimize_expr`] leaves alone because they overflow.
+fn collect_overflows(expr: &Expr, arithmetic: Arithmetic, out: &mut Vec<String>) {
+    match expr {
+        Expr::Binary { left, op, right } => {
+            if let (Expr::Number(l), Expr::Number(r)) = (left.as_ref(), right.as_ref())
+                && *r != 0
+                && fold_binary(*op, *l, *r, arithmetic).is_none()
+            {
+                out.push(expr.to_string());
+            }
+            collect_overflows(left, arithmetic, out);
+            collect_overflows(right, arithmetic, out);
+        }
+        Expr::Unary { op, expr: inner } => {
+            if *op == Operator::NEG
+                && let Expr::Number(val) = inner.as_ref()
+                && fold_neg(*val, arithmetic).is_none()
+            {
+                out.push(expr.to_string());
+            }
+            collect_overflows(inner, arithmetic, out);
+        }
+        _ => {}
+    }
+}
+
+fn statement_overflows(stmt: &mut Statement, out: &mut Vec<String>) {
+    let line = match stmt {
+        Statement::Assignment { line, .. }
+        | Statement::Call { line, .. }
+        | Statement::If { line, .. }
+        | Statement::While { line, .. }
+        | Statement::Write { line, .. }
+        | Statement::Read { line, .. } => *line,
+        Statement::BeginEnd { statements } => {
+            statements.iter_mut().for_each(|s| statement_overflows(s, out));
+            return;
+        }
+        Statement::Empty => return,
+    };
+    let mut found = Vec::new();
+    // The lint is about 64-bit arithmetic, whatever the VM is set to
+    let mut collect = |e: &mut Expr| collect_overflows(e, Arithmetic::default(), &mut found);
+    // Only this statement's own expressions; nested statements report their own lines
+    match stmt {
+        Statement::If {
+            condition,
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            for_each_condition_expr(condition, &mut collect);
+            statement_overflows(then_stmt, out);
+            if let Some(s) = else_stmt {
+                statement_overflows(s, out);
+            }
+        }
+        Statement::While {
+            condition, body, ..
+        } => {
+            for_each_condition_expr(condition, &mut collect);
+            statement_overflows(body, out);
+        }
+        _ => for_each_expr(stmt, &mut collect),
+    }
+    for e in found {
+        out.push(format!(
+            "line {}: '{}' overflows a 64-bit integer, so it is left for the VM to evaluate",
+            line, e
+        ));
+    }
+}
+
+fn block_overflows(block: &mut Block, out: &mut Vec<String>) {
+    statement_overflows(&mut block.statement, out);
+    for proc_decl in &mut block.procedures {
+        block_overflows(&mut proc_decl.block, out);
+    }
+}
+
+/// Warnings for constant arithmetic that overflows, including arithmetic on
+/// constants and on variables whose value propagation knows.
+pub fn overflow_warnings(program: &Program) -> Vec<String> {
+    // Lint the program as written too, in case propagation simplifies
+    // an overflow away
+    let mut program = program.clone();
+    let mut warnings = Vec::new();
+    block_overflows(&mut program.block, &mut warnings);
+    for _ in 0..MAX_LINT_ROUNDS {
+        if propagate::run(&mut program, Arithmetic::default()) == 0 {
+            break;
+        }
+    }
+    let mut propagated = Vec::new();
+    block_overflows(&mut program.block, &mut propagated);
+    for warning in propagated {
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    }
+    warnings
+}
+
+fn optimize_expr(expr: &mut Expr, arithmetic: Arithmetic) {
+    match expr {
+        Expr::Binary { left, op, right } => {
+            optimize_expr(left, arithmetic);
+            optimize_expr(right, arithmetic);
+
+            // Constant folding; what would overflow or divide by zero is left to the VM
+            if let (Expr::Number(l), Expr::Number(r)) = (left.as_ref(), right.as_ref()) {
+                if let Some(val) = fold_binary(*op, *l, *r, arithmetic) {
+                    *expr = Expr::Number(val);
+                }
+                return;
+            }
+
+            // Algebraic Simplification
+            // x + 0 = x
+            if *op == Operator::ADD {
+                if let Expr::Number(0) = right.as_ref() {
+                    *expr = *left.clone();
+                    return;
+                }
+                if let Expr::Number(0) = left.as_ref() {
+                    *expr = *right.clone();
+                    return;
+                }
+            }
+            // x - 0 = x
+            if *op == Operator::SUB
+                && let Expr::Number(0) = right.as_ref() {
+                    *expr = *left.clone();
+                    return;
+                }
+            // x * 1 = x, x * 0 = 0 unless evaluating x could fail
+            if *op == Operator::MUL {
+                if let Expr::Number(1) = right.as_ref() {
+                    *expr = *left.clone();
+                    return;
+                }
+                if let Expr::Number(1) = left.as_ref() {
+                    *expr = *right.clone();
+                    return;
+                }
+                if let Expr::Number(0) = right.as_ref()
+                    && !may_trap(left, arithmetic)
+                {
+                    *expr = Expr::Number(0);
+                    return;
+                }
+                if let Expr::Number(0) = left.as_ref()
+                    && !may_trap(right, arithmetic)
+                {
+                    *expr = Expr::Number(0);
+                    return;
+                }
+                // Without a shift instruction, the only cheaper forms of x * 2^k are for
+                // k = 1 on a plain variable (no LIT, ADD instead of MUL) and the sign flip
+                let (factor, other) = match (left.as_ref(), right.as_ref()) {
+                    (Expr::Number(n), other) | (other, Expr::Number(n)) => (*n, other.clone()),
+                    _ => return,
+                };
+                match (factor, &other) {
+                    (2, Expr::Identifier(_)) => {
+                        *expr = Expr::Binary {
+                            left: Box::new(other.clone()),
+                            op: Operator::ADD,
+                            right: Box::new(other),
+                        };
+                    }
+                    (-1, _) => {
+                        *expr = Expr::Unary {
+                            op: Operator::NEG,
+                            expr: Box::new(other),
+                        };
+                    }
+                    _ => {}
+                }
+                return;
+            }
+            // x / 1 = x
+            if *op == Operator::DIV
+                && let Expr::Number(1) = right.as_ref() {
+                    *expr = *left.clone();
+                }
+        }
+        Expr::Unary { op, expr: inner } => {
+            optimize_expr(inner, arithmetic);
+            if let Expr::Number(val) = inner.as_ref()
+                && *op == Operator::NEG
+                && let Some(neg) = fold_neg(*val, arithmetic) {
+                    *expr = Expr::Number(neg);
+                }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, sexpr};
+
+    fn optimized(source: &str) -> String {
+        optimized_on(source, Arithmetic::default())
+    }
+
+    fn optimized_on(source: &str, arithmetic: Arithmetic) -> String {
+        let mut program = compiler::parse(source, false).unwrap();
+        // Keep calls as calls; inlining has its own tests
+        let mut options = OptimizeOptions {
+            arithmetic,
+            ..Default::default()
+        };
+        options.passes.retain(|&p| p != Pass::Inline);
+        optimize_ast_with(&mut program, &options);
+        sexpr::to_string(&program)
+    }
+
+    #[test]
+    fn test_licm_guards_hoisted_code() {
+        let text = optimized(
+            "program t; var n, i, a, b, s;
+begin
+  read(n); i := 0; s := 0;
+  while i < n do begin a := n + 1; b := a * 3; s := s + b; i := i + 1 end
+end.",
+        );
+        // i := 0 is propagated into the guard
+        let guard = text.find("(if (< 0 n)").expect(&text);
+        let a = text.find("(:= a (+ n 1)").expect(&text);
+        let b = text.find("(:= b (* a 3)").expect(&text);
+        let body = text.find("(while (< i n)").expect(&text);
+        assert!(guard < a && a < b && b < body, "{}", text);
+    }
+
+    #[test]
+    fn test_licm_hazards_block_hoisting() {
+        for source in [
+            // x read before it is assigned
+            "program t; var n, i, x, s; begin read(n); while i < n do begin s := s + x; x := n; i := i + 1 end end.",
+            // x assigned twice
+            "program t; var n, i, x; begin read(n); while i < n do begin x := n; x := x * i; i := i + 1 end; write(x) end.",
+            // division could trap before the write
+            "program t; var n, i, x; begin read(n); while i < n do begin write(i); x := 1 / n; i := i + 1 end end.",
+            // the call might change n
+            "program t; var n, i, x; procedure p; begin n := 0 end; begin read(n); while i < n do begin x := n * 2; call p; i := i + 1 end end.",
+        ] {
+            let text = optimized(source);
+            assert!(!text.contains("(if (< i n)"), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_calls_use_mod_ref_summaries() {
+        let wrap = Arithmetic {
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        };
+        // Wrapping, so k + 1 cannot trap after the call
+        let text = optimized_on(
+            "program t; var n, i, x, k, c;
+procedure log; begin write(k) end;
+procedure tick; begin c := c + 1 end;
+begin
+  read(n, k);
+  c := 0;
+  x := n * k;
+  call log;
+  write(n * k);
+  i := 0;
+  while i < n do begin call tick; x := k + 1; i := i + 1 end;
+  write(x, c)
+end.",
+            wrap,
+        );
+        // log writes nothing, so n * k is still in x
+        assert!(text.contains("(write x :line 9)"), "{}", text);
+        // tick only writes c, so x := k + 1 can leave the loop
+        assert!(text.contains("(:= x (+ k 1) :line 11)\n          (while"), "{}", text);
+        assert!(text.contains("(call log"), "{}", text);
+    }
+
+    #[test]
+    fn test_induction_variables_are_strength_reduced() {
+        let source = "program t; var i, n, a, j;
+begin
+  read(n, j);
+  i := 0;
+  while i < n do begin a := i * 4; write(a, 2 * j, j * (-1)); i := i + 1 end;
+  while j < n do begin write(j * 4); j := j + 1; j := j + 1 end
+end.";
+        let wrap = Arithmetic {
+            overflow: Overflow::Wrap,
+            ..Default::default()
+        };
+        let text = optimized_on(source, wrap);
+        // i * 4 follows i in steps of 4 and starts out at 0 * 4, if the loop runs at all
+        let init = "(if (< 0 n)\n        (begin\n          (:= i_x4 0 :line 5)\n          (while";
+        assert!(text.contains(init), "{}", text);
+        assert!(text.contains("(write i_x4 (+ j j) (- j)"), "{}", text);
+        let update = "(:= i (+ i 1) :line 5)\n              (:= i_x4 (+ i_x4 4) :line 5)";
+        assert!(text.contains(update), "{}", text);
+        // j is assigned twice in its loop, so it is not a basic induction variable
+        assert!(text.contains("(write (* j 4)"), "{}", text);
+
+        // Products the program never computed could trap or saturate
+        for overflow in [Overflow::Trap, Overflow::Saturate] {
+            let text = optimized_on(source, Arithmetic { overflow, ..wrap });
+            assert!(!text.contains("i_x4"), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_overflowing_constants_are_left_unfolded() {
+        let source = "program t; const big = 9223372036854775807; var x, y;
+begin
+  x := big + 1;
+  y := big;
+  write(y * 3, 0 - big - 1, big / 1);
+  if x < 0 - (0 - big - 1) then write(1)
+end.";
+        let text = optimized(source);
+        assert!(text.contains("(:= x (+ 9223372036854775807 1)"), "{}", text);
+        assert!(text.contains("(write (* 9223372036854775807 3) -9223372036854775808 9223372036854775807"), "{}", text);
+
+        let program = compiler::parse(source, false).unwrap();
+        let warnings = overflow_warnings(&program);
+        assert_eq!(
+            warnings,
+            vec![
+                "line 3: '9223372036854775807 + 1' overflows a 64-bit integer, so it is left for the VM to evaluate",
+                "line 5: '9223372036854775807 * 3' overflows a 64-bit integer, so it is left for the VM to evaluate",
+                "line 6: '0 - (-9223372036854775808)' overflows a 64-bit integer, so it is left for the VM to evaluate",
+            ]
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Interprocedural side-effect (mod/ref) analysis.
+//!
+//! For every procedure this computes the non-local variables it may read and
+//! write, directly or through the procedures it calls, iterating over the call
+//! graph until recursion settles. Variables are identified by the procedure
+//! that declares them, so shadowed names never get mixed up.
+
+use super::dataflow::{Effects, condition_vars, expr_vars};
+use crate::ast::Statement;
+use crate::cfg::{Cfg, Terminator};
+use std::collections::{BTreeSet, HashMap};
+
+/// A variable, identified by the index of its declaring procedure and its name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VarRef {
+    pub owner: usize,
+    pub name: String,
+}
+
+/// What one call does to the variables the caller can name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallEffect {
+    pub reads: BTreeSet<String>,
+    pub writes: BTreeSet<String>,
+}
+
+/// Effects of calling each procedure the caller can call, by procedure name.
+pub type CallEffects = HashMap<String, CallEffect>;
+
+#[derive(Debug, Clone, Default)]
+pub struct ProcEffects {
+    /// Indices of the procedures called directly.
+    pub calls: BTreeSet<usize>,
+    /// Non-local variables read, including through calls.
+    pub reads: BTreeSet<VarRef>,
+    /// Non-local variables written, including through calls.
+    pub writes: BTreeSet<VarRef>,
+}
+
+/// Summaries for every procedure, indexed like [`cfg::build_program`](crate::cfg::build_program).
+#[derive(Debug, Clone)]
+pub struct ModRef {
+    pub procedures: Vec<ProcEffects>,
+}
+
+/// The procedure that declares the variable `name` seen from procedure `index`.
+fn resolve_var(cfgs: &[Cfg], index: usize, name: &str) -> Option<usize> {
+    let declares = |cfg: &Cfg| cfg.params.iter().chain(&cfg.locals).any(|v| v == name);
+    if declares(&cfgs[index]) {
+        return Some(index);
+    }
+    if !cfgs[index].outer_vars.iter().any(|v| v == name) {
+        return None;
+    }
+    let mut scope = cfgs[index].parent;
+    while let Some(j) = scope {
+        if declares(&cfgs[j]) {
+            return Some(j);
+        }
+        scope = cfgs[j].parent;
+    }
+    None
+}
+
+/// The procedure a `call name` in procedure `index` refers to.
+fn resolve_proc(cfgs: &[Cfg], index: usize, name: &str) -> Option<usize> {
+    let mut scope = Some(index);
+    while let Some(j) = scope {
+        if let Some(k) =
+            (0..cfgs.len()).find(|&k| cfgs[k].parent == Some(j) && cfgs[k].name == name)
+        {
+            return Some(k);
+        }
+        scope = cfgs[j].parent;
+    }
+    None
+}
+
+fn direct_effects(cfgs: &[Cfg], index: usize) -> ProcEffects {
+    let cfg = &cfgs[index];
+    let mut reads = BTreeSet::new();
+    let mut writes = BTreeSet::new();
+    let mut callees = Vec::new();
+    for block in &cfg.blocks {
+        for node in &block.nodes {
+            match &node.stmt {
+                Statement::Assignment { name, expr, .. } => {
+                    writes.insert(name.clone());
+                    expr_vars(expr, &mut reads);
+                }
+                Statement::Read { names, .. } => writes.extend(names.iter().cloned()),
+                Statement::Write { exprs, .. } => {
+                    exprs.iter().for_each(|e| expr_vars(e, &mut reads))
+                }
+                Statement::Call { name, args, .. } => {
+                    args.iter().for_each(|e| expr_vars(e, &mut reads));
+                    callees.push(name.clone());
+                }
+                _ => {}
+            }
+        }
+        if let Terminator::Branch { condition, .. } = &block.terminator {
+            condition_vars(condition, &mut reads);
+        }
+    }
+    let non_local = |names: BTreeSet<String>| -> BTreeSet<VarRef> {
+        names
+            .into_iter()
+            .filter_map(|name| {
+                resolve_var(cfgs, index, &name)
+                    .filter(|&owner| owner != index)
+                    .map(|owner| VarRef { owner, name })
+            })
+            .collect()
+    };
+    ProcEffects {
+        calls: callees
+            .iter()
+            .filter_map(|c| resolve_proc(cfgs, index, c))
+            .collect(),
+        reads: non_local(reads),
+        writes: non_local(writes),
+    }
+}
+
+/// Computes the summaries, propagating callee effects to callers until nothing changes.
+pub fn analyze(cfgs: &[Cfg]) -> ModRef {
+    let mut procedures: Vec<ProcEffects> =
+        (0..cfgs.len()).map(|i| direct_effects(cfgs, i)).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..procedures.len() {
+            let mut reads = procedures[i].reads.clone();
+            let mut writes = procedures[i].writes.clone();
+            for &k in &procedures[i].calls {
+                // The caller's own variables are local to it, not part of its summary
+                reads.extend(procedures[k].reads.iter().filter(|v| v.owner != i).cloned());
+                writes.extend(
+                    procedures[k]
+                        .writes
+                        .iter()
+                        .filter(|v| v.owner != i)
+                        .cloned(),
+                );
+            }
+            if reads != procedures[i].reads || writes != procedures[i].writes {
+                procedures[i].reads = reads;
+                procedures[i].writes = writes;
+                changed = true;
+            }
+        }
+    }
+    ModRef { procedures }
+}
+
+/// [`Effects`] for every procedure, in the same order as `cfgs`.
+pub fn program_effects(cfgs: &[Cfg]) -> Vec<Effects> {
+    let modref = analyze(cfgs);
+    (0..cfgs.len()).map(|i| modref.effects(cfgs, i)).collect()
+}
+
+impl ModRef {
+    /// Effects of every call procedure `index` can make, in terms of the names it sees.
+    pub fn call_effects(&self, cfgs: &[Cfg], index: usize) -> CallEffects {
+        let visible = |vars: &BTreeSet<VarRef>| -> BTreeSet<String> {
+            vars.iter()
+                .filter(|v| resolve_var(cfgs, index, &v.name) == Some(v.owner))
+                .map(|v| v.name.clone())
+                .collect()
+        };
+        self.procedures[index]
+            .calls
+            .iter()
+            .map(|&k| {
+                let effect = CallEffect {
+                    reads: visible(&self.procedures[k].reads),
+                    writes: visible(&self.procedures[k].writes),
+                };
+                (cfgs[k].name.clone(), effect)
+            })
+            .collect()
+    }
+
+    /// Statement effects for procedure `index`, with calls described by the summaries.
+    pub fn effects(&self, cfgs: &[Cfg], index: usize) -> Effects {
+        Effects::with_calls(&cfgs[index], self.call_effects(cfgs, index))
+    }
+
+    /// Human-readable listing, one procedure per paragraph.
+    pub fn dump(&self, cfgs: &[Cfg]) -> String {
+        let var = |v: &VarRef| format!("{}.{}", cfgs[v.owner].name, v.name);
+        let mut out = String::new();
+        for (i, p) in self.procedures.iter().enumerate() {
+            out.push_str(&format!("procedure {}", cfgs[i].name));
+            if let Some(parent) = cfgs[i].parent {
+                out.push_str(&format!(" (in {})", cfgs[parent].name));
+            }
+            out.push('\n');
+            let calls: Vec<String> = p.calls.iter().map(|&k| cfgs[k].name.clone()).collect();
+            let rows = [
+                ("calls: ", calls.join(" ")),
+                (
+                    "reads: ",
+                    p.reads.iter().map(var).collect::<Vec<_>>().join(" "),
+                ),
+                (
+                    "writes:",
+                    p.writes.iter().map(var).collect::<Vec<_>>().join(" "),
+                ),
+            ];
+            for (label, value) in rows {
+                out.push_str(format!("  {} {}", label, value).trim_end());
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cfg, compiler};
+
+    #[test]
+    fn test_transitive_and_recursive_effects() {
+        let program = compiler::parse(
+            "program t; var a, b, c, n;
+procedure outer;
+  var x;
+  procedure inner; begin x := a; b := x end;
+  begin call inner; write(x) end;
+procedure rec;
+  begin if n > 0 then begin n := n - 1; call rec end else call outer end;
+procedure pure; var a; begin a := 1; write(a) end;
+begin
+  read(n);
+  call rec;
+  call pure
+end.",
+            false,
+        )
+        .unwrap();
+        let cfgs = cfg::build_program(&program);
+        let names: Vec<&str> = cfgs.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["t", "outer", "inner", "rec", "pure"]);
+        let modref = analyze(&cfgs);
+        let refs = |vars: &BTreeSet<VarRef>| -> Vec<String> {
+            vars.iter()
+                .map(|v| format!("{}.{}", cfgs[v.owner].name, v.name))
+                .collect()
+        };
+
+        // inner touches outer's x, but that is local to outer
+        assert_eq!(refs(&modref.procedures[2].writes), vec!["t.b", "outer.x"]);
+        assert_eq!(refs(&modref.procedures[1].writes), vec!["t.b"]);
+        assert_eq!(refs(&modref.procedures[1].reads), vec!["t.a"]);
+        // rec reaches outer through recursion
+        assert_eq!(refs(&modref.procedures[3].writes), vec!["t.b", "t.n"]);
+        assert_eq!(refs(&modref.procedures[3].reads), vec!["t.a", "t.n"]);
+        // pure's a shadows the global
+        assert!(modref.procedures[4].reads.is_empty() && modref.procedures[4].writes.is_empty());
+
+        let calls = modref.call_effects(&cfgs, 0);
+        assert_eq!(
+            calls["rec"].writes,
+            BTreeSet::from(["b".to_string(), "n".to_string()])
+        );
+        assert_eq!(calls["pure"], CallEffect::default());
+
+        let dump = modref.dump(&cfgs);
+        assert!(dump.contains("procedure inner (in outer)\n  calls:\n  reads:  t.a outer.x\n  writes: t.b outer.x\n"), "{}", dump);
+    }
+}
@@ -0,0 +1,299 @@
+//! The pass manager.
+//!
+//! Every optimization is a named [`Pass`]. A pipeline runs its passes in
+//! order, over and over, until a whole round leaves the program unchanged,
+//! and records for each pass how often it ran, what it reported doing, and
+//! how many statements and expression nodes disappeared while it ran.
+
+use super::{Rewrites, cse, dse, inline, propagate, simplify, unroll};
+use crate::ast::{Block, Condition, Expr, Program, Statement};
+use crate::sexpr;
+use std::fmt;
+
+/// Upper bound on rounds over the pipeline; most programs settle in two or three.
+const MAX_ROUNDS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pass {
+    /// Substitutes small procedures at their call sites.
+    Inline,
+    /// Folds constant expressions and simplifies algebraic identities.
+    Fold,
+    /// Removes branches and loops whose condition is constant, and empty statements.
+    Dce,
+    /// Global constant and copy propagation.
+    Propagate,
+    /// Strength reduction of induction variables.
+    Sr,
+    /// Loop-invariant code motion.
+    Licm,
+    /// Unrolls loops with a known trip count.
+    Unroll,
+    /// Global common subexpression elimination.
+    Cse,
+    /// Dead store and unused variable elimination.
+    Dse,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 9] = [
+        Pass::Inline,
+        Pass::Fold,
+        Pass::Dce,
+        Pass::Propagate,
+        Pass::Sr,
+        Pass::Licm,
+        Pass::Unroll,
+        Pass::Cse,
+        Pass::Dse,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Inline => "inline",
+            Pass::Fold => "fold",
+            Pass::Dce => "dce",
+            Pass::Propagate => "propagate",
+            Pass::Sr => "sr",
+            Pass::Licm => "licm",
+            Pass::Unroll => "unroll",
+            Pass::Cse => "cse",
+            Pass::Dse => "dse",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pass> {
+        Pass::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    /// The passes of optimization level `level`, in pipeline order. Level 0 runs
+    /// nothing, 1 the cheap cleanups, 2 everything that does not grow the code
+    /// much, and 3 adds loop unrolling.
+    pub fn level(level: u8) -> Vec<Pass> {
+        let passes: &[Pass] = match level {
+            0 => &[],
+            1 => &[Pass::Fold, Pass::Dce, Pass::Propagate, Pass::Dse],
+            2 => &[
+                Pass::Inline,
+                Pass::Fold,
+                Pass::Dce,
+                Pass::Propagate,
+                Pass::Sr,
+                Pass::Licm,
+                Pass::Cse,
+                Pass::Dse,
+            ],
+            _ => &Pass::ALL,
+        };
+        passes.to_vec()
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What one pass did over all of its runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PassStats {
+    pub pass: Pass,
+    pub runs: usize,
+    /// The pass's own count: calls inlined, uses propagated, stores removed, and so on.
+    pub changes: usize,
+    /// Statements (other than `begin ... end` and empty ones) that disappeared.
+    pub statements_removed: usize,
+    /// Expression nodes that disappeared.
+    pub expressions_folded: usize,
+}
+
+fn count_expr(expr: &Expr) -> usize {
+    match expr {
+        Expr::Binary { left, right, .. } => 1 + count_expr(left) + count_expr(right),
+        Expr::Unary { expr, .. } => 1 + count_expr(expr),
+        Expr::Number(_) | Expr::Identifier(_) => 1,
+    }
+}
+
+fn count_condition(cond: &Condition) -> usize {
+    match cond {
+        Condition::Odd { expr } => count_expr(expr),
+        Condition::Compare { left, right, .. } => count_expr(left) + count_expr(right),
+    }
+}
+
+/// `(statements, expression nodes)` in `stmt`.
+fn count_statement(stmt: &Statement) -> (usize, usize) {
+    let sum = |stmts: &mut dyn Iterator<Item = &Statement>| {
+        stmts
+            .map(count_statement)
+            .fold((0, 0), |(s, e), (s2, e2)| (s + s2, e + e2))
+    };
+    match stmt {
+        Statement::Assignment { expr, .. } => (1, count_expr(expr)),
+        Statement::Write { exprs, .. } | Statement::Call { args: exprs, .. } => {
+            (1, exprs.iter().map(count_expr).sum())
+        }
+        Statement::Read { .. } => (1, 0),
+        Statement::BeginEnd { statements } => sum(&mut statements.iter()),
+        Statement::If {
+            condition,
+            then_stmt,
+            else_stmt,
+            ..
+        } => {
+            let (s, e) = sum(&mut std::iter::once(then_stmt.as_ref()).chain(else_stmt.as_deref()));
+            (s + 1, e + count_condition(condition))
+        }
+        Statement::While {
+            condition, body, ..
+        } => {
+            let (s, e) = count_statement(body);
+            (s + 1, e + count_condition(condition))
+        }
+        Statement::Empty => (0, 0),
+    }
+}
+
+fn count_block(block: &Block) -> (usize, usize) {
+    block
+        .procedures
+        .iter()
+        .map(|p| count_block(&p.block))
+        .fold(count_statement(&block.statement), |(s, e), (s2, e2)| {
+            (s + s2, e + e2)
+        })
+}
+
+/// Runs `pass` once and returns its own count of changes.
+fn run_pass(pass: Pass, program: &mut Program, options: &super::OptimizeOptions) -> (usize, usize) {
+    let only = |rewrite: fn(&mut Rewrites)| {
+        let mut rewrites = Rewrites::default();
+        rewrite(&mut rewrites);
+        rewrites
+    };
+    match pass {
+        Pass::Inline => (inline::run(program, options.inline_limit), 0),
+        Pass::Fold => (simplify(program, only(|r| r.fold = true), options.arithmetic), 0),
+        Pass::Dce => (simplify(program, only(|r| r.dce = true), options.arithmetic), 0),
+        Pass::Propagate => (propagate::run(program, options.arithmetic), 0),
+        Pass::Sr => (simplify(program, only(|r| r.sr = true), options.arithmetic), 0),
+        Pass::Licm => (simplify(program, only(|r| r.licm = true), options.arithmetic), 0),
+        Pass::Unroll => (unroll::run(program, options.unroll_budget, options.arithmetic), 0),
+        Pass::Cse => (cse::run(program), 0),
+        Pass::Dse => dse::run(program, options.arithmetic),
+    }
+}
+
+/// Runs the pipeline in `options` to a fixed point.
+pub(super) fn run(program: &mut Program, options: &super::OptimizeOptions) -> super::OptimizeStats {
+    let mut stats = super::OptimizeStats {
+        passes: options
+            .passes
+            .iter()
+            .map(|&pass| PassStats {
+                pass,
+                runs: 0,
+                changes: 0,
+                statements_removed: 0,
+                expressions_folded: 0,
+            })
+            .collect(),
+        ..Default::default()
+    };
+    for round in 1..=MAX_ROUNDS {
+        let start = program.clone();
+        for (i, &pass) in options.passes.iter().enumerate() {
+            let (statements, exprs) = count_block(&program.block);
+            let (changes, slots) = run_pass(pass, program, options);
+            let (statements_after, exprs_after) = count_block(&program.block);
+
+            let entry = &mut stats.passes[i];
+            entry.runs += 1;
+            entry.changes += changes;
+            entry.statements_removed += statements.saturating_sub(statements_after);
+            entry.expressions_folded += exprs.saturating_sub(exprs_after);
+            match pass {
+                Pass::Inline => stats.inlined_calls += changes,
+                Pass::Unroll => stats.unrolled_loops += changes,
+                Pass::Dse => {
+                    stats.dead_stores += changes;
+                    stats.removed_slots += slots;
+                }
+                _ => {}
+            }
+            if options.print_after.contains(&pass) {
+                let header = format!("after {} (round {})", pass, round);
+                stats.dumps.push((header, sexpr::to_string(program)));
+            }
+        }
+        if *program == start {
+            break;
+        }
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+    use crate::optimizer::{OptimizeOptions, optimize_ast_with};
+
+    const SOURCE: &str = "program t; var x, y, z;
+begin
+  read(x);
+  y := 2 * 3 + 0;
+  if y > 10 then write(y);
+  z := y + 1;
+  z := x;
+  write(z)
+end.";
+
+    #[test]
+    fn test_selected_passes_and_stats() {
+        let mut program = compiler::parse(SOURCE, false).unwrap();
+        let options = OptimizeOptions {
+            passes: vec![Pass::Fold],
+            ..Default::default()
+        };
+        let stats = optimize_ast_with(&mut program, &options);
+        let text = sexpr::to_string(&program);
+        // Folding alone leaves the dead branch and the dead store in place
+        assert!(text.contains("(:= y 6"), "{}", text);
+        assert!(text.contains("(if (> y 10)"), "{}", text);
+        assert_eq!(stats.passes.len(), 1);
+        // 2 * 3 + 0 loses four nodes; the second round changes nothing
+        assert_eq!(stats.passes[0].expressions_folded, 4);
+        assert_eq!(stats.passes[0].runs, 2);
+
+        let mut program = compiler::parse(SOURCE, false).unwrap();
+        let options = OptimizeOptions {
+            passes: Pass::level(1),
+            print_after: vec![Pass::Dce],
+            ..Default::default()
+        };
+        let stats = optimize_ast_with(&mut program, &options);
+        let text = sexpr::to_string(&program);
+        assert!(!text.contains("(if"), "{}", text);
+        assert!(!text.contains("(:= z (+"), "{}", text);
+        let dce = stats.passes.iter().find(|p| p.pass == Pass::Dce).unwrap();
+        assert_eq!(dce.statements_removed, 2);
+        assert_eq!(stats.dead_stores, stats.passes[3].changes);
+        assert!(stats.dead_stores >= 1);
+        assert_eq!(stats.dumps[0].0, "after dce (round 1)");
+        assert_eq!(stats.dumps.len(), stats.passes[1].runs);
+    }
+
+    #[test]
+    fn test_pass_names_and_levels() {
+        for pass in Pass::ALL {
+            assert_eq!(Pass::from_name(pass.name()), Some(pass));
+        }
+        assert_eq!(Pass::from_name("dag"), None);
+        assert!(Pass::level(0).is_empty());
+        assert!(!Pass::level(2).contains(&Pass::Unroll));
+        assert_eq!(Pass::level(3), Pass::ALL.to_vec());
+    }
+}
@@ -0,0 +1,307 @@
+//! Global constant and copy propagation.
+//!
+//! A forward analysis records facts of the form `x = 5` or `x = y` that hold
+//! on every path reaching a statement. Uses of `x` are then replaced by the
+//! constant or the copied variable and the expression is folded again, which
+//! lets `evaluate_condition` decide many more branches.
+
+use super::dataflow::{self, Analysis, Direction, Effects};
+use super::modref;
+use super::optimize_expr;
+use crate::ast::{Block, Condition, Expr, Program, Statement};
+use crate::cfg::{self, Cfg, Node, StmtId};
+use crate::vm::Arithmetic;
+use std::collections::{BTreeMap, HashMap};
+
+/// Known values of variables; `None` means the point has not been reached yet.
+type Facts = Option<BTreeMap<String, Expr>>;
+
+struct Propagation {
+    effects: Effects,
+    /// Declared constants visible in the procedure; they hold everywhere.
+    consts: BTreeMap<String, Expr>,
+    arithmetic: Arithmetic,
+}
+
+fn substitute(expr: &mut Expr, facts: &BTreeMap<String, Expr>) -> usize {
+    match expr {
+        Expr::Identifier(name) => match facts.get(name) {
+            Some(value) => {
+                *expr = value.clone();
+                1
+            }
+            None => 0,
+        },
+        Expr::Binary { left, right, .. } => substitute(left, facts) + substitute(right, facts),
+        Expr::Unary { expr, .. } => substitute(expr, facts),
+        Expr::Number(_) => 0,
+    }
+}
+
+impl Propagation {
+    /// The value `name := expr` gives `name`, if it is a constant or another name.
+    fn value(&self, name: &str, expr: &Expr, facts: &BTreeMap<String, Expr>) -> Option<Expr> {
+        let mut value = expr.clone();
+        substitute(&mut value, facts);
+        optimize_expr(&mut value, self.arithmetic);
+        match &value {
+            Expr::Number(_) => Some(value),
+            Expr::Identifier(other) if other != name => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl Analysis for Propagation {
+    type Fact = Facts;
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn boundary(&self, _cfg: &Cfg) -> Self::Fact {
+        Some(self.consts.clone())
+    }
+
+    fn top(&self, _cfg: &Cfg) -> Self::Fact {
+        None
+    }
+
+    fn meet(&self, a: &Self::Fact, b: &Self::Fact) -> Self::Fact {
+        match (a, b) {
+            (None, other) | (other, None) => other.clone(),
+            (Some(a), Some(b)) => Some(
+                a.iter()
+                    .filter(|(var, value)| b.get(*var) == Some(value))
+                    .map(|(var, value)| (var.clone(), value.clone()))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn transfer(&self, node: &Node, fact: &Self::Fact) -> Self::Fact {
+        let facts = fact.as_ref()?;
+        let defs = self.effects.may_defs(&node.stmt);
+        let mut out: BTreeMap<String, Expr> = facts
+            .iter()
+            .filter(|(var, value)| {
+                !defs.contains(var)
+                    && !matches!(value, Expr::Identifier(source) if defs.contains(source))
+            })
+            .map(|(var, value)| (var.clone(), value.clone()))
+            .collect();
+        if let Statement::Assignment { name, expr, .. } = &node.stmt
+            && let Some(value) = self.value(name, expr, facts)
+        {
+            out.insert(name.clone(), value);
+        }
+        Some(out)
+    }
+}
+
+struct Rewriter {
+    facts: HashMap<StmtId, BTreeMap<String, Expr>>,
+    next_id: StmtId,
+    replaced: usize,
+    arithmetic: Arithmetic,
+}
+
+impl Rewriter {
+    fn expr(&mut self, expr: &mut Expr, id: StmtId) {
+        if let Some(facts) = self.facts.get(&id) {
+            let count = substitute(expr, facts);
+            if count > 0 {
+                self.replaced += count;
+                optimize_expr(expr, self.arithmetic);
+            }
+        }
+    }
+
+    fn condition(&mut self, cond: &mut Condition, id: StmtId) {
+        match cond {
+            Condition::Odd { expr } => self.expr(expr, id),
+            Condition::Compare { left, right, .. } => {
+                self.expr(left, id);
+                self.expr(right, id);
+            }
+        }
+    }
+
+    /// Visits statements in the same pre-order as the CFG builder numbers them.
+    fn statement(&mut self, stmt: &mut Statement) {
+        let id = self.next_id;
+        self.next_id += 1;
+        match stmt {
+            Statement::Assignment { expr, .. } => self.expr(expr, id),
+            Statement::Write { exprs, .. } | Statement::Call { args: exprs, .. } => {
+                for e in exprs {
+                    self.expr(e, id);
+                }
+            }
+            Statement::BeginEnd { statements } => {
+                for s in statements {
+                    self.statement(s);
+                }
+            }
+            Statement::If {
+                condition,
+                then_stmt,
+                else_stmt,
+                ..
+            } => {
+                self.condition(condition, id);
+                self.statement(then_stmt);
+                if let Some(else_stmt) = else_stmt {
+                    self.statement(else_stmt);
+                }
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                self.condition(condition, id);
+                self.statement(body);
+            }
+            Statement::Read { .. } | Statement::Empty => {}
+        }
+    }
+}
+
+fn rewrite_block(
+    block: &mut Block,
+    params: &[String],
+    outer_consts: &BTreeMap<String, Expr>,
+    cfgs: &[Cfg],
+    effects: &[Effects],
+    arithmetic: Arithmetic,
+    index: &mut usize,
+) -> usize {
+    let cfg = &cfgs[*index];
+    let own_effects = effects[*index].clone();
+    *index += 1;
+    // Local names hide outer constants
+    let mut consts: BTreeMap<String, Expr> = outer_consts
+        .iter()
+        .filter(|(name, _)| {
+            !params.contains(name)
+                && !block.vars.contains(name)
+                && !block.procedures.iter().any(|p| &p.name == *name)
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    for c in &block.consts {
+        consts.insert(c.name.clone(), Expr::Number(c.value));
+    }
+    let analysis = Propagation {
+        effects: own_effects,
+        consts: consts.clone(),
+        arithmetic,
+    };
+    let solution = dataflow::solve(&analysis, cfg);
+    let facts = dataflow::node_facts(&analysis, cfg, &solution)
+        .into_iter()
+        .filter_map(|(id, facts)| facts.map(|f| (id, f)))
+        .collect();
+    let mut rewriter = Rewriter {
+        facts,
+        next_id: 0,
+        replaced: 0,
+        arithmetic,
+    };
+    rewriter.statement(&mut block.statement);
+    let mut replaced = rewriter.replaced;
+    for proc_decl in &mut block.procedures {
+        replaced += rewrite_block(
+            &mut proc_decl.block,
+            &proc_decl.params,
+            &consts,
+            cfgs,
+            effects,
+            arithmetic,
+            index,
+        );
+    }
+    replaced
+}
+
+/// Propagates constants and copies in every procedure. Returns the number of uses replaced.
+pub fn run(program: &mut Program, arithmetic: Arithmetic) -> usize {
+    let cfgs = cfg::build_program(program);
+    let effects = modref::program_effects(&cfgs);
+    let mut index = 0;
+    rewrite_block(
+        &mut program.block,
+        &[],
+        &BTreeMap::new(),
+        &cfgs,
+        &effects,
+        arithmetic,
+        &mut index,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, sexpr};
+
+    fn propagated(source: &str) -> String {
+        let mut program = compiler::parse(source, false).unwrap();
+        run(&mut program, Arithmetic::default());
+        sexpr::to_string(&program)
+    }
+
+    #[test]
+    fn test_constants_and_copies() {
+        let text = propagated(
+            "program t; var x, y, z, w;
+begin
+  x := 5;
+  y := x * 2;
+  z := y;
+  if x > 0 then w := 1 else w := 2;
+  write(z + w)
+end.",
+        );
+        assert!(text.contains("(:= y 10"), "{}", text);
+        assert!(text.contains("(:= z 10"), "{}", text);
+        assert!(text.contains("(if (> 5 0)"), "{}", text);
+        // w differs between the branches, so nothing is known after the join
+        assert!(text.contains("(write (+ 10 w)"), "{}", text);
+    }
+
+    #[test]
+    fn test_read_call_and_loops_kill_facts() {
+        let text = propagated(
+            "program t; var x, y, i;
+procedure p; begin x := 3 end;
+begin
+  x := 1;
+  call p;
+  write(x);
+  y := 2;
+  read(y);
+  write(y);
+  i := 0;
+  while i < 3 do i := i + 1;
+  write(i)
+end.",
+        );
+        assert!(text.contains("(write x"), "{}", text);
+        assert!(text.contains("(write y"), "{}", text);
+        assert!(text.contains("(while (< i 3)"), "{}", text);
+        assert!(text.contains("(write i"), "{}", text);
+    }
+
+    #[test]
+    fn test_copy_killed_by_source_assignment() {
+        let text = propagated(
+            "program t; var a, b;
+begin
+  read(a);
+  b := a;
+  write(b);
+  a := a + 1;
+  write(b)
+end.",
+        );
+        assert!(text.contains("(write a"), "{}", text);
+        assert!(text.contains("(write b :line 7)"), "{}", text);
+    }
+}
@@ -0,0 +1,324 @@
+//! Loop unrolling.
+//!
+//! A `while` loop has a known trip count when its condition compares a
+//! counter with a constant, the counter holds a known constant when the loop
+//! is entered, and the body changes it exactly once, at its top level, by a
+//! constant step. Such a loop is replaced by that many copies of its body
+//! when they fit in the size budget; otherwise the body is repeated a few
+//! times inside the loop, with the leftover iterations peeled off in front,
+//! so the condition is tested less often. The copies run in the original
+//! order, so output order is unchanged, and constant propagation afterwards
+//! folds the counter into each copy.
+
+use super::inline::size;
+use super::modref::{self, CallEffects};
+use super::{collect_callees, collect_modified_vars, count_defs};
+use crate::ast::{Block, Condition, Expr, Program, Statement};
+use crate::cfg;
+use crate::types::Operator;
+use crate::vm::Arithmetic;
+use std::collections::{HashMap, HashSet};
+
+/// Largest number of AST nodes unrolling may add per loop by default.
+pub const DEFAULT_UNROLL_BUDGET: usize = 64;
+
+/// Copies of the body per iteration of a partially unrolled loop, at most.
+const MAX_FACTOR: usize = 4;
+
+/// Loops running more often than this are not simulated to find their trip count.
+const MAX_TRIPS: usize = 100_000;
+
+/// Variables known to hold a constant at the current point of a straight-line walk.
+type Known = HashMap<String, i64>;
+
+/// `counter` and a test of its value, if `cond` compares it with a constant.
+fn counter_test(cond: &Condition) -> Option<(&str, impl Fn(i64) -> bool)> {
+    let Condition::Compare { left, op, right } = cond else {
+        return None;
+    };
+    let (name, limit, op) = match (left, right) {
+        (Expr::Identifier(name), Expr::Number(n)) => (name, *n, *op),
+        (Expr::Number(n), Expr::Identifier(name)) => {
+            // Flip `n < i` into `i > n`
+            let op = match op {
+                Operator::LSS => Operator::GTR,
+                Operator::LEQ => Operator::GEQ,
+                Operator::GTR => Operator::LSS,
+                Operator::GEQ => Operator::LEQ,
+                other => *other,
+            };
+            (name, *n, op)
+        }
+        _ => return None,
+    };
+    let test = move |v: i64| match op {
+        Operator::EQL => v == limit,
+        Operator::NEQ => v != limit,
+        Operator::LSS => v < limit,
+        Operator::LEQ => v <= limit,
+        Operator::GTR => v > limit,
+        Operator::GEQ => v >= limit,
+        _ => false,
+    };
+    matches!(
+        op,
+        Operator::EQL
+            | Operator::NEQ
+            | Operator::LSS
+            | Operator::LEQ
+            | Operator::GTR
+            | Operator::GEQ
+    )
+    .then_some((name.as_str(), test))
+}
+
+/// The constant step of `counter` if the body's only change to it is a top-level `counter := counter ± c`.
+fn counter_step(body: &Statement, counter: &str) -> Option<i64> {
+    let statements = match body {
+        Statement::BeginEnd { statements } => statements.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+    if count_defs(body, counter) != 1 {
+        return None;
+    }
+    statements.iter().find_map(|s| match s {
+        Statement::Assignment {
+            name,
+            expr: Expr::Binary { left, op, right },
+            ..
+        } if name == counter && matches!(left.as_ref(), Expr::Identifier(v) if v == counter) => {
+            match (op, right.as_ref()) {
+                (Operator::ADD, Expr::Number(c)) => Some(*c),
+                (Operator::SUB, Expr::Number(c)) => c.checked_neg(),
+                _ => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+fn repeat(body: &Statement, times: usize) -> Vec<Statement> {
+    (0..times).map(|_| body.clone()).collect()
+}
+
+struct Unroller<'a> {
+    calls: &'a CallEffects,
+    budget: usize,
+    arithmetic: Arithmetic,
+    unrolled: usize,
+}
+
+impl Unroller<'_> {
+    /// Number of times the loop body runs, if it can be worked out. Every value
+    /// the counter takes must be a word of the VM's arithmetic, so its overflow
+    /// handling never comes into it.
+    fn trip_count(&self, condition: &Condition, body: &Statement, known: &Known) -> Option<usize> {
+        let (counter, test) = counter_test(condition)?;
+        let mut value = *known.get(counter)?;
+        let step = counter_step(body, counter)?;
+        // A procedure called in the body changes the counter too, or may
+        let mut callees = Vec::new();
+        collect_callees(body, &mut callees);
+        for callee in &callees {
+            match self.calls.get(callee) {
+                Some(effect) if !effect.writes.contains(counter) => {}
+                _ => return None,
+            }
+        }
+        let fits = |v: i64| self.arithmetic.holds(v as i128);
+        if !fits(value) || !fits(step) {
+            return None;
+        }
+        let mut trips = 0;
+        while test(value) {
+            trips += 1;
+            if trips > MAX_TRIPS {
+                return None;
+            }
+            value = value.checked_add(step).filter(|&v| fits(v))?;
+        }
+        Some(trips)
+    }
+
+    /// Forgets whatever `stmt` may change, including through the procedures it calls.
+    fn kill(&self, stmt: &Statement, known: &mut Known) {
+        let mut modified = HashSet::new();
+        collect_modified_vars(stmt, &mut modified);
+        let mut callees = Vec::new();
+        collect_callees(stmt, &mut callees);
+        for callee in &callees {
+            match self.calls.get(callee) {
+                Some(effect) => modified.extend(effect.writes.iter().cloned()),
+                None => known.clear(),
+            }
+        }
+        known.retain(|name, _| !modified.contains(name));
+    }
+
+    /// Unrolls loops in `stmt`, innermost first; `known` holds the constants before it and after it on return.
+    fn statement(&mut self, stmt: &mut Statement, known: &mut Known) {
+        match stmt {
+            Statement::Assignment { name, expr, .. } => match expr {
+                Expr::Number(n) => {
+                    known.insert(name.clone(), *n);
+                }
+                _ => {
+                    known.remove(name);
+                }
+            },
+            Statement::BeginEnd { statements } => {
+                for s in statements {
+                    self.statement(s, known);
+                }
+            }
+            Statement::If {
+                then_stmt,
+                else_stmt,
+                ..
+            } => {
+                self.statement(then_stmt, &mut known.clone());
+                if let Some(s) = else_stmt {
+                    self.statement(s, &mut known.clone());
+                }
+                self.kill(stmt, known);
+            }
+            Statement::While {
+                condition, body, ..
+            } => {
+                let mut inside = known.clone();
+                self.kill(body, &mut inside);
+                self.statement(body, &mut inside);
+                if let Some(trips) = self.trip_count(condition, body, known) {
+                    self.unroll(stmt, trips);
+                }
+                self.kill(stmt, known);
+            }
+            Statement::Read { .. } | Statement::Call { .. } => self.kill(stmt, known),
+            Statement::Write { .. } | Statement::Empty => {}
+        }
+    }
+
+    fn unroll(&mut self, stmt: &mut Statement, trips: usize) {
+        let Statement::While { body, .. } = stmt else {
+            return;
+        };
+        let body_size = size(body);
+        if trips.saturating_mul(body_size) <= self.budget {
+            *stmt = Statement::BeginEnd {
+                statements: repeat(body, trips),
+            };
+            self.unrolled += 1;
+            return;
+        }
+        let factor = MAX_FACTOR.min(self.budget / body_size);
+        if factor < 2 || trips < factor {
+            return;
+        }
+        // The loop then runs a multiple of `factor` times, so the condition is
+        // only needed every `factor` copies
+        let mut statements = repeat(body, trips % factor);
+        **body = Statement::BeginEnd {
+            statements: repeat(body, factor),
+        };
+        statements.push(stmt.clone());
+        *stmt = Statement::BeginEnd { statements };
+        self.unrolled += 1;
+    }
+}
+
+fn unroll_block(
+    block: &mut Block,
+    calls: &[CallEffects],
+    budget: usize,
+    arithmetic: Arithmetic,
+    index: &mut usize,
+) -> usize {
+    let mut unroller = Unroller {
+        calls: &calls[*index],
+        budget,
+        arithmetic,
+        unrolled: 0,
+    };
+    *index += 1;
+    unroller.statement(&mut block.statement, &mut Known::new());
+    let mut count = unroller.unrolled;
+    for proc_decl in &mut block.procedures {
+        count += unroll_block(&mut proc_decl.block, calls, budget, arithmetic, index);
+    }
+    count
+}
+
+/// Unrolls loops with a known trip count, adding at most `budget` AST nodes per loop.
+/// Returns the number of loops unrolled.
+pub fn run(program: &mut Program, budget: usize, arithmetic: Arithmetic) -> usize {
+    let cfgs = cfg::build_program(program);
+    let modref = modref::analyze(&cfgs);
+    let calls: Vec<CallEffects> = (0..cfgs.len())
+        .map(|i| modref.call_effects(&cfgs, i))
+        .collect();
+    unroll_block(&mut program.block, &calls, budget, arithmetic, &mut 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler, sexpr};
+
+    fn unrolled(source: &str, budget: usize) -> (String, usize) {
+        let mut program = compiler::parse(source, false).unwrap();
+        let count = run(&mut program, budget, Arithmetic::default());
+        (sexpr::to_string(&program), count)
+    }
+
+    #[test]
+    fn test_full_and_partial_unrolling() {
+        let source = "program t; var i, j, s;
+begin
+  s := 0;
+  i := 0;
+  while i < 3 do
+  begin
+    if odd i then write(i) else s := s + i;
+    i := i + 1
+  end;
+  j := 10;
+  while 0 < j do
+  begin
+    write(j);
+    j := j - 1
+  end
+end.";
+        let (text, count) = unrolled(source, DEFAULT_UNROLL_BUDGET);
+        assert_eq!(count, 2, "{}", text);
+        // Three copies of the first body, no loop left
+        assert_eq!(text.matches("(if (odd i)").count(), 3, "{}", text);
+        // Ten iterations are too many: two are peeled and the rest run four at a time
+        assert_eq!(text.matches("(while").count(), 1, "{}", text);
+        assert_eq!(text.matches("(write j").count(), 6, "{}", text);
+
+        let (_, count) = unrolled(source, 0);
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn test_unknown_trip_counts() {
+        let cases = [
+            // counter changed twice
+            "program t; var i; begin i := 0; while i < 3 do begin i := i + 1; i := i + 1 end end.",
+            // counter changed inside an if
+            "program t; var i; begin i := 0; while i < 3 do if i = 1 then i := 5 else i := i + 1 end.",
+            // counter read from input before the loop
+            "program t; var i; begin i := 0; read(i); while i < 3 do i := i + 1 end.",
+            // counter changed by a call
+            "program t; var i; procedure p; begin i := 2 end; begin i := 0; call p; while i < 3 do i := i + 1 end.",
+            // counter also changed by a call in the body
+            "program t; var i; procedure p; begin i := i + 5 end; begin i := 0; while i < 3 do begin write(i); call p; i := i + 1 end end.",
+            // never terminates
+            "program t; var i; begin i := 0; while i < 3 do i := i - 1 end.",
+        ];
+        for source in cases {
+            let (text, count) = unrolled(source, 1000);
+            assert_eq!(count, 0, "{}", text);
+        }
+    }
+}
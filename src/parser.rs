@@ -81,9 +81,12 @@ impl<'a> Parser<'a> {
     }
 
     fn program(&mut self) -> ParseResult<Program> {
+        let line = self.lexer.token_line;
+        let name;
         if self.lexer.current_token == TokenType::Program {
             self.next();
-            if let TokenType::Identifier(_) = self.lexer.current_token {
+            if let TokenType::Identifier(id) = self.lexer.current_token.clone() {
+                name = id;
                 self.next();
             } else {
                 self.error("Expected program name")?;
@@ -96,7 +99,7 @@ impl<'a> Parser<'a> {
         }
 
         let block = self.block()?;
-        Ok(Program { block })
+        Ok(Program { name, block, line })
     }
 
     fn block(&mut self) -> ParseResult<Block> {
@@ -248,6 +251,7 @@ impl<'a> Parser<'a> {
     }
 
     fn proc_decl(&mut self) -> ParseResult<ProcedureDecl> {
+        let line = self.lexer.token_line;
         self.next(); // consume 'procedure'
         let name = if let TokenType::Identifier(name) = self.lexer.current_token.clone() {
             self.next();
@@ -286,6 +290,7 @@ impl<'a> Parser<'a> {
             name,
             params,
             block,
+            line,
         })
     }
 
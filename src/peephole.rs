@@ -0,0 +1,314 @@
+//! Bytecode-level cleanups run after code generation.
+//!
+//! The AST optimizer cannot see artefacts of code generation itself, such as
+//! jumps to jumps or the frame bookkeeping of calls. This pass rewrites the
+//! instruction list directly:
+//!
+//! - `LOD l a; STO l a` is dropped (unless the `STO` is a jump target)
+//! - `LIT 0; OPR ADD` and `INT 0 0` are dropped
+//! - `JMP`/`JPC` to a `JMP` are retargeted to its destination, and a `JMP` to
+//!   the very next instruction is dropped
+//! - instructions that cannot be reached from address 0 are dropped
+//!
+//! `CAL` keeps targeting procedure entries, so the procedure table in the
+//! debug info stays meaningful. All addresses in the code and in the debug
+//! info are rewritten after instructions are removed.
+
+use crate::debug_info::{DebugInfo, LineEntry};
+use crate::types::{Instruction, OpCode, Operator};
+use std::collections::HashSet;
+
+fn is_ret(instr: &Instruction) -> bool {
+    instr.f == OpCode::OPR && instr.a == Operator::RET as i64
+}
+
+fn jump_targets(code: &[Instruction]) -> HashSet<usize> {
+    code.iter()
+        .filter(|i| matches!(i.f, OpCode::JMP | OpCode::JPC | OpCode::CAL))
+        .map(|i| i.a as usize)
+        .collect()
+}
+
+/// Points every `JMP`/`JPC` at the end of its jump chain. Returns true if anything changed.
+fn thread_jumps(code: &mut [Instruction]) -> bool {
+    let mut changed = false;
+    for i in 0..code.len() {
+        if !matches!(code[i].f, OpCode::JMP | OpCode::JPC) {
+            continue;
+        }
+        let mut target = code[i].a as usize;
+        // Bounded so that a `JMP` to itself cannot loop forever
+        for _ in 0..code.len() {
+            match code.get(target) {
+                Some(next) if next.f == OpCode::JMP && next.a as usize != target => {
+                    target = next.a as usize;
+                }
+                _ => break,
+            }
+        }
+        if target != code[i].a as usize {
+            code[i].a = target as i64;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Marks instructions that do nothing, given the current jump targets.
+fn mark_redundant(code: &[Instruction], keep: &mut [bool]) {
+    let targets = jump_targets(code);
+    let mut i = 0;
+    while i < code.len() {
+        let instr = code[i];
+        let next = code.get(i + 1);
+        match (instr.f, next) {
+            (OpCode::LOD, Some(n))
+                if n.f == OpCode::STO
+                    && n.l == instr.l
+                    && n.a == instr.a
+                    && !targets.contains(&(i + 1)) =>
+            {
+                keep[i] = false;
+                keep[i + 1] = false;
+                i += 2;
+                continue;
+            }
+            (OpCode::LIT, Some(n))
+                if instr.a == 0
+                    && n.f == OpCode::OPR
+                    && n.a == Operator::ADD as i64
+                    && !targets.contains(&(i + 1)) =>
+            {
+                keep[i] = false;
+                keep[i + 1] = false;
+                i += 2;
+                continue;
+            }
+            (OpCode::INT, _) if instr.a == 0 => keep[i] = false,
+            (OpCode::JMP, _) if instr.a as usize == i + 1 => keep[i] = false,
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+/// Clears `keep` for every instruction control flow cannot reach from address 0.
+fn mark_unreachable(code: &[Instruction], keep: &mut [bool]) {
+    let mut reached = vec![false; code.len()];
+    let mut work = vec![0];
+    while let Some(addr) = work.pop() {
+        if addr >= code.len() || reached[addr] {
+            continue;
+        }
+        reached[addr] = true;
+        let instr = code[addr];
+        match instr.f {
+            OpCode::JMP => work.push(instr.a as usize),
+            OpCode::JPC | OpCode::CAL => {
+                work.push(instr.a as usize);
+                work.push(addr + 1);
+            }
+            OpCode::OPR if is_ret(&instr) => {}
+            _ => work.push(addr + 1),
+        }
+    }
+    for (k, r) in keep.iter_mut().zip(reached) {
+        *k &= r;
+    }
+}
+
+/// Drops the instructions not in `keep` and rewrites every address that referred to them.
+fn compact(code: &[Instruction], keep: &[bool], debug: &mut DebugInfo) -> Vec<Instruction> {
+    // forward[a]: new address of the first kept instruction at or after `a`
+    let mut forward = vec![0; code.len() + 1];
+    let mut next_addr = keep.iter().filter(|&&k| k).count();
+    forward[code.len()] = next_addr;
+    for addr in (0..code.len()).rev() {
+        if keep[addr] {
+            next_addr -= 1;
+        }
+        forward[addr] = next_addr;
+    }
+    let remap = |a: usize| forward[a.min(code.len())];
+
+    let new_code: Vec<Instruction> = code
+        .iter()
+        .zip(keep)
+        .filter(|(_, k)| **k)
+        .map(|(instr, _)| {
+            let mut instr = *instr;
+            if matches!(instr.f, OpCode::JMP | OpCode::JPC | OpCode::CAL) {
+                instr.a = remap(instr.a as usize) as i64;
+            }
+            instr
+        })
+        .collect();
+
+    // Procedures whose body was removed were never called; drop them and renumber parents
+    let mut new_index = Vec::with_capacity(debug.procedures.len());
+    let mut next_index = 0;
+    for p in &debug.procedures {
+        if p.body < code.len() && keep[p.body] {
+            new_index.push(Some(next_index));
+            next_index += 1;
+        } else {
+            new_index.push(None);
+        }
+    }
+    let old_procedures = std::mem::take(&mut debug.procedures);
+    for (p, idx) in old_procedures.into_iter().zip(&new_index) {
+        if idx.is_none() {
+            continue;
+        }
+        let mut p = p;
+        p.parent = p.parent.and_then(|parent| new_index[parent]);
+        p.entry = remap(p.entry);
+        p.body = remap(p.body);
+        // The exit may itself be unreachable (e.g. after an endless loop); use the last kept instruction
+        p.exit = if keep.get(p.exit) == Some(&true) {
+            remap(p.exit)
+        } else {
+            remap(p.exit).saturating_sub(1).max(p.body)
+        };
+        debug.procedures.push(p);
+    }
+
+    let mut lines: Vec<LineEntry> = Vec::new();
+    for entry in &debug.lines {
+        let addr = remap(entry.addr);
+        if addr >= new_code.len() {
+            continue;
+        }
+        if lines.last().is_some_and(|last| last.addr == addr) {
+            lines.pop();
+        }
+        if lines.last().is_none_or(|last| last.line != entry.line) {
+            lines.push(LineEntry {
+                addr,
+                line: entry.line,
+            });
+        }
+    }
+    debug.lines = lines;
+
+    new_code
+}
+
+/// Runs all peephole rewrites until none applies, updating `debug` to match the new code.
+pub fn optimize(code: Vec<Instruction>, debug: &mut DebugInfo) -> Vec<Instruction> {
+    let mut code = code;
+    loop {
+        let threaded = thread_jumps(&mut code);
+        let mut keep = vec![true; code.len()];
+        mark_redundant(&code, &mut keep);
+        mark_unreachable(&code, &mut keep);
+        if keep.iter().all(|&k| k) {
+            if !threaded {
+                return code;
+            }
+            continue;
+        }
+        code = compact(&code, &keep, debug);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{self, CompileOptions};
+    use crate::vm::{VM, VMState};
+
+    fn run(code: Vec<Instruction>, input: &[i64]) -> Vec<String> {
+        let mut vm = VM::new(code);
+        vm.input = input.iter().copied().collect();
+        while vm.state == VMState::Running {
+            vm.step();
+        }
+        assert_eq!(vm.state, VMState::Halted);
+        vm.output
+    }
+
+    #[test]
+    fn test_patterns() {
+        use OpCode::*;
+        let code = vec![
+            Instruction::new(JMP, 0, 1),
+            Instruction::new(INT, 0, 4),
+            Instruction::new(LOD, 0, 3),
+            Instruction::new(STO, 0, 3),
+            Instruction::new(LIT, 0, 5),
+            Instruction::new(LIT, 0, 0),
+            Instruction::new(OPR, 0, Operator::ADD as i64),
+            Instruction::new(INT, 0, 0),
+            Instruction::new(JMP, 0, 10),
+            Instruction::new(LIT, 0, 99), // unreachable
+            Instruction::new(JMP, 0, 11),
+            Instruction::new(OPR, 0, Operator::WRT as i64),
+            Instruction::new(OPR, 0, Operator::RET as i64),
+        ];
+        let mut debug = DebugInfo::default();
+        let optimized = optimize(code.clone(), &mut debug);
+        assert_eq!(
+            optimized,
+            vec![
+                Instruction::new(INT, 0, 4),
+                Instruction::new(LIT, 0, 5),
+                Instruction::new(OPR, 0, Operator::WRT as i64),
+                Instruction::new(OPR, 0, Operator::RET as i64),
+            ]
+        );
+        assert_eq!(run(optimized, &[]), run(code, &[]));
+    }
+
+    #[test]
+    fn test_store_that_is_a_jump_target_is_kept() {
+        use OpCode::*;
+        // while-style loop whose body ends by jumping onto the STO
+        let code = vec![
+            Instruction::new(INT, 0, 4),
+            Instruction::new(LIT, 0, 7),
+            Instruction::new(JMP, 0, 4),
+            Instruction::new(LOD, 0, 3),
+            Instruction::new(STO, 0, 3),
+            Instruction::new(LOD, 0, 3),
+            Instruction::new(OPR, 0, Operator::WRT as i64),
+            Instruction::new(OPR, 0, Operator::RET as i64),
+        ];
+        let mut debug = DebugInfo::default();
+        let optimized = optimize(code.clone(), &mut debug);
+        assert!(optimized.contains(&Instruction::new(STO, 0, 3)));
+        assert_eq!(run(optimized, &[]), vec!["7".to_string()]);
+    }
+
+    #[test]
+    fn test_programs_behave_the_same() {
+        let cases: [(&str, &[i64]); 4] = [
+            ("testcase/call.txt", &[3, 4]),
+            ("testcase/recursion.txt", &[5]),
+            ("testcase/scope.txt", &[4]),
+            ("samples/bytecode_opt.pl0", &[]),
+        ];
+        for (path, input) in cases {
+            let source = std::fs::read_to_string(path).unwrap();
+            let c = compiler::compile(&source, &CompileOptions::default()).unwrap();
+            let mut debug = c.debug_info.clone();
+            let optimized = optimize(c.code.clone(), &mut debug);
+            assert!(optimized.len() <= c.code.len(), "{}", path);
+            assert_eq!(
+                run(optimized.clone(), input),
+                run(c.code, input),
+                "{}",
+                path
+            );
+
+            // Procedure table must still describe the new code
+            for p in &debug.procedures {
+                assert!(p.entry <= p.body && p.body <= p.exit && p.exit < optimized.len());
+                assert_eq!(optimized[p.body].f, OpCode::INT, "{} {}", path, p.name);
+            }
+            for w in debug.lines.windows(2) {
+                assert!(w[0].addr < w[1].addr);
+            }
+        }
+    }
+}
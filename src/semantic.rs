@@ -47,8 +47,8 @@ impl<'a> SemanticAnalyzer<'a> {
         }
 
         // Declare variables
-        let mut var_offset = 3; // SL, DL, RA
-        for var_name in &block.vars {
+        // Slots 0..3 hold SL, DL, RA
+        for (var_offset, var_name) in (3..).zip(block.vars.iter()) {
             if let Err(e) = self.symbol_table.define(Symbol {
                 name: var_name.clone(),
                 kind: SymbolType::Variable {
@@ -58,7 +58,6 @@ impl<'a> SemanticAnalyzer<'a> {
             }) {
                 self.errors.push(e);
             }
-            var_offset += 1;
         }
 
         // Declare procedures
@@ -0,0 +1,575 @@
+//! S-expression form of the AST, meant for humans and for tools that find
+//! JSON too noisy.
+//!
+//! ```text
+//! (program demo :line 1
+//!   (block
+//!     (const (n 10))
+//!     (var x y)
+//!     (procedure p (a) :line 4
+//!       (block (write (* a 2) :line 5)))
+//!     (begin
+//!       (read x :line 7)
+//!       (if (< x n) (call p x :line 8) :line 8))))
+//! ```
+//!
+//! Expressions use prefix operators (`(+ a 1)`, unary `(- a)`); numbers and
+//! identifiers are bare atoms. `:line N` pairs are optional everywhere.
+
+use crate::ast::{Block, Condition, ConstDecl, Expr, ProcedureDecl, Program, Statement};
+use crate::lexer;
+use crate::types::Operator;
+
+fn op_symbol(op: &Operator) -> String {
+    match op {
+        Operator::ADD => "+".to_string(),
+        Operator::SUB | Operator::NEG => "-".to_string(),
+        Operator::MUL => "*".to_string(),
+        Operator::DIV => "/".to_string(),
+        Operator::EQL => "=".to_string(),
+        Operator::NEQ => "#".to_string(),
+        Operator::LSS => "<".to_string(),
+        Operator::LEQ => "<=".to_string(),
+        Operator::GTR => ">".to_string(),
+        Operator::GEQ => ">=".to_string(),
+        Operator::ODD => "odd".to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+fn expr_to_string(expr: &Expr) -> String {
+    match expr {
+        Expr::Number(n) => n.to_string(),
+        Expr::Identifier(name) => name.clone(),
+        Expr::Binary { left, op, right } => format!(
+            "({} {} {})",
+            op_symbol(op),
+            expr_to_string(left),
+            expr_to_string(right)
+        ),
+        Expr::Unary { op, expr } => format!("({} {})", op_symbol(op), expr_to_string(expr)),
+    }
+}
+
+fn condition_to_string(cond: &Condition) -> String {
+    match cond {
+        Condition::Odd { expr } => format!("(odd {})", expr_to_string(expr)),
+        Condition::Compare { left, op, right } => format!(
+            "({} {} {})",
+            op_symbol(op),
+            expr_to_string(left),
+            expr_to_string(right)
+        ),
+    }
+}
+
+fn write_statement(out: &mut String, stmt: &Statement, indent: usize) {
+    let pad = "  ".repeat(indent);
+    match stmt {
+        Statement::Assignment { name, expr, line } => out.push_str(&format!(
+            "{}(:= {} {} :line {})",
+            pad,
+            name,
+            expr_to_string(expr),
+            line
+        )),
+        Statement::Call { name, args, line } => {
+            out.push_str(&format!("{}(call {}", pad, name));
+            for arg in args {
+                out.push_str(&format!(" {}", expr_to_string(arg)));
+            }
+            out.push_str(&format!(" :line {})", line));
+        }
+        Statement::BeginEnd { statements } => {
+            out.push_str(&format!("{}(begin", pad));
+            for s in statements {
+                out.push('\n');
+                write_statement(out, s, indent + 1);
+            }
+            out.push(')');
+        }
+        Statement::If {
+            condition,
+            then_stmt,
+            else_stmt,
+            line,
+        } => {
+            out.push_str(&format!("{}(if {}\n", pad, condition_to_string(condition)));
+            write_statement(out, then_stmt, indent + 1);
+            if let Some(else_stmt) = else_stmt {
+                out.push('\n');
+                write_statement(out, else_stmt, indent + 1);
+            }
+            out.push_str(&format!(" :line {})", line));
+        }
+        Statement::While {
+            condition,
+            body,
+            line,
+        } => {
+            out.push_str(&format!(
+                "{}(while {}\n",
+                pad,
+                condition_to_string(condition)
+            ));
+            write_statement(out, body, indent + 1);
+            out.push_str(&format!(" :line {})", line));
+        }
+        Statement::Read { names, line } => {
+            out.push_str(&format!("{}(read", pad));
+            for name in names {
+                out.push_str(&format!(" {}", name));
+            }
+            out.push_str(&format!(" :line {})", line));
+        }
+        Statement::Write { exprs, line } => {
+            out.push_str(&format!("{}(write", pad));
+            for e in exprs {
+                out.push_str(&format!(" {}", expr_to_string(e)));
+            }
+            out.push_str(&format!(" :line {})", line));
+        }
+        Statement::Empty => out.push_str(&format!("{}(empty)", pad)),
+    }
+}
+
+fn write_block(out: &mut String, block: &Block, indent: usize) {
+    let pad = "  ".repeat(indent);
+    out.push_str(&format!("{}(block", pad));
+    if !block.consts.is_empty() {
+        out.push_str(&format!("\n{}  (const", pad));
+        for c in &block.consts {
+            out.push_str(&format!(" ({} {})", c.name, c.value));
+        }
+        out.push(')');
+    }
+    if !block.vars.is_empty() {
+        out.push_str(&format!("\n{}  (var {})", pad, block.vars.join(" ")));
+    }
+    for proc_decl in &block.procedures {
+        out.push_str(&format!(
+            "\n{}  (procedure {} ({}) :line {}\n",
+            pad,
+            proc_decl.name,
+            proc_decl.params.join(" "),
+            proc_decl.line
+        ));
+        write_block(out, &proc_decl.block, indent + 2);
+        out.push(')');
+    }
+    out.push('\n');
+    write_statement(out, &block.statement, indent + 1);
+    out.push(')');
+}
+
+/// Renders a program as an indented S-expression.
+pub fn to_string(program: &Program) -> String {
+    let mut out = format!("(program {} :line {}\n", program.name, program.line);
+    write_block(&mut out, &program.block, 1);
+    out.push_str(")\n");
+    out
+}
+
+#[derive(Debug, Clone)]
+enum Sx {
+    Atom(String, usize),
+    List(Vec<Sx>, usize),
+}
+
+impl Sx {
+    fn line(&self) -> usize {
+        match self {
+            Sx::Atom(_, line) | Sx::List(_, line) => *line,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Sx::Atom(a, _) => format!("'{}'", a),
+            Sx::List(..) => "a list".to_string(),
+        }
+    }
+}
+
+fn error(sx: &Sx, message: &str) -> String {
+    format!("line {}: {}, found {}", sx.line(), message, sx.describe())
+}
+
+fn read_all(text: &str) -> Result<Vec<Sx>, String> {
+    // Each open list is kept with the line it started on
+    let mut stack: Vec<(Vec<Sx>, usize)> = vec![(Vec::new(), 1)];
+    let mut atom = String::new();
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+
+    let flush = |atom: &mut String, stack: &mut Vec<(Vec<Sx>, usize)>, line: usize| {
+        if !atom.is_empty() {
+            let top = stack.last_mut().expect("root list is never popped");
+            top.0.push(Sx::Atom(std::mem::take(atom), line));
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => {
+                flush(&mut atom, &mut stack, line);
+                stack.push((Vec::new(), line));
+            }
+            ')' => {
+                flush(&mut atom, &mut stack, line);
+                if stack.len() == 1 {
+                    return Err(format!("line {}: unbalanced ')'", line));
+                }
+                let (items, start) = stack.pop().unwrap();
+                stack.last_mut().unwrap().0.push(Sx::List(items, start));
+            }
+            ';' => {
+                flush(&mut atom, &mut stack, line);
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                        break;
+                    }
+                }
+            }
+            c if c.is_whitespace() => {
+                flush(&mut atom, &mut stack, line);
+                if c == '\n' {
+                    line += 1;
+                }
+            }
+            c => atom.push(c),
+        }
+    }
+    flush(&mut atom, &mut stack, line);
+
+    if stack.len() > 1 {
+        let (_, start) = stack.last().unwrap();
+        return Err(format!("line {}: unclosed '('", start));
+    }
+    Ok(stack.pop().unwrap().0)
+}
+
+/// Splits `:line N` pairs off a list, returning the remaining items and the line.
+fn take_line(items: &[Sx]) -> Result<(Vec<&Sx>, usize), String> {
+    let mut rest = Vec::new();
+    let mut line = 0;
+    let mut iter = items.iter();
+    while let Some(item) = iter.next() {
+        match item {
+            Sx::Atom(a, _) if a == ":line" => {
+                let value = iter
+                    .next()
+                    .ok_or_else(|| format!("line {}: ':line' needs a value", item.line()))?;
+                line = match value {
+                    Sx::Atom(n, _) => n
+                        .parse()
+                        .map_err(|_| error(value, "expected a line number"))?,
+                    _ => return Err(error(value, "expected a line number")),
+                };
+            }
+            _ => rest.push(item),
+        }
+    }
+    Ok((rest, line))
+}
+
+fn list<'a>(sx: &'a Sx, what: &str) -> Result<(&'a str, &'a [Sx]), String> {
+    match sx {
+        Sx::List(items, _) => match items.first() {
+            Some(Sx::Atom(head, _)) => Ok((head.as_str(), &items[1..])),
+            _ => Err(error(sx, &format!("expected {}", what))),
+        },
+        _ => Err(error(sx, &format!("expected {}", what))),
+    }
+}
+
+fn ident(sx: &Sx) -> Result<String, String> {
+    match sx {
+        Sx::Atom(a, _) if lexer::is_identifier(a) => Ok(a.clone()),
+        _ => Err(error(sx, "expected an identifier")),
+    }
+}
+
+fn number(sx: &Sx) -> Result<i64, String> {
+    match sx {
+        Sx::Atom(a, _) => a.parse().map_err(|_| error(sx, "expected a number")),
+        _ => Err(error(sx, "expected a number")),
+    }
+}
+
+fn arity(sx: &Sx, args: &[&Sx], n: usize) -> Result<(), String> {
+    if args.len() == n {
+        Ok(())
+    } else {
+        Err(format!(
+            "line {}: expected {} operand(s), found {}",
+            sx.line(),
+            n,
+            args.len()
+        ))
+    }
+}
+
+fn parse_expr(sx: &Sx) -> Result<Expr, String> {
+    if let Sx::Atom(a, _) = sx {
+        return match a.parse::<i64>() {
+            Ok(n) => Ok(Expr::Number(n)),
+            Err(_) => Ok(Expr::Identifier(ident(sx)?)),
+        };
+    }
+    let (head, args) = list(sx, "an expression")?;
+    let args: Vec<&Sx> = args.iter().collect();
+    let op = match head {
+        "+" => Operator::ADD,
+        "-" if args.len() == 1 => {
+            return Ok(Expr::Unary {
+                op: Operator::NEG,
+                expr: Box::new(parse_expr(args[0])?),
+            });
+        }
+        "-" => Operator::SUB,
+        "*" => Operator::MUL,
+        "/" => Operator::DIV,
+        _ => return Err(error(sx, "expected an arithmetic operator")),
+    };
+    arity(sx, &args, 2)?;
+    Ok(Expr::Binary {
+        left: Box::new(parse_expr(args[0])?),
+        op,
+        right: Box::new(parse_expr(args[1])?),
+    })
+}
+
+fn parse_condition(sx: &Sx) -> Result<Condition, String> {
+    let (head, args) = list(sx, "a condition")?;
+    let args: Vec<&Sx> = args.iter().collect();
+    let op = match head {
+        "odd" => {
+            arity(sx, &args, 1)?;
+            return Ok(Condition::Odd {
+                expr: parse_expr(args[0])?,
+            });
+        }
+        "=" => Operator::EQL,
+        "#" => Operator::NEQ,
+        "<" => Operator::LSS,
+        "<=" => Operator::LEQ,
+        ">" => Operator::GTR,
+        ">=" => Operator::GEQ,
+        _ => return Err(error(sx, "expected a comparison")),
+    };
+    arity(sx, &args, 2)?;
+    Ok(Condition::Compare {
+        left: parse_expr(args[0])?,
+        op,
+        right: parse_expr(args[1])?,
+    })
+}
+
+fn parse_statement(sx: &Sx) -> Result<Statement, String> {
+    let (head, items) = list(sx, "a statement")?;
+    let (args, line) = take_line(items)?;
+    match head {
+        ":=" => {
+            arity(sx, &args, 2)?;
+            Ok(Statement::Assignment {
+                name: ident(args[0])?,
+                expr: parse_expr(args[1])?,
+                line,
+            })
+        }
+        "call" => {
+            let (name, rest) = args
+                .split_first()
+                .ok_or_else(|| error(sx, "expected a procedure name"))?;
+            Ok(Statement::Call {
+                name: ident(name)?,
+                args: rest
+                    .iter()
+                    .map(|a| parse_expr(a))
+                    .collect::<Result<_, _>>()?,
+                line,
+            })
+        }
+        "begin" => Ok(Statement::BeginEnd {
+            statements: args
+                .iter()
+                .map(|s| parse_statement(s))
+                .collect::<Result<_, _>>()?,
+        }),
+        "if" => {
+            if args.len() != 2 && args.len() != 3 {
+                return Err(error(sx, "expected (if COND THEN [ELSE])"));
+            }
+            Ok(Statement::If {
+                condition: parse_condition(args[0])?,
+                then_stmt: Box::new(parse_statement(args[1])?),
+                else_stmt: match args.get(2) {
+                    Some(s) => Some(Box::new(parse_statement(s)?)),
+                    None => None,
+                },
+                line,
+            })
+        }
+        "while" => {
+            arity(sx, &args, 2)?;
+            Ok(Statement::While {
+                condition: parse_condition(args[0])?,
+                body: Box::new(parse_statement(args[1])?),
+                line,
+            })
+        }
+        "read" => Ok(Statement::Read {
+            names: args.iter().map(|a| ident(a)).collect::<Result<_, _>>()?,
+            line,
+        }),
+        "write" => Ok(Statement::Write {
+            exprs: args
+                .iter()
+                .map(|a| parse_expr(a))
+                .collect::<Result<_, _>>()?,
+            line,
+        }),
+        "empty" => Ok(Statement::Empty),
+        _ => Err(error(sx, "expected a statement")),
+    }
+}
+
+fn parse_block(sx: &Sx) -> Result<Block, String> {
+    let (head, items) = list(sx, "(block ...)")?;
+    if head != "block" {
+        return Err(error(sx, "expected (block ...)"));
+    }
+    let mut block = Block {
+        consts: Vec::new(),
+        vars: Vec::new(),
+        procedures: Vec::new(),
+        statement: Statement::Empty,
+        scope_id: None,
+    };
+    let Some((statement, decls)) = items.split_last() else {
+        return Err(error(sx, "block needs a statement"));
+    };
+    for decl in decls {
+        let (head, items) = list(decl, "a declaration")?;
+        match head {
+            "const" => {
+                for c in items {
+                    let pair = match c {
+                        Sx::List(pair, _) if pair.len() == 2 => pair,
+                        _ => return Err(error(c, "expected (NAME VALUE)")),
+                    };
+                    block.consts.push(ConstDecl {
+                        name: ident(&pair[0])?,
+                        value: number(&pair[1])?,
+                    });
+                }
+            }
+            "var" => {
+                for v in items {
+                    block.vars.push(ident(v)?);
+                }
+            }
+            "procedure" => {
+                let (args, line) = take_line(items)?;
+                if args.len() != 3 {
+                    return Err(error(decl, "expected (procedure NAME (PARAMS) BLOCK)"));
+                }
+                let params = match args[1] {
+                    Sx::List(params, _) => params.iter().map(ident).collect::<Result<_, _>>()?,
+                    other => return Err(error(other, "expected a parameter list")),
+                };
+                block.procedures.push(ProcedureDecl {
+                    name: ident(args[0])?,
+                    params,
+                    block: parse_block(args[2])?,
+                    line,
+                });
+            }
+            _ => return Err(error(decl, "expected const, var or procedure")),
+        }
+    }
+    block.statement = parse_statement(statement)?;
+    Ok(block)
+}
+
+/// Parses the output of [`to_string`] (or anything written by hand in the same shape).
+pub fn from_str(text: &str) -> Result<Program, String> {
+    let forms = read_all(text)?;
+    let sx = match forms.as_slice() {
+        [sx] => sx,
+        [] => return Err("empty input".to_string()),
+        [_, extra, ..] => return Err(error(extra, "expected a single (program ...) form")),
+    };
+    let (head, items) = list(sx, "(program ...)")?;
+    if head != "program" {
+        return Err(error(sx, "expected (program ...)"));
+    }
+    let (args, line) = take_line(items)?;
+    if args.len() != 2 {
+        return Err(error(sx, "expected (program NAME BLOCK)"));
+    }
+    Ok(Program {
+        name: ident(args[0])?,
+        block: parse_block(args[1])?,
+        line,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+
+    #[test]
+    fn test_round_trip() {
+        let source = "program t;
+const n = 3;
+var x, y;
+procedure p(a);
+begin
+  if odd a then write(-a) else write(a / 2 * (n - 1))
+end;
+begin
+  read(x);
+  while x # 0 do begin call p(x); x := x - 1 end;
+  if x <= y then ;
+  y := 0
+end.";
+        let program = compiler::parse(source, false).unwrap();
+        let text = to_string(&program);
+        let parsed = from_str(&text).unwrap();
+        assert_eq!(to_string(&parsed), text);
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", program));
+    }
+
+    #[test]
+    fn test_round_trip_underscored_names() {
+        let source = "program t;
+var my_x;
+procedure set_x_2;
+begin my_x := 2 end;
+begin call set_x_2; write(my_x) end.";
+        let program = compiler::parse(source, false).unwrap();
+        let parsed = from_str(&to_string(&program)).unwrap();
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", program));
+        assert!(from_str("(program _t (block (empty)))").is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(
+            from_str("(program t (block (empty))")
+                .unwrap_err()
+                .contains("unclosed")
+        );
+        assert!(
+            from_str("(program t (block (:= x)))")
+                .unwrap_err()
+                .contains("operand")
+        );
+        let err = from_str("(program t\n (block (bogus)))").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+    }
+}
@@ -0,0 +1,359 @@
+//! Static checks on P-code before it runs.
+//!
+//! Code loaded from a file may have been written by hand or damaged, and the
+//! VM only notices a bad instruction when it gets there. The verifier walks
+//! every procedure reachable from address 0 and checks that:
+//!
+//! - jump and call targets are inside the code
+//! - every `OPR` has a known operator code
+//! - static levels never reach past the outermost frame
+//! - the stack depth at each instruction is the same on every path there, and
+//!   never drops below the current frame
+//! - frame-relative addresses stay inside the frame built so far
+//!
+//! Depths are counted from the base of the current frame: a call leaves the
+//! caller's depth unchanged, and a procedure starts at depth 0.
+
+use crate::types::{Instruction, OpCode, Operator};
+use std::collections::HashMap;
+
+/// Stack effect of `OPR op`: cells it needs on the stack, and the change in depth.
+fn opr_effect(op: Operator) -> (i64, i64) {
+    match op {
+        Operator::RET | Operator::WRL => (0, 0),
+        Operator::NEG | Operator::ODD => (1, 0),
+        Operator::RED => (0, 1),
+        Operator::WRT => (1, -1),
+        Operator::ADD
+        | Operator::SUB
+        | Operator::MUL
+        | Operator::DIV
+        | Operator::EQL
+        | Operator::NEQ
+        | Operator::LSS
+        | Operator::GEQ
+        | Operator::GTR
+        | Operator::LEQ => (2, -1),
+    }
+}
+
+struct Verifier<'a> {
+    code: &'a [Instruction],
+    errors: Vec<String>,
+    /// Stack depth first seen at each visited instruction.
+    depth: HashMap<usize, i64>,
+    /// Static nesting of each procedure entry found so far; the main program is 0.
+    nesting: HashMap<usize, usize>,
+    /// Procedure entries still to walk.
+    pending: Vec<usize>,
+}
+
+impl Verifier<'_> {
+    fn error(&mut self, pc: usize, msg: String) {
+        self.errors.push(format!("pc {}: {}", pc, msg));
+    }
+
+    /// `a` as an address inside the code, or `None` after reporting it.
+    fn target(&mut self, pc: usize, a: i64) -> Option<usize> {
+        match usize::try_from(a) {
+            Ok(target) if target < self.code.len() => Some(target),
+            _ => {
+                let msg = format!("target {} is outside the code (0..{})", a, self.code.len());
+                self.error(pc, msg);
+                None
+            }
+        }
+    }
+
+    fn check_level(&mut self, pc: usize, l: usize, nesting: usize) -> bool {
+        if l > nesting {
+            let msg = format!("level {} is deeper than the nesting {} here", l, nesting);
+            self.error(pc, msg);
+        }
+        l <= nesting
+    }
+
+    /// Checks a load or store of `a` at level `l`, with `depth` cells in the frame.
+    fn check_address(&mut self, pc: usize, l: usize, a: i64, depth: i64, nesting: usize) {
+        if !self.check_level(pc, l, nesting) {
+            return;
+        }
+        // Other frames' sizes and the caller's arguments below the frame are
+        // not tracked, so only the current frame's locals can be bounded
+        if l == 0 && a >= depth {
+            let msg = format!("address {} is beyond the {} cells of the frame", a, depth);
+            self.error(pc, msg);
+        }
+    }
+
+    /// Walks the procedure starting at `entry` with `nesting` enclosing procedures.
+    fn procedure(&mut self, entry: usize, nesting: usize) {
+        let mut work = vec![(entry, 0)];
+        while let Some((pc, depth)) = work.pop() {
+            if let Some(&seen) = self.depth.get(&pc) {
+                if seen != depth {
+                    let msg = format!(
+                        "stack depth is {} on one path here and {} on another",
+                        seen, depth
+                    );
+                    self.error(pc, msg);
+                }
+                continue;
+            }
+            self.depth.insert(pc, depth);
+
+            let ir = self.code[pc];
+            let (needs, change) = match ir.f {
+                OpCode::LIT => (0, 1),
+                OpCode::LOD => {
+                    self.check_address(pc, ir.l, ir.a, depth, nesting);
+                    (0, 1)
+                }
+                OpCode::STO => {
+                    self.check_address(pc, ir.l, ir.a, depth - 1, nesting);
+                    (1, -1)
+                }
+                OpCode::RED => {
+                    self.check_address(pc, ir.l, ir.a, depth, nesting);
+                    (0, 0)
+                }
+                OpCode::WRT | OpCode::JPC => (1, -1),
+                OpCode::INT => match ir.a.checked_neg() {
+                    Some(neg) => (neg.max(0), ir.a),
+                    None => {
+                        self.error(pc, "stack depth out of range".to_string());
+                        continue;
+                    }
+                },
+                OpCode::JMP | OpCode::CAL => (0, 0),
+                OpCode::OPR => match Operator::from_i64(ir.a) {
+                    Some(op) => opr_effect(op),
+                    None => {
+                        self.error(pc, format!("unknown operator code {}", ir.a));
+                        continue;
+                    }
+                },
+            };
+            if depth < needs {
+                let msg = format!(
+                    "needs {} cells on the stack but the frame has {}",
+                    needs, depth
+                );
+                self.error(pc, msg);
+                continue;
+            }
+            let Some(next_depth) = depth.checked_add(change) else {
+                self.error(pc, "stack depth out of range".to_string());
+                continue;
+            };
+
+            match ir.f {
+                OpCode::JMP => {
+                    if let Some(target) = self.target(pc, ir.a) {
+                        work.push((target, next_depth));
+                    }
+                    continue;
+                }
+                OpCode::JPC => {
+                    if let Some(target) = self.target(pc, ir.a) {
+                        work.push((target, next_depth));
+                    }
+                }
+                OpCode::CAL => {
+                    if let Some(target) = self.target(pc, ir.a)
+                        && self.check_level(pc, ir.l, nesting)
+                    {
+                        self.call(pc, target, nesting - ir.l + 1);
+                    }
+                }
+                OpCode::OPR if ir.a == Operator::RET as i64 => continue,
+                _ => {}
+            }
+            if pc + 1 >= self.code.len() {
+                self.error(pc, "execution runs past the end of the code".to_string());
+                continue;
+            }
+            work.push((pc + 1, next_depth));
+        }
+    }
+
+    /// Records a call to `target`, a procedure nested `nesting` deep.
+    fn call(&mut self, pc: usize, target: usize, nesting: usize) {
+        match self.nesting.get(&target) {
+            Some(&known) if known != nesting => {
+                let msg = format!(
+                    "procedure at {} would be nested {} deep here but {} deep elsewhere",
+                    target, nesting, known
+                );
+                self.error(pc, msg);
+            }
+            Some(_) => {}
+            None => {
+                self.nesting.insert(target, nesting);
+                self.pending.push(target);
+            }
+        }
+    }
+}
+
+/// Checks `code` before it runs. Returns every problem found, each prefixed
+/// with the address of the offending instruction.
+pub fn verify(code: &[Instruction]) -> Result<(), Vec<String>> {
+    if code.is_empty() {
+        return Err(vec!["the program has no instructions".to_string()]);
+    }
+    let mut verifier = Verifier {
+        code,
+        errors: Vec::new(),
+        depth: HashMap::new(),
+        nesting: HashMap::from([(0, 0)]),
+        pending: vec![0],
+    };
+    while let Some(entry) = verifier.pending.pop() {
+        let nesting = verifier.nesting[&entry];
+        verifier.procedure(entry, nesting);
+    }
+    if verifier.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(verifier.errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{self, CompileOptions};
+    use crate::optimizer::{OptimizeOptions, Pass};
+    use std::fs;
+
+    fn ins(f: OpCode, l: usize, a: i64) -> Instruction {
+        Instruction::new(f, l, a)
+    }
+
+    fn opr(op: Operator) -> Instruction {
+        ins(OpCode::OPR, 0, op as i64)
+    }
+
+    #[test]
+    fn test_compiled_programs_verify() {
+        let dirs = ["testcase", "testcase/generated", "samples"];
+        let paths = dirs
+            .iter()
+            .flat_map(|dir| fs::read_dir(dir).unwrap())
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file());
+        for path in paths {
+            let source = fs::read_to_string(&path).unwrap();
+            for level in [0, 3] {
+                let options = CompileOptions {
+                    optimize: level > 0,
+                    optimizer: OptimizeOptions {
+                        passes: Pass::level(level),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                // Some test cases are meant not to compile
+                let Ok(compilation) = compiler::compile(&source, &options) else {
+                    continue;
+                };
+                assert_eq!(verify(&compilation.code), Ok(()), "{}", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn test_bad_code_is_rejected() {
+        let cases = [
+            (
+                vec![ins(OpCode::JMP, 0, 7)],
+                "pc 0: target 7 is outside the code (0..1)",
+            ),
+            (
+                vec![
+                    ins(OpCode::INT, 0, 3),
+                    ins(OpCode::OPR, 0, 7),
+                    opr(Operator::RET),
+                ],
+                "pc 1: unknown operator code 7",
+            ),
+            (
+                vec![
+                    ins(OpCode::INT, 0, 4),
+                    ins(OpCode::LOD, 1, 3),
+                    opr(Operator::RET),
+                ],
+                "pc 1: level 1 is deeper than the nesting 0 here",
+            ),
+            (
+                vec![
+                    ins(OpCode::INT, 0, 4),
+                    ins(OpCode::LOD, 0, 4),
+                    opr(Operator::RET),
+                ],
+                "pc 1: address 4 is beyond the 4 cells of the frame",
+            ),
+            (
+                vec![
+                    ins(OpCode::INT, 0, 3),
+                    opr(Operator::WRT),
+                    ins(OpCode::LIT, 0, 1),
+                ],
+                "pc 2: execution runs past the end of the code",
+            ),
+            (
+                vec![
+                    ins(OpCode::LIT, 0, 1),
+                    opr(Operator::ADD),
+                    opr(Operator::RET),
+                ],
+                "pc 1: needs 2 cells on the stack but the frame has 1",
+            ),
+            // The loop pushes one more cell every time round
+            (
+                vec![
+                    ins(OpCode::INT, 0, 3),
+                    ins(OpCode::LIT, 0, 1),
+                    ins(OpCode::LIT, 0, 1),
+                    ins(OpCode::JPC, 0, 1),
+                    opr(Operator::RET),
+                ],
+                "pc 1: stack depth is 3 on one path here and 4 on another",
+            ),
+            // A procedure called from two different nesting levels
+            (
+                vec![
+                    ins(OpCode::INT, 0, 3),
+                    ins(OpCode::CAL, 0, 3),
+                    opr(Operator::RET),
+                    ins(OpCode::INT, 0, 3),
+                    ins(OpCode::CAL, 0, 3),
+                    opr(Operator::RET),
+                ],
+                "pc 4: procedure at 3 would be nested 2 deep here but 1 deep elsewhere",
+            ),
+            (
+                vec![
+                    ins(OpCode::INT, 0, 3),
+                    ins(OpCode::INT, 0, i64::MAX),
+                    opr(Operator::RET),
+                ],
+                "pc 1: stack depth out of range",
+            ),
+            (
+                vec![
+                    ins(OpCode::INT, 0, 3),
+                    ins(OpCode::INT, 0, i64::MIN),
+                    opr(Operator::RET),
+                ],
+                "pc 1: stack depth out of range",
+            ),
+        ];
+        for (code, expected) in cases {
+            let errors = verify(&code).unwrap_err();
+            assert_eq!(errors[0], expected, "{:?}", errors);
+        }
+        assert!(verify(&[]).is_err());
+    }
+}
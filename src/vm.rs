@@ -1,5 +1,11 @@
+use crate::debug_info::DebugInfo;
 use crate::types::{Instruction, OpCode, Operator};
-use std::io::{self, Write};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::fmt;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum VMState {
@@ -7,44 +13,459 @@ pub enum VMState {
     Halted,
     WaitingForInput,
     Error(String),
+    /// Stopped by one of the VM's [`Limits`] before the program finished.
+    LimitExceeded(Limit),
 }
 
-pub struct VM {
+/// A resource limit a program can run into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Time,
+    Output,
+    Depth,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Steps => "instruction limit exceeded",
+            Limit::Time => "time limit exceeded",
+            Limit::Output => "output limit exceeded",
+            Limit::Depth => "call depth limit exceeded",
+        })
+    }
+}
+
+/// Resources a program may use in total; `None` means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Limits {
+    /// Instructions executed.
+    pub steps: Option<usize>,
+    /// Wall-clock time since the first instruction.
+    pub time: Option<Duration>,
+    /// Bytes written, not counting line breaks between values.
+    pub output_bytes: Option<usize>,
+    /// Procedure calls active at once.
+    pub depth: Option<usize>,
+}
+
+/// Instructions between two looks at the clock when there is a time limit.
+const CLOCK_INTERVAL: usize = 1024;
+
+/// What the VM does when an arithmetic result does not fit in a machine word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Overflow {
+    /// Keep the low bits, as two's complement hardware does.
+    Wrap,
+    /// Stop with a runtime error.
+    #[default]
+    Trap,
+    /// Clamp to the smallest or largest word.
+    Saturate,
+}
+
+impl Overflow {
+    pub const ALL: [Overflow; 3] = [Overflow::Wrap, Overflow::Trap, Overflow::Saturate];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Overflow::Wrap => "wrap",
+            Overflow::Trap => "trap",
+            Overflow::Saturate => "saturate",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Overflow> {
+        Overflow::ALL.into_iter().find(|o| o.name() == name)
+    }
+}
+
+/// Word size and overflow behaviour of the VM's integer arithmetic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Arithmetic {
+    pub overflow: Overflow,
+    /// One of [`Arithmetic::WORD_SIZES`].
+    pub word_bits: u32,
+}
+
+impl Default for Arithmetic {
+    fn default() -> Self {
+        Self {
+            overflow: Overflow::Trap,
+            word_bits: 64,
+        }
+    }
+}
+
+impl Arithmetic {
+    pub const WORD_SIZES: [u32; 3] = [16, 32, 64];
+
+    pub fn min(self) -> i64 {
+        i64::MIN >> (64 - self.word_bits)
+    }
+
+    pub fn max(self) -> i64 {
+        i64::MAX >> (64 - self.word_bits)
+    }
+
+    /// Whether `value` is a machine word as it is, with no overflow handling.
+    pub fn holds(self, value: i128) -> bool {
+        (self.min() as i128..=self.max() as i128).contains(&value)
+    }
+
+    /// `value` as a machine word, or `None` if it does not fit and the mode is [`Overflow::Trap`].
+    pub fn fit(self, value: i128) -> Option<i64> {
+        if self.holds(value) {
+            return Some(value as i64);
+        }
+        match self.overflow {
+            Overflow::Wrap => {
+                // Truncate to 64 bits, then sign-extend from the word's top bit
+                let shift = 64 - self.word_bits;
+                Some(((value as i64) << shift) >> shift)
+            }
+            Overflow::Trap => None,
+            Overflow::Saturate => Some(if value < 0 { self.min() } else { self.max() }),
+        }
+    }
+}
+
+/// Where `read` gets its values from.
+pub trait Input {
+    /// The next value; `Ok(None)` if there is none yet, so the VM waits for
+    /// input, or an error that stops the program.
+    fn read(&mut self) -> Result<Option<i64>, String>;
+}
+
+/// Where `write` sends its values.
+pub trait Output {
+    /// Writes one value, or `"\n"` for a line break.
+    fn write(&mut self, text: String) -> Result<(), String>;
+}
+
+/// A FIFO queue of values; when it is empty, the VM waits until more are pushed.
+impl Input for VecDeque<i64> {
+    fn read(&mut self) -> Result<Option<i64>, String> {
+        Ok(self.pop_front())
+    }
+}
+
+/// Collects everything written, in order.
+impl Output for Vec<String> {
+    fn write(&mut self, text: String) -> Result<(), String> {
+        self.push(text);
+        Ok(())
+    }
+}
+
+/// Whitespace-separated integers read from a file, a string or stdin as they
+/// are needed. Running out of them or reading something else is an error.
+pub struct ReaderInput<R> {
+    reader: R,
+    buffered: VecDeque<i64>,
+    /// Prompt for and re-read invalid lines instead of failing on them.
+    interactive: bool,
+}
+
+impl<R: BufRead> ReaderInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffered: VecDeque::new(),
+            interactive: false,
+        }
+    }
+
+    /// Prompts on stdout before reading a line, and asks again after an invalid one.
+    pub fn interactive(reader: R) -> Self {
+        Self {
+            interactive: true,
+            ..Self::new(reader)
+        }
+    }
+}
+
+impl<R: BufRead> Input for ReaderInput<R> {
+    fn read(&mut self) -> Result<Option<i64>, String> {
+        while self.buffered.is_empty() {
+            if self.interactive {
+                print!("Input: ");
+                io::stdout().flush().ok();
+            }
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) => {
+                    if self.interactive {
+                        println!();
+                    }
+                    return Err("Unexpected end of input".to_string());
+                }
+                Ok(_) => {}
+                Err(e) => return Err(format!("Failed to read input: {}", e)),
+            }
+            let values: Result<Vec<i64>, _> = line.split_whitespace().map(str::parse).collect();
+            match values {
+                Ok(values) => self.buffered.extend(values),
+                Err(_) if self.interactive => println!("Invalid input"),
+                Err(_) => return Err(format!("Invalid input '{}'", line.trim())),
+            }
+        }
+        Ok(self.buffered.pop_front())
+    }
+}
+
+/// Writes each value on its own line to a file, stdout or any other writer.
+pub struct WriterOutput<W>(pub W);
+
+impl<W: Write> Output for WriterOutput<W> {
+    fn write(&mut self, text: String) -> Result<(), String> {
+        writeln!(self.0, "{}", text).map_err(|e| format!("Failed to write output: {}", e))
+    }
+}
+
+/// Values sent from another thread, such as a GUI's event loop.
+pub struct ChannelInput(pub Receiver<i64>);
+
+impl Input for ChannelInput {
+    fn read(&mut self) -> Result<Option<i64>, String> {
+        match self.0.try_recv() {
+            Ok(value) => Ok(Some(value)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err("Input closed".to_string()),
+        }
+    }
+}
+
+/// Sends everything written to another thread.
+pub struct ChannelOutput(pub Sender<String>);
+
+impl Output for ChannelOutput {
+    fn write(&mut self, text: String) -> Result<(), String> {
+        self.0.send(text).map_err(|_| "Output closed".to_string())
+    }
+}
+
+/// Cells the stack starts with; it grows on demand up to the VM's stack limit.
+const INITIAL_STACK: usize = 1000;
+
+/// Default largest number of stack cells a program may use.
+pub const DEFAULT_STACK_LIMIT: usize = 1 << 20;
+
+pub struct VM<I = VecDeque<i64>, O = Vec<String>> {
     pub code: Vec<Instruction>, // CODE: Stores P-code
     pub stack: Vec<i64>,        // STACK: Dynamic data space
     pub p: usize,               // P: Program address register (PC)
     pub b: usize,               // B: Base address register (BP)
     pub t: usize,               // T: Top of stack register (SP)
     pub i: Instruction,         // I: Instruction register
-    pub output: Vec<String>,
-    pub input_queue: Vec<i64>,
+    pub input: I,
+    pub output: O,
     pub state: VMState,
     pub instruction_count: usize,
+    pub debug_info: Option<DebugInfo>, // Used to attach source locations to errors
+    pub arithmetic: Arithmetic,
+    pub stack_limit: usize,
+    pub limits: Limits,
+    /// Bytes written so far.
+    pub output_bytes: usize,
+    /// Procedure calls currently active.
+    pub depth: usize,
+    started: Option<Instant>,
 }
 
 impl VM {
     pub fn new(code: Vec<Instruction>) -> Self {
         Self {
             code,
-            stack: vec![0; 1000], // Initial stack size
+            stack: vec![0; INITIAL_STACK],
             p: 0,
             b: 0,
             t: 0,
             i: Instruction::new(OpCode::LIT, 0, 0), // Initial dummy instruction
+            input: VecDeque::new(),
             output: Vec::new(),
-            input_queue: Vec::new(),
             state: VMState::Running,
             instruction_count: 0,
+            debug_info: None,
+            arithmetic: Arithmetic::default(),
+            stack_limit: DEFAULT_STACK_LIMIT,
+            limits: Limits::default(),
+            output_bytes: 0,
+            depth: 0,
+            started: None,
+        }
+    }
+}
+
+impl<I: Input, O: Output> VM<I, O> {
+    /// The same machine reading from `input` and writing to `output`.
+    pub fn with_io<I2: Input, O2: Output>(self, input: I2, output: O2) -> VM<I2, O2> {
+        VM {
+            code: self.code,
+            stack: self.stack,
+            p: self.p,
+            b: self.b,
+            t: self.t,
+            i: self.i,
+            input,
+            output,
+            state: self.state,
+            instruction_count: self.instruction_count,
+            debug_info: self.debug_info,
+            arithmetic: self.arithmetic,
+            stack_limit: self.stack_limit,
+            limits: self.limits,
+            output_bytes: self.output_bytes,
+            depth: self.depth,
+            started: self.started,
+        }
+    }
+
+    pub fn with_debug_info(mut self, debug_info: Option<DebugInfo>) -> Self {
+        self.debug_info = debug_info;
+        self
+    }
+
+    pub fn with_arithmetic(mut self, arithmetic: Arithmetic) -> Self {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    /// Lets the stack grow to at most `limit` cells.
+    pub fn with_stack_limit(mut self, limit: usize) -> Self {
+        self.stack_limit = limit;
+        self.stack.truncate(limit);
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Source location of the instruction at `addr`, if debug info is available.
+    pub fn location(&self, addr: usize) -> Option<String> {
+        self.debug_info.as_ref().and_then(|d| d.describe(addr))
+    }
+
+    /// Stops the machine with an error raised by the instruction at `addr`.
+    fn fail(&mut self, addr: usize, msg: &str) {
+        let msg = match self.location(addr) {
+            Some(loc) => format!("{} at pc {} ({})", msg, addr, loc),
+            None => format!("{} at pc {}", msg, addr),
+        };
+        self.state = VMState::Error(msg);
+    }
+
+    /// `value` as a machine word, or an error if it overflows and the mode traps.
+    fn fit(&self, value: i128) -> Result<i64, String> {
+        self.arithmetic.fit(value).ok_or_else(|| {
+            format!(
+                "Integer overflow ({} does not fit in {} bits)",
+                value, self.arithmetic.word_bits
+            )
+        })
+    }
+
+    /// Grows the stack to at least `len` cells.
+    fn reserve(&mut self, len: usize) -> Result<(), String> {
+        if len <= self.stack.len() {
+            return Ok(());
+        }
+        if len > self.stack_limit {
+            return Err(format!(
+                "Stack overflow (limit is {} cells)",
+                self.stack_limit
+            ));
         }
+        let len = len.max(self.stack.len() * 2).min(self.stack_limit);
+        self.stack.resize(len, 0);
+        Ok(())
     }
 
-    fn base(&self, mut l: usize) -> usize {
+    fn push(&mut self, value: i64) -> Result<(), String> {
+        self.reserve(self.t + 1)?;
+        self.stack[self.t] = value;
+        self.t += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i64, String> {
+        if self.t == 0 {
+            return Err("Stack underflow".to_string());
+        }
+        self.t -= 1;
+        Ok(self.stack[self.t])
+    }
+
+    /// Replaces the top two stack cells with `f` of them.
+    fn binary(&mut self, f: fn(i128, i128) -> i128) -> Result<(), String> {
+        let r = self.pop()?;
+        let l = self.pop()?;
+        let value = self.fit(f(l as i128, r as i128))?;
+        self.push(value)
+    }
+
+    /// Replaces the top two stack cells with 1 if `f` holds for them, else 0.
+    fn compare(&mut self, f: fn(i64, i64) -> bool) -> Result<(), String> {
+        let r = self.pop()?;
+        let l = self.pop()?;
+        self.push(f(l, r) as i64)
+    }
+
+    /// Base of the frame `l` static links up from the current one.
+    fn base(&self, l: usize) -> Result<usize, String> {
         let mut b = self.b;
-        while l > 0 {
-            b = self.stack[b] as usize;
-            l -= 1;
+        for _ in 0..l {
+            // A static link points to the base of an enclosing, so lower, frame
+            let link = self.stack.get(b).copied().unwrap_or(-1);
+            b = usize::try_from(link)
+                .ok()
+                .filter(|&link| link < b)
+                .ok_or_else(|| format!("Invalid static link {} in frame {}", link, b))?;
+        }
+        Ok(b)
+    }
+
+    /// Stack index of variable `offset` in the frame `l` levels up.
+    fn address(&self, l: usize, offset: i64) -> Result<usize, String> {
+        let base = self.base(l)?;
+        (base as i64)
+            .checked_add(offset)
+            .and_then(|addr| usize::try_from(addr).ok())
+            .filter(|&addr| addr < self.t)
+            .ok_or_else(|| {
+                let addr = base as i128 + offset as i128;
+                format!("Invalid address {} (stack top is {})", addr, self.t)
+            })
+    }
+
+    fn write(&mut self, text: String) -> Result<(), String> {
+        self.output_bytes += text.len();
+        self.output.write(text)
+    }
+
+    /// The first of the limits the program has gone past, if any.
+    fn exceeded(&self) -> Option<Limit> {
+        let over = |limit: Option<usize>, used: usize| limit.is_some_and(|l| used > l);
+        if over(self.limits.output_bytes, self.output_bytes) {
+            return Some(Limit::Output);
+        }
+        if over(self.limits.depth, self.depth) {
+            return Some(Limit::Depth);
         }
-        b
+        if let (Some(time), Some(started)) = (self.limits.time, self.started)
+            && self.instruction_count.is_multiple_of(CLOCK_INTERVAL)
+            && started.elapsed() > time
+        {
+            return Some(Limit::Time);
+        }
+        None
+    }
+
+    fn target(a: i64) -> Result<usize, String> {
+        usize::try_from(a).map_err(|_| format!("Invalid jump target {}", a))
     }
 
     pub fn step(&mut self) {
@@ -52,234 +473,196 @@ impl VM {
             return;
         }
 
-        if self.p >= self.code.len() {
-            self.state = VMState::Error("PC out of bounds".to_string());
+        if self.limits.steps.is_some_and(|steps| self.instruction_count >= steps) {
+            self.state = VMState::LimitExceeded(Limit::Steps);
             return;
         }
+        if self.limits.time.is_some() && self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+
+        let pc = self.p;
+        let Some(&ir) = self.code.get(pc) else {
+            self.state = VMState::Error(format!("PC out of bounds ({})", pc));
+            return;
+        };
 
         // Fetch instruction into I register
-        self.i = self.code[self.p];
+        self.i = ir;
         self.p += 1;
         self.instruction_count += 1;
 
-        let ir = self.i; // Use local alias for convenience matching original code structure
+        if let Err(msg) = self.execute(ir, pc) {
+            self.fail(pc, &msg);
+        } else if let Some(limit) = self.exceeded() {
+            self.state = VMState::LimitExceeded(limit);
+        }
+    }
+
+    /// Runs at most `fuel` instructions and returns the state the VM is left
+    /// in: still `Running` if the fuel ran out first, so calling again goes on
+    /// where it stopped.
+    pub fn run_for(&mut self, fuel: usize) -> VMState {
+        for _ in 0..fuel {
+            if self.state != VMState::Running {
+                break;
+            }
+            self.step();
+        }
+        self.state.clone()
+    }
 
+    fn execute(&mut self, ir: Instruction, pc: usize) -> Result<(), String> {
         match ir.f {
             OpCode::LIT => {
-                self.stack[self.t] = ir.a;
-                self.t += 1;
+                let word = self.fit(ir.a as i128)?;
+                self.push(word)?;
             }
             OpCode::OPR => {
                 match Operator::from_i64(ir.a) {
                     Some(Operator::RET) => {
                         // RET
-                        self.t = self.b;
-                        self.p = self.stack[self.t + 2] as usize;
-                        self.b = self.stack[self.t + 1] as usize;
+                        let frame = self.b;
+                        let link = |i: usize| self.stack.get(frame + i).copied().unwrap_or(-1);
+                        let (dl, ra) = (link(1), link(2));
+                        let b = usize::try_from(dl)
+                            .ok()
+                            .filter(|&dl| dl <= frame)
+                            .ok_or_else(|| format!("Invalid dynamic link {}", dl))?;
+                        let p = usize::try_from(ra)
+                            .map_err(|_| format!("Invalid return address {}", ra))?;
+                        self.t = frame;
+                        self.p = p;
+                        self.b = b;
+                        self.depth = self.depth.saturating_sub(1);
                         if self.p == 0 {
                             self.state = VMState::Halted;
                         }
                     }
                     Some(Operator::NEG) => {
                         // NEG
-                        self.stack[self.t - 1] = -self.stack[self.t - 1];
-                    }
-                    Some(Operator::ADD) => {
-                        // ADD
-                        self.t -= 1;
-                        self.stack[self.t - 1] += self.stack[self.t];
-                    }
-                    Some(Operator::SUB) => {
-                        // SUB
-                        self.t -= 1;
-                        self.stack[self.t - 1] -= self.stack[self.t];
-                    }
-                    Some(Operator::MUL) => {
-                        // MUL
-                        self.t -= 1;
-                        self.stack[self.t - 1] *= self.stack[self.t];
+                        let value = self.pop()?;
+                        let word = self.fit(-(value as i128))?;
+                        self.push(word)?;
                     }
+                    Some(Operator::ADD) => self.binary(|l, r| l + r)?,
+                    Some(Operator::SUB) => self.binary(|l, r| l - r)?,
+                    Some(Operator::MUL) => self.binary(|l, r| l * r)?,
                     Some(Operator::DIV) => {
-                        // DIV
-                        self.t -= 1;
-                        if self.stack[self.t] == 0 {
-                            self.state = VMState::Error("Division by zero".to_string());
-                            return;
+                        if self.t > 0 && self.stack[self.t - 1] == 0 {
+                            self.t -= 1;
+                            return Err("Division by zero".to_string());
                         }
-                        self.stack[self.t - 1] /= self.stack[self.t];
+                        // i64::MIN / -1 is the one quotient that can overflow
+                        self.binary(|l, r| l / r)?;
                     }
                     Some(Operator::ODD) => {
                         // ODD
-                        self.stack[self.t - 1] %= 2;
-                    }
-                    Some(Operator::EQL) => {
-                        // EQL
-                        self.t -= 1;
-                        self.stack[self.t - 1] = if self.stack[self.t - 1] == self.stack[self.t] {
-                            1
-                        } else {
-                            0
-                        };
-                    }
-                    Some(Operator::NEQ) => {
-                        // NEQ
-                        self.t -= 1;
-                        self.stack[self.t - 1] = if self.stack[self.t - 1] != self.stack[self.t] {
-                            1
-                        } else {
-                            0
-                        };
-                    }
-                    Some(Operator::LSS) => {
-                        // LSS
-                        self.t -= 1;
-                        self.stack[self.t - 1] = if self.stack[self.t - 1] < self.stack[self.t] {
-                            1
-                        } else {
-                            0
-                        };
-                    }
-                    Some(Operator::GEQ) => {
-                        // GEQ
-                        self.t -= 1;
-                        self.stack[self.t - 1] = if self.stack[self.t - 1] >= self.stack[self.t] {
-                            1
-                        } else {
-                            0
-                        };
-                    }
-                    Some(Operator::GTR) => {
-                        // GTR
-                        self.t -= 1;
-                        self.stack[self.t - 1] = if self.stack[self.t - 1] > self.stack[self.t] {
-                            1
-                        } else {
-                            0
-                        };
-                    }
-                    Some(Operator::LEQ) => {
-                        // LEQ
-                        self.t -= 1;
-                        self.stack[self.t - 1] = if self.stack[self.t - 1] <= self.stack[self.t] {
-                            1
-                        } else {
-                            0
-                        };
+                        let value = self.pop()?;
+                        self.push(value % 2)?;
                     }
+                    Some(Operator::EQL) => self.compare(|l, r| l == r)?,
+                    Some(Operator::NEQ) => self.compare(|l, r| l != r)?,
+                    Some(Operator::LSS) => self.compare(|l, r| l < r)?,
+                    Some(Operator::GEQ) => self.compare(|l, r| l >= r)?,
+                    Some(Operator::GTR) => self.compare(|l, r| l > r)?,
+                    Some(Operator::LEQ) => self.compare(|l, r| l <= r)?,
                     Some(Operator::WRT) => {
                         // Write stack top
-                        self.t -= 1;
-                        let val = self.stack[self.t];
-                        self.output.push(val.to_string());
+                        let val = self.pop()?;
+                        self.write(val.to_string())?;
                     }
                     Some(Operator::WRL) => {
                         // Write newline
-                        self.output.push("\n".to_string());
+                        self.write("\n".to_string())?;
                     }
                     Some(Operator::RED) => {
                         // Read to stack top
-                        if let Some(val) = self.input_queue.pop() {
-                            self.stack[self.t] = val;
-                            self.t += 1;
+                        if let Some(val) = self.input.read()? {
+                            let word = self.fit(val as i128)?;
+                            self.push(word)?;
                         } else {
-                            self.p -= 1;
+                            self.p = pc;
                             self.state = VMState::WaitingForInput;
                         }
                     }
-                    None => {
-                        self.state = VMState::Error(format!("Unknown OPR {}", ir.a));
-                    }
+                    None => return Err(format!("Unknown OPR {}", ir.a)),
                 }
             }
             OpCode::LOD => {
-                let base = self.base(ir.l);
-                let addr = (base as i64 + ir.a) as usize;
-                self.stack[self.t] = self.stack[addr];
-                self.t += 1;
+                let addr = self.address(ir.l, ir.a)?;
+                self.push(self.stack[addr])?;
             }
             OpCode::STO => {
-                let base = self.base(ir.l);
-                let addr = (base as i64 + ir.a) as usize;
-                self.t -= 1;
-                self.stack[addr] = self.stack[self.t];
+                let value = self.pop()?;
+                let addr = self.address(ir.l, ir.a)?;
+                self.stack[addr] = value;
             }
             OpCode::CAL => {
-                let base = self.base(ir.l);
+                let base = self.base(ir.l)?;
+                let target = Self::target(ir.a)?;
+                self.reserve(self.t + 3)?;
                 self.stack[self.t] = base as i64; // Static Link (SL)
                 self.stack[self.t + 1] = self.b as i64; // Dynamic Link (DL)
                 self.stack[self.t + 2] = self.p as i64; // Return Address (RA)
                 self.b = self.t;
-                self.p = ir.a as usize;
+                self.p = target;
+                self.depth += 1;
             }
             OpCode::INT => {
-                self.t = (self.t as i64 + ir.a) as usize;
+                // Only a positive size can overflow, and no stack is that large
+                let t = match (self.t as i64).checked_add(ir.a) {
+                    Some(t) => usize::try_from(t).map_err(|_| "Stack underflow".to_string())?,
+                    None => usize::MAX,
+                };
+                self.reserve(t)?;
+                self.t = t;
             }
             OpCode::JMP => {
-                self.p = ir.a as usize;
+                self.p = Self::target(ir.a)?;
             }
             OpCode::JPC => {
-                self.t -= 1;
-                if self.stack[self.t] == 0 {
-                    self.p = ir.a as usize;
+                let target = Self::target(ir.a)?;
+                if self.pop()? == 0 {
+                    self.p = target;
                 }
             }
             OpCode::RED => {
-                if let Some(val) = self.input_queue.pop() {
-                    let base = self.base(ir.l);
-                    let addr = (base as i64 + ir.a) as usize;
-                    self.stack[addr] = val;
+                if let Some(val) = self.input.read()? {
+                    let word = self.fit(val as i128)?;
+                    let addr = self.address(ir.l, ir.a)?;
+                    self.stack[addr] = word;
                 } else {
                     // Push back PC to retry this instruction when input is available
-                    self.p -= 1;
+                    self.p = pc;
                     self.state = VMState::WaitingForInput;
                 }
             }
             OpCode::WRT => {
-                self.t -= 1; // Pop
-                let val = self.stack[self.t];
-                self.output.push(val.to_string());
+                let val = self.pop()?;
+                self.write(val.to_string())?;
             }
         }
+        Ok(())
     }
 
+    /// Runs the program from the start until it halts, fails, or waits for
+    /// input that is not there, then reports how it ended on stdout.
     pub fn interpret(&mut self) {
         println!("Start PL/0");
         self.p = 0;
         self.b = 0;
         self.t = 0;
         self.state = VMState::Running;
-        let mut output_index = 0;
-
-        loop {
-            match self.state {
-                VMState::Running => {
-                    self.step();
-                    while output_index < self.output.len() {
-                        println!("{}", self.output[output_index]);
-                        output_index += 1;
-                    }
-                }
-                VMState::Halted => {
-                    println!("Program finished");
-                    break;
-                }
-                VMState::Error(ref e) => {
-                    println!("Runtime Error: {}", e);
-                    break;
-                }
-                VMState::WaitingForInput => {
-                    print!("Input: ");
-                    io::stdout().flush().unwrap();
-                    let mut input = String::new();
-                    io::stdin().read_line(&mut input).unwrap();
-                    if let Ok(val) = input.trim().parse::<i64>() {
-                        self.input_queue.push(val);
-                        self.state = VMState::Running;
-                    } else {
-                        println!("Invalid input");
-                    }
-                }
-            }
+        while self.state == VMState::Running {
+            self.step();
+        }
+        io::stdout().flush().ok();
+        match &self.state {
+            VMState::Halted => println!("Program finished"),
+            VMState::Error(e) => println!("Runtime Error: {}", e),
+            VMState::LimitExceeded(limit) => println!("Stopped: {} at pc {}", limit, self.p),
+            _ => println!("Runtime Error: no input available at pc {}", self.p),
         }
     }
 }
@@ -306,4 +689,279 @@ mod tests {
 
         assert_eq!(vm.stack[vm.t - 1], 30);
     }
+
+    fn run(code: Vec<Instruction>, arithmetic: Arithmetic) -> VM {
+        let mut vm = VM::new(code).with_arithmetic(arithmetic);
+        while vm.state == VMState::Running && vm.p < vm.code.len() {
+            vm.step();
+        }
+        vm
+    }
+
+    #[test]
+    fn test_overflow_modes() {
+        // i64::MIN / -1, then 3 * 2^62
+        let div = vec![
+            Instruction::new(OpCode::LIT, 0, i64::MIN),
+            Instruction::new(OpCode::LIT, 0, -1),
+            Instruction::new(OpCode::OPR, 0, Operator::DIV as i64),
+        ];
+        let mul = vec![
+            Instruction::new(OpCode::LIT, 0, 3),
+            Instruction::new(OpCode::LIT, 0, 1 << 62),
+            Instruction::new(OpCode::OPR, 0, Operator::MUL as i64),
+        ];
+        let mode = |overflow| Arithmetic {
+            overflow,
+            word_bits: 64,
+        };
+
+        let vm = run(div.clone(), mode(Overflow::Trap));
+        match &vm.state {
+            VMState::Error(msg) => {
+                assert!(msg.starts_with("Integer overflow"), "{}", msg);
+                assert!(msg.ends_with("at pc 2"), "{}", msg);
+            }
+            other => panic!("expected overflow error, got {:?}", other),
+        }
+        assert_eq!(run(div.clone(), mode(Overflow::Wrap)).stack[0], i64::MIN);
+        assert_eq!(run(div, mode(Overflow::Saturate)).stack[0], i64::MAX);
+        assert_eq!(run(mul.clone(), mode(Overflow::Wrap)).stack[0], -(1 << 62));
+        assert_eq!(run(mul, mode(Overflow::Saturate)).stack[0], i64::MAX);
+    }
+
+    #[test]
+    fn test_word_sizes() {
+        // 30000 + 30000, then negated
+        let code = vec![
+            Instruction::new(OpCode::LIT, 0, 30000),
+            Instruction::new(OpCode::LIT, 0, 30000),
+            Instruction::new(OpCode::OPR, 0, Operator::ADD as i64),
+            Instruction::new(OpCode::OPR, 0, Operator::NEG as i64),
+        ];
+        let bits = |overflow, word_bits| Arithmetic {
+            overflow,
+            word_bits,
+        };
+        assert_eq!(run(code.clone(), bits(Overflow::Wrap, 16)).stack[0], 5536);
+        assert_eq!(run(code.clone(), bits(Overflow::Saturate, 16)).stack[0], -32767);
+        assert_eq!(run(code.clone(), bits(Overflow::Trap, 32)).stack[0], -60000);
+        assert!(matches!(
+            run(code, bits(Overflow::Trap, 16)).state,
+            VMState::Error(_)
+        ));
+
+        // Literals and input must fit as well
+        let code = vec![Instruction::new(OpCode::LIT, 0, 1 << 20)];
+        assert_eq!(run(code.clone(), bits(Overflow::Wrap, 16)).stack[0], 0);
+        assert_eq!(run(code, bits(Overflow::Saturate, 32)).stack[0], 1 << 20);
+        assert_eq!(bits(Overflow::Trap, 16).min(), -32768);
+        assert_eq!(bits(Overflow::Trap, 32).max(), i32::MAX as i64);
+    }
+
+    #[test]
+    fn test_bad_programs_fail_cleanly() {
+        let op = |op: Operator| Instruction::new(OpCode::OPR, 0, op as i64);
+        let cases = [
+            (vec![Instruction::new(OpCode::LIT, 0, 1), op(Operator::ADD)], "Stack underflow"),
+            (vec![Instruction::new(OpCode::INT, 0, -1)], "Stack underflow"),
+            (vec![Instruction::new(OpCode::JPC, 0, 0)], "Stack underflow"),
+            (
+                vec![
+                    Instruction::new(OpCode::INT, 0, 3),
+                    Instruction::new(OpCode::LOD, 0, 7),
+                ],
+                "Invalid address 7 (stack top is 3)",
+            ),
+            (
+                vec![
+                    Instruction::new(OpCode::INT, 0, 3),
+                    Instruction::new(OpCode::STO, 0, -5),
+                ],
+                "Invalid address -5 (stack top is 2)",
+            ),
+            (
+                vec![
+                    Instruction::new(OpCode::INT, 0, 3),
+                    Instruction::new(OpCode::LOD, 1, 3),
+                ],
+                "Invalid static link 0 in frame 0",
+            ),
+            (vec![Instruction::new(OpCode::JMP, 0, -2)], "Invalid jump target -2"),
+            (
+                vec![
+                    Instruction::new(OpCode::INT, 0, 3),
+                    Instruction::new(OpCode::CAL, 0, 0),
+                ],
+                "Stack overflow (limit is 64 cells)",
+            ),
+            (
+                vec![
+                    Instruction::new(OpCode::INT, 0, 3),
+                    Instruction::new(OpCode::INT, 0, i64::MAX),
+                ],
+                "Stack overflow (limit is 64 cells)",
+            ),
+            (
+                vec![
+                    Instruction::new(OpCode::INT, 0, 3),
+                    Instruction::new(OpCode::CAL, 0, 2),
+                    Instruction::new(OpCode::INT, 0, 3),
+                    Instruction::new(OpCode::LOD, 0, i64::MAX),
+                ],
+                "Invalid address 9223372036854775810 (stack top is 6)",
+            ),
+        ];
+        for (code, expected) in cases {
+            let mut vm = VM::new(code).with_stack_limit(64);
+            while vm.state == VMState::Running {
+                vm.step();
+            }
+            match &vm.state {
+                VMState::Error(msg) => assert!(msg.starts_with(expected), "{}", msg),
+                other => panic!("expected {:?}, got {:?}", expected, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_stack_grows_up_to_its_limit() {
+        // A procedure that counts a global down from 500, calling itself each time
+        let code = vec![
+            Instruction::new(OpCode::JMP, 0, 10),
+            Instruction::new(OpCode::INT, 0, 3),
+            Instruction::new(OpCode::LOD, 1, 3),
+            Instruction::new(OpCode::LIT, 0, 1),
+            Instruction::new(OpCode::OPR, 0, Operator::SUB as i64),
+            Instruction::new(OpCode::STO, 1, 3),
+            Instruction::new(OpCode::LOD, 1, 3),
+            Instruction::new(OpCode::JPC, 0, 9),
+            Instruction::new(OpCode::CAL, 1, 1),
+            Instruction::new(OpCode::OPR, 0, Operator::RET as i64),
+            Instruction::new(OpCode::INT, 0, 4),
+            Instruction::new(OpCode::LIT, 0, 500),
+            Instruction::new(OpCode::STO, 0, 3),
+            Instruction::new(OpCode::CAL, 0, 1),
+            Instruction::new(OpCode::OPR, 0, Operator::RET as i64),
+        ];
+        let mut vm = VM::new(code.clone());
+        while vm.state == VMState::Running {
+            vm.step();
+        }
+        assert_eq!(vm.state, VMState::Halted);
+        assert!(vm.stack.len() > INITIAL_STACK);
+
+        let mut vm = VM::new(code).with_stack_limit(INITIAL_STACK);
+        while vm.state == VMState::Running {
+            vm.step();
+        }
+        assert!(matches!(&vm.state, VMState::Error(msg) if msg.starts_with("Stack overflow")));
+    }
+
+    #[test]
+    fn test_io_backends() {
+        // read(a); read(b); write(a - b)
+        let code = vec![
+            Instruction::new(OpCode::INT, 0, 5),
+            Instruction::new(OpCode::RED, 0, 3),
+            Instruction::new(OpCode::RED, 0, 4),
+            Instruction::new(OpCode::LOD, 0, 3),
+            Instruction::new(OpCode::LOD, 0, 4),
+            Instruction::new(OpCode::OPR, 0, Operator::SUB as i64),
+            Instruction::new(OpCode::WRT, 0, 0),
+            Instruction::new(OpCode::OPR, 0, Operator::RET as i64),
+        ];
+        fn finish<I: Input, O: Output>(vm: &mut VM<I, O>) {
+            while vm.state == VMState::Running {
+                vm.step();
+            }
+        }
+
+        // Queued input comes out in the order it went in
+        let mut vm = VM::new(code.clone());
+        vm.input.push_back(10);
+        finish(&mut vm);
+        assert_eq!(vm.state, VMState::WaitingForInput);
+        vm.input.push_back(3);
+        vm.state = VMState::Running;
+        finish(&mut vm);
+        assert_eq!(vm.output, vec!["7"]);
+
+        let mut out = Vec::new();
+        let input = ReaderInput::new("10\n  4".as_bytes());
+        let mut vm = VM::new(code.clone()).with_io(input, WriterOutput(&mut out));
+        finish(&mut vm);
+        assert_eq!(vm.state, VMState::Halted);
+        assert_eq!(out, b"6\n");
+
+        // Batch input fails instead of waiting once it runs out
+        for (text, error) in [
+            ("10", "Unexpected end of input at pc 2"),
+            ("1 x", "Invalid input '1 x' at pc 1"),
+        ] {
+            let input = ReaderInput::new(text.as_bytes());
+            let mut vm = VM::new(code.clone()).with_io(input, Vec::new());
+            finish(&mut vm);
+            assert_eq!(vm.state, VMState::Error(error.to_string()));
+        }
+
+        let (to_vm, input) = std::sync::mpsc::channel();
+        let (output, from_vm) = std::sync::mpsc::channel();
+        let mut vm = VM::new(code).with_io(ChannelInput(input), ChannelOutput(output));
+        to_vm.send(2).unwrap();
+        finish(&mut vm);
+        assert_eq!(vm.state, VMState::WaitingForInput);
+        to_vm.send(5).unwrap();
+        vm.state = VMState::Running;
+        finish(&mut vm);
+        assert_eq!(from_vm.try_recv(), Ok("-3".to_string()));
+    }
+
+    #[test]
+    fn test_fuel_and_limits() {
+        // while true do write(7)
+        let forever = vec![
+            Instruction::new(OpCode::LIT, 0, 7),
+            Instruction::new(OpCode::WRT, 0, 0),
+            Instruction::new(OpCode::JMP, 0, 0),
+        ];
+        let mut vm = VM::new(forever.clone());
+        assert_eq!(vm.run_for(10), VMState::Running);
+        assert_eq!(vm.instruction_count, 10);
+        assert_eq!(vm.run_for(5), VMState::Running);
+        assert_eq!(vm.output.len(), 5);
+
+        let stopped = |limits: Limits| {
+            let mut vm = VM::new(forever.clone()).with_limits(limits);
+            vm.run_for(1_000_000)
+        };
+        let limits = Limits {
+            steps: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(stopped(limits), VMState::LimitExceeded(Limit::Steps));
+        let limits = Limits {
+            output_bytes: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(stopped(limits), VMState::LimitExceeded(Limit::Output));
+        let limits = Limits {
+            time: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(stopped(limits), VMState::LimitExceeded(Limit::Time));
+
+        // A procedure that calls itself forever
+        let recurse = vec![
+            Instruction::new(OpCode::INT, 0, 3),
+            Instruction::new(OpCode::CAL, 0, 0),
+        ];
+        let limits = Limits {
+            depth: Some(50),
+            ..Default::default()
+        };
+        let mut vm = VM::new(recurse).with_limits(limits);
+        assert_eq!(vm.run_for(1_000_000), VMState::LimitExceeded(Limit::Depth));
+        assert_eq!(vm.depth, 51);
+    }
 }
@@ -1,10 +1,14 @@
 use pl0::codegen::CodeGenerator;
+use pl0::compiler::{self, CompileOptions, compile};
 use pl0::lexer::Lexer;
-use pl0::optimizer::optimize_ast;
+use pl0::listing;
+use pl0::optimizer::{OptimizeOptions, Pass, optimize_ast};
 use pl0::parser::Parser;
 use pl0::semantic::SemanticAnalyzer;
+use pl0::sexpr;
 use pl0::symbol_table::SymbolTable;
-use pl0::vm::{VM, VMState};
+use pl0::types::{OpCode, Operator};
+use pl0::vm::{Arithmetic, Overflow, VM, VMState};
 use std::fs;
 use std::path::Path;
 
@@ -78,6 +82,12 @@ fn test_all_testcases() {
                 .expect("Semantic analysis failed");
 
             optimize_ast(&mut program);
+            // Inlining and dead store elimination rewrite declarations
+            let mut symbol_table = SymbolTable::new();
+            let mut semantic_analyzer = SemanticAnalyzer::new(&mut symbol_table);
+            semantic_analyzer
+                .analyze(&mut program)
+                .expect("Semantic analysis failed after optimization");
 
             let mut generator = CodeGenerator::new();
             generator.generate(&program, &mut symbol_table)
@@ -87,10 +97,7 @@ fn test_all_testcases() {
             Ok(code) => {
                 let mut vm = VM::new(code);
 
-                // VM pops from back, so we reverse the input to simulate a queue
-                let mut input = test_case.input.clone();
-                input.reverse();
-                vm.input_queue = input;
+                vm.input = test_case.input.iter().copied().collect();
 
                 let mut steps = 0;
                 while vm.state == VMState::Running && steps < 100000 {
@@ -115,3 +122,808 @@ fn test_all_testcases() {
         }
     }
 }
+
+#[test]
+fn test_runtime_error_reports_source_line() {
+    let source = "program divzero;
+var x, y;
+procedure f;
+begin
+  y := 10 / x
+end;
+begin
+  x := 0;
+  call f
+end";
+    let compilation = compile(source, &CompileOptions::default()).expect("compile failed");
+    let info = &compilation.debug_info;
+    assert_eq!(info.procedures.len(), 2);
+    assert_eq!(info.procedures[1].name, "f");
+    assert_eq!(info.procedures[1].parent, Some(0));
+    assert_eq!(compilation.code[info.procedures[1].exit].f, OpCode::OPR);
+    let main_vars: Vec<_> = info.procedures[0].vars.iter().map(|v| v.name.as_str()).collect();
+    assert_eq!(main_vars, vec!["x", "y"]);
+
+    let mut vm = VM::new(compilation.code).with_debug_info(Some(compilation.debug_info));
+    while vm.state == VMState::Running {
+        vm.step();
+    }
+    match vm.state {
+        VMState::Error(msg) => {
+            assert!(msg.starts_with("Division by zero"), "{}", msg);
+            assert!(msg.contains("line 5 in f"), "{}", msg);
+        }
+        other => panic!("expected runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_listing_annotates_code() {
+    let source = fs::read_to_string("testcase/call.txt").expect("Failed to read file");
+    for optimize in [false, true] {
+        // Keep multiply a procedure so its header shows up in both listings
+        let options = CompileOptions {
+            optimize,
+            optimizer: OptimizeOptions {
+                inline_limit: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let compilation = compile(&source, &options).expect("compile failed");
+        let text = listing::render(&source, &compilation.code, &compilation.debug_info);
+
+        // Every instruction appears exactly once
+        for addr in 0..compilation.code.len() {
+            let prefix = format!("{:>6} ", addr);
+            assert_eq!(
+                text.lines().filter(|l| l.starts_with(&prefix)).count(),
+                1,
+                "instruction {} in:\n{}",
+                addr,
+                text
+            );
+        }
+        assert!(text.contains("; procedure multiply (level 1"));
+        assert!(text.contains("; frame: SL@0 DL@1 RA@2 a@-2 b@-1"));
+        assert!(text.contains("; call multiply"));
+        assert!(text.contains("; res"));
+    }
+}
+
+#[test]
+fn test_serialized_ast_compiles_identically() {
+    for file in ["base1.txt", "call.txt", "recursion.txt", "scope.txt"] {
+        let source = fs::read_to_string(Path::new("testcase").join(file)).unwrap();
+        let options = CompileOptions::default();
+        let expected = compile(&source, &options).unwrap();
+
+        let program = compiler::parse(&source, false).unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        let from_json = compiler::compile_program(serde_json::from_str(&json).unwrap(), &options)
+            .unwrap();
+        assert_eq!(from_json.code, expected.code, "{} via json", file);
+
+        let text = sexpr::to_string(&program);
+        let from_sexpr =
+            compiler::compile_program(sexpr::from_str(&text).unwrap(), &options).unwrap();
+        assert_eq!(from_sexpr.code, expected.code, "{} via sexpr", file);
+        assert_eq!(from_sexpr.debug_info, expected.debug_info, "{} via sexpr", file);
+    }
+}
+
+/// Runs `source` compiled with and without optimizations on each input and
+/// checks that the output and the way the program ends are identical.
+fn assert_optimizer_preserves_behavior(name: &str, source: &str, inputs: &[&[i64]]) {
+    assert_optimizer_preserves_behavior_on(name, source, inputs, Arithmetic::default());
+}
+
+/// Like [`assert_optimizer_preserves_behavior`], on a VM with the given arithmetic.
+fn assert_optimizer_preserves_behavior_on(
+    name: &str,
+    source: &str,
+    inputs: &[&[i64]],
+    arithmetic: Arithmetic,
+) {
+    let run = |level: u8, input: &[i64]| {
+        let options = CompileOptions {
+            optimize: level > 0,
+            optimizer: OptimizeOptions {
+                passes: Pass::level(level),
+                arithmetic,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let compilation = compile(source, &options).expect("compile failed");
+        let mut vm = VM::new(compilation.code).with_arithmetic(arithmetic);
+        vm.input = input.iter().copied().collect();
+        let mut steps = 0;
+        while vm.state == VMState::Running && steps < 1_000_000 {
+            vm.step();
+            steps += 1;
+        }
+        // Addresses differ between the builds; compare only what went wrong
+        let end = match &vm.state {
+            VMState::Error(msg) => msg.split(" at pc").next().unwrap().to_string(),
+            other => format!("{:?}", other),
+        };
+        (vm.output, end)
+    };
+    for input in inputs {
+        let expected = run(0, input);
+        // Every level, since each one runs a different mix of passes
+        for level in 1..=3 {
+            assert_eq!(
+                run(level, input),
+                expected,
+                "{} with input {:?} at -O{} on {:?}\n{}",
+                name,
+                input,
+                level,
+                arithmetic,
+                source
+            );
+        }
+    }
+}
+
+#[test]
+fn test_licm_preserves_behavior() {
+    let cases: [(&str, &str, &[&[i64]]); 8] = [
+        (
+            "zero-trip loop",
+            "program t; var n, i, x;
+begin
+  read(n); x := 7; i := 0;
+  while i < n do begin x := n * 2; i := i + 1 end;
+  write(x)
+end.",
+            &[&[0], &[3]],
+        ),
+        (
+            "target read before assignment",
+            "program t; var n, i, x, s;
+begin
+  read(n); x := 1; i := 0; s := 0;
+  while i < n do begin s := s + x; x := n + 1; i := i + 1 end;
+  write(s)
+end.",
+            &[&[0], &[1], &[4]],
+        ),
+        (
+            "target in condition",
+            "program t; var n, x;
+begin
+  read(n); x := 0;
+  while x < 5 do x := n + 5;
+  write(x)
+end.",
+            &[&[0], &[2]],
+        ),
+        (
+            "call modifies operand",
+            "program t; var n, i, x, k;
+procedure bump; begin k := k + 1 end;
+begin
+  read(n); k := 0; i := 0;
+  while i < n do begin x := k * 2; call bump; i := i + 1 end;
+  write(x, k)
+end.",
+            &[&[0], &[3]],
+        ),
+        (
+            "trapping expression",
+            "program t; var n, d, i, x;
+begin
+  read(n, d); i := 0; x := 0;
+  while i < n do begin write(i); x := 100 / d; i := i + 1 end;
+  write(x)
+end.",
+            &[&[0, 0], &[2, 0], &[2, 5]],
+        ),
+        (
+            "chain of invariants",
+            "program t; var n, i, a, b, s;
+begin
+  read(n); i := 0; s := 0;
+  while i < n do begin a := n + 1; b := a * 3; s := s + b; i := i + 1 end;
+  write(s, a, b)
+end.",
+            &[&[0], &[4]],
+        ),
+        (
+            "propagated zero next to a trap",
+            "program t; var n, d, z, i, x;
+begin
+  read(n, d); z := 0; i := 0; x := 5;
+  while i < n do begin x := z * (100 / d); i := i + 1 end;
+  write(x)
+end.",
+            &[&[0, 0], &[2, 0], &[2, 4]],
+        ),
+        (
+            "unrolled zero next to a trap",
+            "program t; var d, i, x;
+begin
+  read(d); i := 0; x := 5;
+  while i < 3 do begin x := (i - i) * (d / i); write(x); i := i + 1 end
+end.",
+            &[&[0], &[6]],
+        ),
+    ];
+    for (name, source, inputs) in cases {
+        assert_optimizer_preserves_behavior(name, source, inputs);
+    }
+    for (file, input) in [("base1.txt", [5]), ("scope.txt", [4]), ("recursion.txt", [5])] {
+        let source = fs::read_to_string(Path::new("testcase").join(file)).unwrap();
+        assert_optimizer_preserves_behavior(file, &source, &[&input]);
+    }
+}
+
+#[test]
+fn test_propagation_preserves_behavior() {
+    let source = "program t;
+const limit = 3;
+var n, x, y, k;
+procedure setk; begin k := n end;
+begin
+  read(n);
+  x := limit;
+  y := x;
+  if y = 3 then write(1) else write(2);
+  k := 0;
+  call setk;
+  if k = 0 then write(10) else write(20);
+  read(x);
+  write(x + y);
+  while x < limit do begin x := x + 1; y := x end;
+  write(y)
+end.";
+    assert_optimizer_preserves_behavior("propagation", source, &[&[0, 0], &[5, 1], &[2, 7]]);
+
+    // The first branch is decided at compile time; the one after the call is not
+    let options = CompileOptions {
+        optimize: true,
+        ..Default::default()
+    };
+    let optimized = compile(source, &options).unwrap();
+    let plain = compile(source, &CompileOptions::default()).unwrap();
+    assert!(optimized.code.len() < plain.code.len());
+    let jpc = |code: &[pl0::types::Instruction]| code.iter().filter(|i| i.f == OpCode::JPC).count();
+    assert_eq!(jpc(&optimized.code) + 1, jpc(&plain.code));
+}
+
+#[test]
+fn test_dead_store_elimination_shrinks_frames() {
+    let source = "program t;
+var a, b, unused, g;
+procedure show;
+var tmp;
+begin
+  tmp := g;
+  write(g)
+end;
+begin
+  read(a);
+  b := a;
+  b := a * 3;
+  g := b;
+  call show;
+  write(b)
+end.";
+    assert_optimizer_preserves_behavior("dead stores", source, &[&[0], &[7]]);
+
+    let options = CompileOptions {
+        optimize: true,
+        optimizer: OptimizeOptions {
+            inline_limit: 0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let optimized = compile(source, &options).unwrap();
+    assert_eq!(optimized.stats.dead_stores, 2);
+    assert_eq!(optimized.stats.removed_slots, 2);
+    let frames: Vec<i64> = optimized
+        .code
+        .iter()
+        .filter(|i| i.f == OpCode::INT)
+        .map(|i| i.a)
+        .collect();
+    // show keeps only its three bookkeeping cells; main keeps a, b and g
+    assert_eq!(frames, vec![3, 6]);
+}
+
+#[test]
+fn test_inlining_preserves_behavior() {
+    for (file, inputs) in [
+        ("call.txt", &[&[3, 4][..], &[-2, 5]][..]),
+        ("scope.txt", &[&[0][..], &[4]]),
+        ("recursion.txt", &[&[0][..], &[5]]),
+    ] {
+        let source = fs::read_to_string(Path::new("testcase").join(file)).unwrap();
+        assert_optimizer_preserves_behavior(file, &source, inputs);
+    }
+
+    // inner reads outer's i and the global s from two levels down; after inlining
+    // into outer they are a local and a level-1 access instead
+    let source = "program t;
+var s, n;
+procedure outer(k);
+  var i, j;
+  procedure inner(d);
+    var s;
+  begin
+    s := i * d;
+    j := j + s
+  end;
+  procedure clash;
+  begin
+    s := s + j
+  end;
+begin
+  i := 0;
+  j := 0;
+  while i < k do
+  begin
+    call inner(2);
+    call clash;
+    i := i + 1
+  end;
+  write(j)
+end;
+begin
+  read(n);
+  s := 1;
+  call outer(n);
+  call outer(n + 1);
+  write(s)
+end.";
+    assert_optimizer_preserves_behavior("nested inlining", source, &[&[0], &[3]]);
+
+    let inlined = compile(
+        source,
+        &CompileOptions {
+            optimize: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(inlined.stats.inlined_calls, 2);
+    let calls = |code: &[pl0::types::Instruction]| code.iter().filter(|i| i.f == OpCode::CAL).count();
+    assert_eq!(calls(&inlined.code), 2);
+    let kept = compile(
+        source,
+        &CompileOptions {
+            optimize: true,
+            optimizer: OptimizeOptions {
+                inline_limit: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(kept.stats.inlined_calls, 0);
+    assert_eq!(calls(&kept.code), 4);
+}
+
+#[test]
+fn test_self_tail_calls_run_in_constant_stack() {
+    let source = "program t;
+var n, s;
+procedure count(k, acc);
+begin
+  if k = 0 then
+    s := acc
+  else
+  begin
+    call count(k - 1, acc + k);
+  end
+end;
+procedure countdown(k);
+begin
+  if k > 0 then call countdown(k - 1);
+  write(k)
+end;
+begin
+  read(n);
+  call count(n, 0);
+  write(s);
+  call countdown(3)
+end.";
+    for optimize in [false, true] {
+        let options = CompileOptions {
+            optimize,
+            ..Default::default()
+        };
+        let compilation = compile(source, &options).unwrap();
+        let mut vm = VM::new(compilation.code);
+        vm.input.push_back(1_000_000);
+        while vm.state == VMState::Running {
+            vm.step();
+        }
+        assert_eq!(vm.state, VMState::Halted, "optimize: {}", optimize);
+        let sum: i64 = (1..=1_000_000).sum();
+        // countdown is not a tail call: every k is written on the way back
+        assert_eq!(vm.output, vec![sum.to_string(), "0".into(), "1".into(), "2".into(), "3".into()]);
+    }
+}
+
+#[test]
+fn test_strength_reduction_preserves_behavior() {
+    let source = "program t;
+var i, n, a, b, s, k;
+procedure bump; begin s := s + 1 end;
+begin
+  read(n);
+  i := 0; s := 0; k := 10;
+  while i < n do
+  begin
+    a := i * 4;
+    if odd i then b := 3 * i else b := i * (-1);
+    s := s + a + b + i * 2;
+    call bump;
+    i := i + 1
+  end;
+  write(s, i * 4);
+  while k > 0 - n do
+  begin
+    write(k * 5 - i * 3);
+    k := k - 3
+  end
+end.";
+    assert_optimizer_preserves_behavior("strength reduction", source, &[&[0], &[1], &[9]]);
+}
+
+#[test]
+fn test_strength_reduction_adds_no_overflows() {
+    // The loop never runs, and i * 3 would overflow
+    let never = "program t;
+var i, a;
+begin
+  read(i);
+  while i < 0 do begin a := i * 3; write(a); i := i + 1 end;
+  write(i)
+end.";
+    // The last iteration's i * 3 is the largest product the loop computes
+    let last = "program t;
+var i, a;
+begin
+  read(i);
+  while i < 3074457345618258603 do begin a := i * 3; write(a); i := i + 1 end;
+  write(i)
+end.";
+    for overflow in Overflow::ALL {
+        let arithmetic = Arithmetic {
+            overflow,
+            ..Default::default()
+        };
+        let input: &[i64] = &[4611686018427387904];
+        assert_optimizer_preserves_behavior_on("zero-trip loop", never, &[input], arithmetic);
+        let input: &[i64] = &[3074457345618258601];
+        assert_optimizer_preserves_behavior_on("last iteration", last, &[input], arithmetic);
+    }
+}
+
+#[test]
+fn test_unrolling_preserves_behavior() {
+    let source = "program t;
+const n = 3;
+var i, j, s, k;
+begin
+  read(k);
+  s := 0; i := 0;
+  while i < n do
+  begin
+    j := 0;
+    while j <= i do
+    begin
+      if odd (i + j) then write(i * 10 + j) else if i = j then s := s + k;
+      j := j + 1
+    end;
+    i := i + 1
+  end;
+  write(s);
+  i := 20;
+  while i > 9 do
+  begin
+    if i = k then write(0 - i);
+    write(i);
+    i := i - 1
+  end
+end.";
+    assert_optimizer_preserves_behavior("unrolling", source, &[&[0], &[2], &[13]]);
+
+    let o3 = |unroll_budget| CompileOptions {
+        optimize: true,
+        optimizer: OptimizeOptions {
+            passes: Pass::level(3),
+            unroll_budget,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let unrolled = compile(source, &o3(pl0::optimizer::DEFAULT_UNROLL_BUDGET)).unwrap();
+    assert!(unrolled.stats.unrolled_loops >= 2);
+    let kept = compile(source, &o3(0)).unwrap();
+    assert_eq!(kept.stats.unrolled_loops, 0);
+    // Fewer loop tests and back jumps get executed
+    let steps = |code: Vec<pl0::types::Instruction>| {
+        let mut vm = VM::new(code);
+        vm.input.push_back(2);
+        let mut steps = 0;
+        while vm.state == VMState::Running {
+            vm.step();
+            steps += 1;
+        }
+        steps
+    };
+    assert!(steps(unrolled.code) < steps(kept.code));
+}
+
+#[test]
+fn test_overflowing_constants_survive_optimization() {
+    let source = "program t;
+var x, y;
+begin
+  x := 9223372036854775807 * 2;
+  y := 4611686018427387904 + 1;
+  write(y)
+end.";
+    let program = compiler::parse(source, false).unwrap();
+    let warnings = compiler::warnings(&program);
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert!(warnings[0].starts_with("line 4:"), "{}", warnings[0]);
+
+    let options = CompileOptions {
+        optimize: true,
+        optimizer: OptimizeOptions {
+            passes: Pass::level(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let optimized = compile(source, &options).unwrap();
+    // The dead store to x still has to multiply, since the multiply overflows
+    assert!(
+        optimized
+            .code
+            .iter()
+            .any(|i| i.f == OpCode::OPR && i.a == Operator::MUL as i64)
+    );
+}
+
+#[test]
+fn test_trapping_operands_are_not_multiplied_away() {
+    let direct = "program t;
+var x, z;
+begin
+  read(z);
+  x := (1 / z) * 0;
+  write(x);
+  x := 0 * (9223372036854775807 + 1);
+  write(x)
+end.";
+    let program = compiler::parse(direct, false).unwrap();
+    let warnings = compiler::warnings(&program);
+    assert!(warnings.iter().any(|w| w.starts_with("line 7:")), "{:?}", warnings);
+    assert_optimizer_preserves_behavior("trapping operand", direct, &[&[0], &[2]]);
+
+    // Folding finds the zero once the loop has set c to 0
+    let folded = "program t;
+var a, b, c;
+begin
+  read(b, c);
+  a := 0;
+  while a < 4 do
+  begin
+    write(8, 3);
+    c := ((-(0)) * (b / c)) * 15;
+    a := a + 1
+  end
+end.";
+    assert_optimizer_preserves_behavior("folded zero", folded, &[&[5, 1]]);
+
+    // Propagation, after unrolling, makes -(a) a zero next to a division by i = 0
+    let propagated = "program t;
+var a, i, c;
+begin
+  a := 0;
+  i := 2;
+  while i > 0 - 2 do
+  begin
+    c := (-(a)) * ((-(14)) / i);
+    write(c);
+    i := i - 1
+  end
+end.";
+    assert_optimizer_preserves_behavior("propagated zero", propagated, &[&[]]);
+}
+
+/// Writes small random programs for differential testing. Loops count with
+/// their own variables, which nothing else assigns, so every program ends;
+/// the procedure declares its own counters for the same reason.
+struct ProgramGenerator {
+    state: u64,
+}
+
+impl ProgramGenerator {
+    const VARS: [&'static str; 4] = ["a", "b", "c", "d"];
+    const COUNTERS: [&'static str; 2] = ["i", "j"];
+
+    fn new(seed: u64) -> Self {
+        ProgramGenerator {
+            state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+        }
+    }
+
+    /// A number below `n`, from a xorshift generator
+    fn below(&mut self, n: usize) -> usize {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state % n as u64) as usize
+    }
+
+    fn program(&mut self) -> String {
+        let body = self.statements(0, 0, true);
+        let procedure = self.statements(2, 0, false);
+        format!(
+            "program t;
+var a, b, c, d, i, j;
+procedure p;
+var i, j;
+begin
+  {}
+end;
+begin
+  read(a, b); c := 0; d := 0; i := 0; j := 0;
+  {}
+end.",
+            procedure, body
+        )
+    }
+
+    fn statements(&mut self, depth: usize, loops: usize, calls: bool) -> String {
+        let count = 1 + self.below(3);
+        (0..count)
+            .map(|_| self.statement(depth, loops, calls))
+            .collect::<Vec<_>>()
+            .join(";\n  ")
+    }
+
+    fn statement(&mut self, depth: usize, loops: usize, calls: bool) -> String {
+        let choice = if depth >= 3 { self.below(3) } else { self.below(7) };
+        match choice {
+            0 | 1 => {
+                let var = Self::VARS[self.below(Self::VARS.len())];
+                format!("{} := {}", var, self.expr(0, loops))
+            }
+            2 if calls => "call p".to_string(),
+            2 => format!("write({})", self.expr(0, loops)),
+            3 => format!(
+                "if {} then begin {} end else begin {} end",
+                self.condition(loops),
+                self.statements(depth + 1, loops, calls),
+                self.statements(depth + 1, loops, calls)
+            ),
+            4 if loops < Self::COUNTERS.len() => {
+                let counter = Self::COUNTERS[loops];
+                format!(
+                    "{c} := {}; while {c} < {} do begin {}; {c} := {c} + 1 end",
+                    self.below(2),
+                    self.below(5),
+                    self.statements(depth + 1, loops + 1, calls),
+                    c = counter
+                )
+            }
+            _ => format!("write({})", self.expr(0, loops)),
+        }
+    }
+
+    fn condition(&mut self, loops: usize) -> String {
+        if self.below(5) == 0 {
+            return format!("odd ({})", self.expr(1, loops));
+        }
+        let op = ["=", "#", "<", "<=", ">", ">="][self.below(6)];
+        format!("{} {} {}", self.expr(1, loops), op, self.expr(1, loops))
+    }
+
+    fn expr(&mut self, depth: usize, loops: usize) -> String {
+        if depth >= 3 || self.below(3) == 0 {
+            return match self.below(8) {
+                0 | 1 => "0".to_string(),
+                2 => "9223372036854775807".to_string(),
+                3 if loops > 0 => Self::COUNTERS[self.below(loops)].to_string(),
+                3 | 4 => (1 + self.below(4)).to_string(),
+                _ => Self::VARS[self.below(Self::VARS.len())].to_string(),
+            };
+        }
+        match self.below(6) {
+            0 => format!("(-({}))", self.expr(depth + 1, loops)),
+            choice => format!(
+                "({} {} {})",
+                self.expr(depth + 1, loops),
+                ["+", "-", "*", "/", "*"][choice - 1],
+                self.expr(depth + 1, loops)
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_random_programs_behave_the_same_at_every_level() {
+    for seed in 0..300 {
+        let source = ProgramGenerator::new(seed).program();
+        let name = format!("seed {}", seed);
+        assert_optimizer_preserves_behavior(&name, &source, &[&[0, 3], &[-2, 7]]);
+    }
+}
+
+#[test]
+fn test_folding_follows_the_word_size() {
+    let source = "program t;
+const big = 200;
+var x, y;
+begin
+  read(x);
+  write(x);
+  write(200 * 200 - 30000);
+  y := big * 300;
+  write(y / 2, 0 - 32768 - 1, -(0 - 2147483647 - 1));
+  write(x * 200 - 30000)
+end.";
+    for overflow in Overflow::ALL {
+        for word_bits in Arithmetic::WORD_SIZES {
+            let arithmetic = Arithmetic {
+                overflow,
+                word_bits,
+            };
+            assert_optimizer_preserves_behavior_on("folding", source, &[&[200]], arithmetic);
+        }
+    }
+}
+
+#[test]
+fn test_deep_recursion_grows_the_stack_up_to_its_limit() {
+    let source = "program r;
+var n;
+procedure down;
+var k;
+begin
+  k := n;
+  n := n - 1;
+  if n > 0 then call down;
+  write(k)
+end;
+begin
+  n := 5000;
+  call down
+end.";
+    let compilation = compile(source, &CompileOptions::default()).unwrap();
+    let run = |limit: usize| {
+        let mut vm = VM::new(compilation.code.clone())
+            .with_debug_info(Some(compilation.debug_info.clone()))
+            .with_stack_limit(limit);
+        while vm.state == VMState::Running {
+            vm.step();
+        }
+        vm
+    };
+
+    // Every frame takes four cells, far more than the initial stack holds
+    let vm = run(pl0::vm::DEFAULT_STACK_LIMIT);
+    assert_eq!(vm.state, VMState::Halted);
+    assert_eq!(vm.output.len(), 5000);
+    assert_eq!(vm.output.last().map(String::as_str), Some("5000"));
+
+    match run(10_000).state {
+        VMState::Error(msg) => {
+            assert!(msg.starts_with("Stack overflow"), "{}", msg);
+            assert!(msg.contains("in down"), "{}", msg);
+        }
+        other => panic!("expected stack overflow, got {:?}", other),
+    }
+}